@@ -0,0 +1,106 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use journald::JournalEntry;
+
+use journald_writer::bench::{self, OutputFormat};
+
+/// An entry as written by a service through the native protocol
+fn service_entry() -> JournalEntry {
+	let mut entry = JournalEntry::new();
+	for (key, value) in &[
+		("__CURSOR", "s=9d4c8a0e2f5b4e6c8f1a3b5d7e9f1a2b;i=1a2b3c;b=0123456789abcdef0123456789abcdef;m=5f5e100;t=5f1e2d3c4b5a6;x=1a2b3c4d5e6f7a8b"),
+		("__REALTIME_TIMESTAMP", "1760429700000000"),
+		("__MONOTONIC_TIMESTAMP", "100000000"),
+		("_BOOT_ID", "0123456789abcdef0123456789abcdef"),
+		("_MACHINE_ID", "fedcba9876543210fedcba9876543210"),
+		("_HOSTNAME", "host"),
+		("_TRANSPORT", "journal"),
+		("_PID", "1234"),
+		("_UID", "0"),
+		("_GID", "0"),
+		("_COMM", "sshd"),
+		("_EXE", "/usr/sbin/sshd"),
+		("_CMDLINE", "sshd: user [priv]"),
+		("_SYSTEMD_UNIT", "sshd.service"),
+		("PRIORITY", "6"),
+		("SYSLOG_FACILITY", "4"),
+		("SYSLOG_IDENTIFIER", "sshd"),
+		("MESSAGE", "Accepted publickey for user from 192.0.2.1 port 52718 ssh2"),
+	] {
+		entry.set_field(key, value);
+	}
+	entry
+}
+
+/// The lossless export format against the formatted ones, see `write_export`
+fn formats(c: &mut Criterion) {
+	let config = bench::config();
+	let entry = service_entry();
+	let mut out = Vec::with_capacity(4096);
+	for (name, format) in &[
+		("export", OutputFormat::Export),
+		("text", OutputFormat::Text),
+		("json", OutputFormat::Json),
+	] {
+		c.bench_function(name, |b| {
+			b.iter(|| {
+				out.clear();
+				bench::write_line(black_box(&entry), *format, &config, &mut out).unwrap();
+			})
+		});
+	}
+}
+
+/// Copying export entries as they are against formatting them from their `Record`
+fn passthrough(c: &mut Criterion) {
+	let config = bench::config();
+	for (name, entry) in &[("", service_entry()), ("wide ", wide_entry())] {
+		let mut out = Vec::with_capacity(64 * 1024);
+		c.bench_function(&format!("{}export passthrough", name), |b| {
+			b.iter(|| {
+				out.clear();
+				bench::write_passthrough(black_box(entry), &config, &mut out).unwrap();
+			})
+		});
+		c.bench_function(&format!("{}export formatted", name), |b| {
+			b.iter(|| {
+				out.clear();
+				bench::write_line(black_box(entry), OutputFormat::Export, &config, &mut out)
+					.unwrap();
+			})
+		});
+	}
+}
+
+/// An entry with hundreds of fields, e.g. of an application logging its whole context
+fn wide_entry() -> JournalEntry {
+	let mut entry = service_entry();
+	for i in 0..500 {
+		entry.set_field(
+			&format!("CONTEXT_FIELD_{}", i),
+			&format!("value of field {}", i),
+		);
+	}
+	entry
+}
+
+/// Streaming the fields of wide entries into the output against collecting them into a map
+fn wide_entries(c: &mut Criterion) {
+	let config = bench::config();
+	let entry = wide_entry();
+	let mut out = Vec::with_capacity(64 * 1024);
+	c.bench_function("wide json streamed", |b| {
+		b.iter(|| {
+			out.clear();
+			bench::write_line(black_box(&entry), OutputFormat::Json, &config, &mut out).unwrap();
+		})
+	});
+	c.bench_function("wide json map", |b| {
+		b.iter(|| {
+			out.clear();
+			bench::write_json_map(black_box(&entry), &config, &mut out).unwrap();
+		})
+	});
+}
+
+criterion_group!(benches, formats, passthrough, wide_entries);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+
+/// Directory and naming of the daily files of `target: archive`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveConfig {
+	pub dir: PathBuf,
+	/// Appended to the date, before `.gz`
+	#[serde(default = "default_suffix")]
+	pub suffix: String,
+	/// gzip level from 0 (none) to 9 (best)
+	#[serde(default = "default_level")]
+	pub level: u32,
+}
+
+fn default_suffix() -> String {
+	".log".to_string()
+}
+
+fn default_level() -> u32 {
+	6
+}
+
+/// Writes entries into one gzip file per UTC day of their timestamp, e.g.
+/// `2026-10-14.log.gz`, for archiving a time range in one pass.
+///
+/// The file of a day is finished once an entry of another day arrives, and when the sink is
+/// dropped. Writing a day again, e.g. after a restart, appends another gzip member, which
+/// `zcat` and `gzip -d` read as one file.
+pub(crate) struct DailyArchive {
+	config: ArchiveConfig,
+	/// Day and encoder of the open file
+	current: Option<(NaiveDate, GzEncoder<std::fs::File>)>,
+	/// Whether entries were written since the last flush
+	pending: bool,
+}
+
+impl DailyArchive {
+	pub(crate) fn new(config: ArchiveConfig) -> Result<Self> {
+		std::fs::create_dir_all(&config.dir)
+			.with_context(|| format!("Creating archive dir {}", config.dir.display()))?;
+		Ok(Self {
+			config,
+			current: None,
+			pending: false,
+		})
+	}
+
+	pub(crate) fn write(&mut self, time: &DateTime<Utc>, data: &[u8]) -> Result<()> {
+		let day = time.naive_utc().date();
+		if self.current.as_ref().map(|(current, _)| *current) != Some(day) {
+			self.finish()?;
+			self.current = Some((day, self.open(day)?));
+		}
+		if let Some((_, encoder)) = &mut self.current {
+			encoder.write_all(data).context("Writing to archive")?;
+			self.pending = true;
+		}
+
+		Ok(())
+	}
+
+	/// Make the written entries readable from the file, without finishing it.
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		if let Some((day, encoder)) = &mut self.current {
+			encoder
+				.flush()
+				.with_context(|| format!("Flushing archive of {}", day))?;
+		}
+		self.pending = false;
+
+		Ok(())
+	}
+
+	/// Whether written entries may still be buffered in the encoder.
+	pub(crate) fn has_pending(&self) -> bool {
+		self.pending
+	}
+
+	fn open(&self, day: NaiveDate) -> Result<GzEncoder<std::fs::File>> {
+		let path = self.path(day);
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&path)
+			.with_context(|| format!("Opening archive {}", path.display()))?;
+		debug!("archiving entries of {} to {}", day, path.display());
+
+		Ok(GzEncoder::new(
+			file,
+			Compression::new(self.config.level.min(9)),
+		))
+	}
+
+	/// Write the gzip trailer of the open file and close it.
+	fn finish(&mut self) -> Result<()> {
+		if let Some((day, encoder)) = self.current.take() {
+			let file = encoder
+				.finish()
+				.with_context(|| format!("Finishing archive of {}", day))?;
+			file.sync_all()
+				.with_context(|| format!("Syncing archive of {}", day))?;
+			info!("finished archive {}", self.path(day).display());
+		}
+		self.pending = false;
+
+		Ok(())
+	}
+
+	fn path(&self, day: NaiveDate) -> PathBuf {
+		self.config.dir.join(format!(
+			"{}{}.gz",
+			day.format("%Y-%m-%d"),
+			self.config.suffix
+		))
+	}
+}
+
+impl Drop for DailyArchive {
+	fn drop(&mut self) {
+		if let Err(e) = self.finish() {
+			warn!("failed to finish archive: {:#}", e);
+		}
+	}
+}
@@ -0,0 +1,52 @@
+//! The formats, for the benchmarks in `benches/`. Not a stable interface.
+
+use anyhow::{Context, Result};
+use journald::JournalEntry;
+
+pub use crate::config::Config;
+pub use crate::writer::OutputFormat;
+use crate::writer::{self, Record};
+
+/// A config with the defaults of all options.
+pub fn config() -> Config {
+	serde_yaml::from_str(
+		r#"
+cursor_file: "/tmp/journald-writer-bench/cursor"
+log_writer_config:
+  target_dir: "/tmp/journald-writer-bench"
+  prefix: "journal-"
+  suffix: ".log"
+  min_avail_of_total: 0.05
+  warn_if_avail_reached: false
+  max_file_size: 536870912
+"#,
+	)
+	.expect("bench config is valid")
+}
+
+/// Write the entry in `format`, as a files sink does.
+pub fn write_line(
+	entry: &JournalEntry,
+	format: OutputFormat,
+	config: &Config,
+	out: &mut Vec<u8>,
+) -> Result<()> {
+	let record = Record::new(entry, config)?.context("entry without timestamp")?;
+	writer::write_log_line(&record, out, format, config)
+}
+
+/// The json format serialized from the map of `record_fields`, as the structured formats did
+/// before streaming the fields, to compare against `write_line`.
+pub fn write_json_map(entry: &JournalEntry, config: &Config, out: &mut Vec<u8>) -> Result<()> {
+	let record = Record::new(entry, config)?.context("entry without timestamp")?;
+	serde_json::to_writer(&mut *out, &writer::record_fields(&record, config))?;
+	out.push(b'\n');
+
+	Ok(())
+}
+
+/// Write the entry as a files sink does with `format: export` and nothing transforming the
+/// entries, without its `Record`, to compare against `write_line`.
+pub fn write_passthrough(entry: &JournalEntry, config: &Config, out: &mut Vec<u8>) -> Result<()> {
+	writer::write_passthrough(entry, out, config)
+}
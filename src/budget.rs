@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use log::*;
+
+/// What to do once writing would exceed `max_total_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPolicy {
+	/// Stop forwarding, without advancing the cursor, until space is freed externally
+	Block,
+	/// Delete the oldest files in the target directory, never the most recent one
+	DeleteOldest,
+}
+
+impl Default for BudgetPolicy {
+	fn default() -> Self {
+		BudgetPolicy::Block
+	}
+}
+
+/// Hard limit of the bytes stored in a target directory, including its subdirectories.
+pub(crate) struct Budget {
+	root: PathBuf,
+	max: u64,
+	policy: BudgetPolicy,
+	used: u64,
+}
+
+impl Budget {
+	pub(crate) fn new(target_dir: &Path, max: u64, policy: BudgetPolicy) -> Result<Self> {
+		let root = budget_root(target_dir);
+		let used = files(&root)?.iter().map(|(_, size, _)| size).sum();
+		debug!("{} bytes used in {}", used, root.display());
+
+		Ok(Self {
+			root,
+			max,
+			policy,
+			used,
+		})
+	}
+
+	/// Account for `bytes` about to be written, making room or waiting for it first.
+	pub(crate) fn reserve(&mut self, bytes: u64) -> Result<()> {
+		if self.used + bytes > self.max {
+			match self.policy {
+				BudgetPolicy::Block => self.block(bytes)?,
+				BudgetPolicy::DeleteOldest => self.delete_oldest(bytes)?,
+			}
+		}
+
+		self.used += bytes;
+		Ok(())
+	}
+
+	/// Wait for space without the watchdog taking it for a blocked write.
+	fn block(&mut self, bytes: u64) -> Result<()> {
+		crate::watchdog::disarmed(|| self.wait_for_space(bytes))
+	}
+
+	fn wait_for_space(&mut self, bytes: u64) -> Result<()> {
+		warn!(
+			"{} exceeds max_total_bytes, blocking until space is freed",
+			self.root.display()
+		);
+		while self.used + bytes > self.max {
+			if crate::EXIT_FLAG.load(Ordering::Relaxed) {
+				bail!("Exit requested while blocked on max_total_bytes");
+			}
+			thread::sleep(Duration::from_secs(10));
+			self.used = files(&self.root)?.iter().map(|(_, size, _)| size).sum();
+		}
+		info!("{} is below max_total_bytes again", self.root.display());
+
+		Ok(())
+	}
+
+	fn delete_oldest(&mut self, bytes: u64) -> Result<()> {
+		let mut files = files(&self.root)?;
+		files.sort_by_key(|(_, _, modified)| *modified);
+		self.used = files.iter().map(|(_, size, _)| size).sum();
+
+		// the most recent file is likely still written to
+		let deletable = files.len().saturating_sub(1);
+		for (path, size, _) in files.into_iter().take(deletable) {
+			if self.used + bytes <= self.max {
+				break;
+			}
+			info!("deleting {} to stay below max_total_bytes", path.display());
+			fs::remove_file(&path).with_context(|| format!("Deleting {}", path.display()))?;
+			self.used -= size;
+		}
+
+		if self.used + bytes > self.max {
+			warn!(
+				"{} still exceeds max_total_bytes, nothing left to delete",
+				self.root.display()
+			);
+		}
+
+		Ok(())
+	}
+}
+
+/// The part of `target_dir` before the first partition placeholder.
+fn budget_root(target_dir: &Path) -> PathBuf {
+	target_dir
+		.components()
+		.take_while(|c| match c {
+			Component::Normal(name) => !name.to_string_lossy().contains('{'),
+			_ => true,
+		})
+		.collect()
+}
+
+/// All regular files below `dir`, with size and modification time.
+fn files(dir: &Path) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+	let mut files = Vec::new();
+	if !dir.exists() {
+		return Ok(files);
+	}
+
+	for entry in fs::read_dir(dir).with_context(|| format!("Reading {}", dir.display()))? {
+		let entry = entry.with_context(|| format!("Reading {}", dir.display()))?;
+		let metadata = entry
+			.metadata()
+			.with_context(|| format!("Reading metadata of {}", entry.path().display()))?;
+		if metadata.is_dir() {
+			files.extend(self::files(&entry.path())?);
+		} else if metadata.is_file() {
+			let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+			files.push((entry.path(), metadata.len(), modified));
+		}
+	}
+
+	Ok(files)
+}
@@ -0,0 +1,1077 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log_writer::LogWriterConfig;
+
+/// Syntax of the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+	Yaml,
+	Toml,
+	Json,
+}
+
+impl ConfigFormat {
+	pub fn from_name(name: &str) -> Result<Self> {
+		match name {
+			"yaml" | "yml" => Ok(ConfigFormat::Yaml),
+			"toml" => Ok(ConfigFormat::Toml),
+			"json" => Ok(ConfigFormat::Json),
+			_ => bail!(
+				"Unknown config format {}, expected yaml, toml or json",
+				name
+			),
+		}
+	}
+
+	/// Detect the format from the file extension, defaulting to YAML
+	fn of_path(path: &Path) -> Self {
+		path.extension()
+			.and_then(|extension| extension.to_str())
+			.and_then(|extension| Self::from_name(extension).ok())
+			.unwrap_or(ConfigFormat::Yaml)
+	}
+}
+
+/// Read and parse the config file at `path`, or from stdin if `path` is `-`.
+///
+/// The format is detected from the file extension unless given. Environment variables are
+/// interpolated before parsing, outside of comments, see [`interpolate_env`].
+pub fn load<P: AsRef<Path>>(path: P, format: Option<ConfigFormat>) -> Result<Config> {
+	let config_str = if path.as_ref() == Path::new("-") {
+		let mut config_str = String::new();
+		std::io::stdin()
+			.read_to_string(&mut config_str)
+			.context("Reading config from stdin")?;
+		config_str
+	} else {
+		std::fs::read_to_string(path.as_ref()).context("Reading config file")?
+	};
+	let source = if path.as_ref() == Path::new("-") {
+		None
+	} else {
+		Some(ConfigSource {
+			path: path.as_ref().to_path_buf(),
+			format,
+			content_hash: crate::reload::content_hash(&config_str),
+		})
+	};
+	let format = format.unwrap_or_else(|| ConfigFormat::of_path(path.as_ref()));
+	let comments = format != ConfigFormat::Json;
+	let config_str = interpolate_env(&config_str, comments).context("Interpolating config file")?;
+	let mut config: Config = match format {
+		ConfigFormat::Yaml => serde_yaml::from_str(&config_str).map_err(describe_parse_error),
+		ConfigFormat::Toml => toml::from_str(&config_str).map_err(describe_parse_error),
+		ConfigFormat::Json => serde_json::from_str(&config_str).map_err(describe_parse_error),
+	}
+	.context("Parsing config file")?;
+	config.source = source;
+
+	Ok(config)
+}
+
+/// The file a config was loaded from, for `watch_config`
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+	pub path: PathBuf,
+	pub format: Option<ConfigFormat>,
+	/// Of the content before interpolation, to tell whether a change altered it
+	pub content_hash: u64,
+}
+
+/// Add the closest valid name to errors about unknown fields or variants.
+///
+/// serde reports these as ``unknown field `nmae`, expected one of `name`, `path` at line 3
+/// column 1``, so the names are taken from the message.
+fn describe_parse_error<E>(error: E) -> anyhow::Error
+where
+	E: std::error::Error + Send + Sync + 'static,
+{
+	let message = error.to_string();
+	if !message.contains("unknown field") && !message.contains("unknown variant") {
+		return error.into();
+	}
+
+	let names: Vec<&str> = message.split('`').skip(1).step_by(2).collect();
+	let (unknown, expected) = match names.split_first() {
+		Some(names) => names,
+		None => return error.into(),
+	};
+	let closest = expected
+		.iter()
+		.map(|name| (edit_distance(unknown, name), name))
+		.filter(|(distance, _)| *distance <= 3)
+		.min_by_key(|(distance, _)| *distance);
+
+	match closest {
+		Some((_, name)) => anyhow::anyhow!("{}, did you mean `{}`?", message, name),
+		None => error.into(),
+	}
+}
+
+/// Levenshtein distance of two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut previous: Vec<usize> = (0..=b.len()).collect();
+	for (i, a) in a.chars().enumerate() {
+		let mut current = vec![i + 1];
+		for (j, b) in b.iter().enumerate() {
+			let substitution = previous[j] + if a == *b { 0 } else { 1 };
+			current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+		}
+		previous = current;
+	}
+
+	previous[b.len()]
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	pub cursor_file: PathBuf,
+	/// If `cursor_file` is a symlink, write the cursor to its final target, following a chain
+	/// of links, instead of replacing it
+	#[serde(default)]
+	pub follow_cursor_symlink: bool,
+	/// Start from `cursor_file` but never write it, e.g. to follow the cursor of another
+	/// instance. Reopening the reader only resumes from that instance's last write.
+	#[serde(default)]
+	pub cursor_readonly: bool,
+	/// Fail at startup if the cursor directory can't be created or written. Otherwise the
+	/// cursor is only kept in memory, so reopening the journal resumes from the last written
+	/// entry, but forwarding starts anew from `start_position` or the last persisted cursor
+	/// after a restart.
+	#[serde(default = "default_true")]
+	pub cursor_required: bool,
+	/// Set when the cursor can't be written with `cursor_required: false`, the reader is then
+	/// reopened at the last written entry rather than at `cursor_file`
+	#[serde(skip)]
+	pub cursor_in_memory: bool,
+	/// Write the cursor on a background thread, so forwarding never waits for it. Cursors
+	/// due while one is written are coalesced, and all are written on checkpoints and exit.
+	#[serde(default)]
+	pub async_cursor: bool,
+	/// What to do with entries without `__CURSOR`, which would be forwarded again after a
+	/// restart as the cursor can't advance to them
+	#[serde(default)]
+	pub on_missing_cursor: MissingCursorPolicy,
+	/// Whether entries may be duplicated or lost after a crash
+	#[serde(default)]
+	pub delivery: Delivery,
+	/// After seeking to the persisted cursor, step onto its entry so reading continues after
+	/// it. Without, the entry of the cursor is forwarded again, for at-least-once delivery if
+	/// the sinks may have lost it. If the entry is gone, reading continues from the seek point
+	/// either way.
+	#[serde(default = "default_true")]
+	pub reposition_after_seek: bool,
+	/// What to do when the cursor file was replaced by another process, e.g. by orchestration
+	/// restoring state, noticed by its inode changing before the next cursor update
+	#[serde(default)]
+	pub on_cursor_replaced: CursorReplacedPolicy,
+	/// Identifies this forwarder in the json formats and the `{instance_id}` placeholder.
+	/// `auto` generates a random id once, kept in `instance_id` next to the cursor file.
+	#[serde(default)]
+	pub instance_id: Option<String>,
+	/// Don't persist the cursor during this time after startup, so a crash loop doesn't
+	/// advance it. Entries forwarded in the meantime are forwarded again after a crash.
+	#[serde(default, with = "humantime_serde")]
+	pub cursor_initial_delay: Option<Duration>,
+	/// Don't persist the cursor before this many entries were written after startup, like
+	/// `cursor_initial_delay`. With both, the cursor is held back until both have passed.
+	#[serde(default)]
+	pub cursor_initial_entries: u64,
+	/// Maximum age of the cursor file for `--healthcheck` to succeed. The cursor is only
+	/// written while entries are forwarded, so this should exceed the longest quiet period.
+	#[serde(
+		default = "default_healthcheck_max_cursor_age",
+		with = "humantime_serde"
+	)]
+	pub healthcheck_max_cursor_age: Duration,
+	pub log_writer_config: LogWriterConfig,
+	/// Write the PID to this file while running
+	#[serde(default)]
+	pub pid_file: Option<PathBuf>,
+	/// Unix socket to answer `status` requests on
+	#[serde(default)]
+	pub control_socket: Option<PathBuf>,
+	/// Where to start reading if there is no usable cursor
+	#[serde(default)]
+	pub start_position: StartPosition,
+	/// Time filling the `{year}`, `{month}`, `{day}` and `{hour}` placeholders of
+	/// `target_dir` and `prefix`
+	#[serde(default)]
+	pub bucket_by: crate::router::BucketBy,
+	/// Write into a separate set of files per value of this field, prefixed with the value
+	/// and `+`. Other characters than ASCII alphanumerics, `-`, `_` and `.` are
+	/// percent-encoded, an entry without the field is routed as `%none`.
+	#[serde(default)]
+	pub route_by: Option<crate::router::RouteBy>,
+	/// Spread the entries over several sets of files, numbered from 0 after the route key.
+	/// Entries are only ordered within one file, with `mode: hash` per unit. Needs at least
+	/// 1 and at most `max_open_writers` files.
+	#[serde(default)]
+	pub fan_out: Option<crate::router::FanOut>,
+	/// Start a new set of files whenever `_BOOT_ID` changes, named after the boot id and the
+	/// time the boot started, for a per-boot archive. Without `only_local` the entries of the
+	/// boots of several machines interleave, so each is written into the files of its boot,
+	/// which stay open up to `max_open_writers`.
+	#[serde(default)]
+	pub file_per_boot: bool,
+	/// Facility of entries without `SYSLOG_FACILITY`, for `route_by: facility` and the
+	/// syslog format
+	#[serde(default)]
+	pub default_facility: crate::syslog::Facility,
+	/// Maximum number of routed writers kept open at the same time, across all routing
+	/// features. The least recently used writer is closed if more would be needed.
+	#[serde(default = "default_max_open_writers", alias = "max_open_files")]
+	pub max_open_writers: usize,
+	/// Maximum number of bytes stored in the target directory of each sink
+	#[serde(default)]
+	pub max_total_bytes: Option<u64>,
+	/// What to do once `max_total_bytes` would be exceeded
+	#[serde(default)]
+	pub on_budget_exceeded: crate::budget::BudgetPolicy,
+	/// Open the files of all routes known at startup, so they exist before the first entry:
+	/// those of `fan_out`, and with `route_by` those of the identifiers or facilities if
+	/// `match_expression` only matches a list of them
+	#[serde(default)]
+	pub precreate_routes: bool,
+	/// Create the target directory of files sinks again if it vanished at runtime, and reopen
+	/// their files, instead of failing or writing into deleted files
+	#[serde(default)]
+	pub recreate_target_dir: bool,
+	/// Close each output file after this many entries and continue in a new one, for uniform
+	/// batches downstream. `max_file_size` of log_writer still applies.
+	#[serde(default)]
+	pub rotate_every_n_entries: Option<u64>,
+	/// Summarize each output file once it is closed, by rotation or at exit: time and cursor
+	/// of its first and last entry and the number of entries
+	#[serde(default)]
+	pub file_trailer: Option<crate::trailer::TrailerFormat>,
+	/// Write the checksum with this algorithm and the number of bytes and lines of each
+	/// output file once it is rotated or closed, into a file next to it named with
+	/// `.manifest.json` appended
+	#[serde(default)]
+	pub file_manifest: Option<crate::manifest::ManifestAlgorithm>,
+	/// Write into this directory while `log_writer_config.target_dir` is full or read-only,
+	/// switching back once writing there succeeds again. The cursor advances either way.
+	#[serde(default)]
+	pub overflow_dir: Option<PathBuf>,
+	/// How often writing to the target directory is tried again while writing into
+	/// `overflow_dir`
+	#[serde(default = "default_overflow_retry_interval", with = "humantime_serde")]
+	pub overflow_retry_interval: Duration,
+	/// Append the byte offset, reception time and cursor of an entry to an index next to
+	/// each output file (the file name with `.idx`) at most this often
+	#[serde(default, with = "humantime_serde")]
+	pub index_interval: Option<Duration>,
+	/// Append a JSON line with the name of each output file opened and the cursor of its
+	/// first entry to `file_events` next to the cursor file, including files rotated inside
+	/// log_writer by `max_file_size`.
+	#[serde(default)]
+	pub file_events: bool,
+	/// Only read entries of the currently running boot
+	#[serde(default)]
+	pub current_boot_only: bool,
+	/// Only read entries logged on this machine, set to false to also forward those received
+	/// from other hosts, e.g. by `systemd-journal-remote` on a central collector
+	#[serde(default = "default_true")]
+	pub only_local: bool,
+	/// `/etc/machine-id`, read at startup without `only_local` to recognize remote entries
+	#[serde(skip)]
+	pub machine_id: Option<String>,
+	/// Only read entries of the boot with this `_BOOT_ID`
+	#[serde(default)]
+	pub boot_id: Option<String>,
+	/// Only read entries of the boot at this offset as in `journalctl -b`: 0 for the newest
+	/// boot, -1 for the one before, 1 for the oldest. Resolved to `boot_id` at startup.
+	#[serde(default)]
+	pub boot_offset: Option<i32>,
+	/// Persist the cursor immediately after entries with this or a more severe priority
+	#[serde(default)]
+	pub always_persist_above_priority: Option<crate::writer::Priority>,
+	/// Write entries with this or a more severe priority through right away: flush all sinks,
+	/// including pending http batches, even while catching up or within
+	/// `cursor_initial_delay` or `cursor_initial_entries`, and persist the cursor, waiting for
+	/// it with `async_cursor`
+	#[serde(default)]
+	pub flush_immediately_above_priority: Option<crate::writer::Priority>,
+	/// Only forward entries matching this expression in `journalctl` syntax
+	#[serde(default)]
+	pub match_expression: Option<crate::match_expr::MatchExpression>,
+	/// Only forward entries received via one of these transports, all if empty
+	#[serde(default)]
+	pub transports: Vec<crate::journal::Transport>,
+	/// Only forward entries of processes running as one of these user ids, all if empty
+	#[serde(default)]
+	pub uids: Vec<u32>,
+	/// Only forward entries of processes running as one of these group ids, all if empty
+	#[serde(default)]
+	pub gids: Vec<u32>,
+	/// If entries are older than this, e.g. after downtime, only flush the output together
+	/// with cursor updates until caught up
+	#[serde(default, with = "humantime_serde")]
+	pub catch_up_threshold: Option<Duration>,
+	/// Once no entry arrived for this long, flush all sinks, including batches below their
+	/// thresholds and output held back while catching up, and persist the cursor of the last
+	/// entry written
+	#[serde(default, with = "humantime_serde")]
+	pub idle_flush_after: Option<Duration>,
+	/// While draining the entries available at a wakeup, check for `on_sighup:
+	/// reopen_output` and config reloads only every this many entries instead of before
+	/// each one. They are always checked once all entries are read.
+	#[serde(default)]
+	pub max_drain_per_wakeup: Option<usize>,
+	/// If the reader had to fall back to `start_position` because the persisted cursor is
+	/// gone, skip entries not newer than the last forwarded one
+	#[serde(default)]
+	pub dedup_on_reconnect: bool,
+	/// If the persisted cursor is older than this, e.g. after long downtime, skip ahead to
+	/// entries received within this duration. The time range and, within the cursor's
+	/// journal file, the number of entries skipped are logged.
+	#[serde(default, with = "humantime_serde")]
+	pub max_backfill: Option<Duration>,
+	/// Resume this many entries before the persisted cursor, to pick up entries that weren't
+	/// completely written when the cursor was. Entries of the same journal file up to the
+	/// cursor are skipped by sequence number. Sequence numbers of other journal files, e.g.
+	/// the one rotated before the cursor's or those of other users, can't be compared, so
+	/// their re-read entries are forwarded again.
+	#[serde(default)]
+	pub reread_entries: usize,
+	/// Like `reread_entries`, but resume at entries received this long before the cursor
+	/// instead
+	#[serde(default, with = "humantime_serde")]
+	pub reread_window: Option<Duration>,
+	/// Remember the cursors of this many of the last forwarded entries, and skip entries
+	/// read again with one of them, also of other journal files and after reconnecting.
+	/// Each takes about 24 bytes; the oldest forwarded is evicted first, so re-read entries
+	/// older than all remembered ones are forwarded again. 0 disables the cache.
+	#[serde(default)]
+	pub dedup_cache_size: usize,
+	/// Upper bound of the delay before reopening the journal after the reader failed, which
+	/// starts at one second and doubles with each consecutive failure
+	#[serde(default = "default_reconnect_max_delay", with = "humantime_serde")]
+	pub reconnect_max_delay: Duration,
+	/// Add a random delay of up to this to each reconnect, to spread out a fleet reconnecting
+	/// at the same time
+	#[serde(default, with = "humantime_serde")]
+	pub reconnect_jitter: Option<Duration>,
+	/// Exit after this many consecutive failed reconnects without reading an entry, instead
+	/// of retrying forever
+	#[serde(default)]
+	pub max_reconnect_attempts: Option<u32>,
+	/// Restart forwarding in process this many times after it failed, with the cursor of the
+	/// entries already written persisted first. 0 exits on the first failure
+	#[serde(default)]
+	pub max_restarts: u32,
+	/// What to do if an entry can't be written
+	#[serde(default)]
+	pub on_error: ErrorPolicy,
+	/// With `on_error: skip`, abort once more than this many entries failed in a row, so a
+	/// systematic failure isn't skipped forever
+	#[serde(default)]
+	pub max_consecutive_errors: Option<u64>,
+	/// What to do on SIGHUP
+	#[serde(default)]
+	pub on_sighup: SighupAction,
+	/// Reload the config file when it changes, and reopen the sinks with its output, format
+	/// and entry filter options, keeping the reader and cursor. A file failing to parse or
+	/// whose sinks can't be opened is reported and ignored.
+	#[serde(default)]
+	pub watch_config: bool,
+	/// Set by `load`, `None` for a config read from stdin
+	#[serde(skip)]
+	pub source: Option<ConfigSource>,
+	/// Record entries skipped because of `on_error: skip` in this file
+	#[serde(default)]
+	pub error_sink: Option<crate::dead_letter::ErrorSinkConfig>,
+	/// On shutdown, wait up to this long for the pending batches of http and otlp sinks to be
+	/// acknowledged, and persist the cursor of the last entry only if all were. Without it,
+	/// batches are retried up to `max_retries` and the cursor stays at its last update.
+	#[serde(default, with = "humantime_serde")]
+	pub shutdown_ack_timeout: Option<Duration>,
+	/// Report writes to the output taking longer than this, and abort if `on_error` is
+	/// `abort`. Time spent blocking for `on_budget_exceeded: block` isn't counted.
+	#[serde(default, with = "humantime_serde")]
+	pub write_timeout: Option<Duration>,
+	/// Report entries taking longer than a threshold from being read until written, and run
+	/// a command if that persists
+	#[serde(default)]
+	pub write_latency: Option<crate::latency::LatencyConfig>,
+	/// Fields every entry is expected to have, e.g. `CONTAINER_NAME`
+	#[serde(default)]
+	pub required_fields: Vec<String>,
+	/// When `required_fields` are checked
+	#[serde(default)]
+	pub required_fields_check: RequiredFieldsCheck,
+	/// Command transforming the json fields of each entry, replacing them in the structured
+	/// formats. Only run while a sink writes one of them.
+	#[serde(default)]
+	pub enrich_command: Option<crate::enrich::EnrichConfig>,
+	/// Regular expression replacements applied in order to the message of each entry, before
+	/// `redact`
+	#[serde(default)]
+	pub message_transforms: Vec<crate::transform::MessageTransform>,
+	/// Replace the values of fields and matches within the message before writing entries
+	#[serde(default)]
+	pub redact: Option<crate::redact::RedactConfig>,
+	/// Only forward entries in which the value of one of these fields differs from the last
+	/// one seen, e.g. a state field repeated by a service. Entries without them are forwarded.
+	#[serde(default)]
+	pub change_only_fields: Vec<String>,
+	/// What to do with entries without reception time, source time or
+	/// `__REALTIME_TIMESTAMP`
+	#[serde(default)]
+	pub on_missing_timestamp: MissingTimestampPolicy,
+	/// Journal field with the time of the entry, in microseconds since the epoch or RFC 3339,
+	/// rendered instead of the reception time if present and valid
+	#[serde(default)]
+	pub timestamp_field: Option<String>,
+	/// Limit of the total size of all fields of an entry
+	#[serde(default)]
+	pub max_entry_bytes: Option<usize>,
+	/// What to do with entries larger than `max_entry_bytes`. Each is logged, and the
+	/// number skipped and truncated is summarized at exit.
+	#[serde(default)]
+	pub on_oversized_entry: OversizedEntryPolicy,
+	/// What to do with entries timestamped in the future, e.g. because of clock skew
+	#[serde(default)]
+	pub future_timestamp_policy: FutureTimestampPolicy,
+	/// Never render a timestamp earlier than the one of the previous entry
+	#[serde(default)]
+	pub enforce_monotonic_timestamps: bool,
+	/// Warn if the reception time goes back by more than this, e.g. after an NTP correction
+	#[serde(default, with = "humantime_serde")]
+	pub clock_jump_threshold: Option<Duration>,
+	/// How timestamps are rendered after a clock jump. Applied before
+	/// `enforce_monotonic_timestamps`, which otherwise repeats the timestamp before the jump
+	/// until the clock caught up
+	#[serde(default)]
+	pub on_clock_jump: ClockJumpPolicy,
+	/// How entries are serialized
+	#[serde(default)]
+	pub format: crate::writer::OutputFormat,
+	/// Serialize at most this many fields besides `MESSAGE` in the json format, the number
+	/// of left out fields is added as `_truncated_fields`
+	#[serde(default)]
+	pub max_fields: Option<usize>,
+	/// Truncate field values to this many bytes in the json format
+	#[serde(default)]
+	pub max_field_bytes: Option<usize>,
+	/// Indent the json format with `--dry-run`, file output stays one line per entry
+	#[serde(default)]
+	pub json_pretty: bool,
+	/// Write the fields of the json formats sorted by name, instead of the derived fields
+	/// first and then those of the entry
+	#[serde(default)]
+	pub sort_fields: bool,
+	/// Write these fields of the json formats first, in this order
+	#[serde(default)]
+	pub field_order: Vec<String>,
+	/// Columns of the csv and tsv formats, any key of the json format
+	#[serde(default = "default_csv_columns")]
+	pub csv_columns: Vec<String>,
+	/// Write the column names as first row whenever a file writer of the csv and tsv formats
+	/// is opened
+	#[serde(default)]
+	pub csv_header: bool,
+	/// Pad or truncate the identifier to this many characters in the text format
+	#[serde(default)]
+	pub pad_identifier: Option<usize>,
+	/// Strip the identifier to its last path component, e.g. `foo` for `/usr/bin/foo`. The
+	/// json formats keep the unmodified `SYSLOG_IDENTIFIER` as `raw_identifier`
+	#[serde(default)]
+	pub identifier_basename: bool,
+	/// Lowercase the identifier, with the json formats keeping it as `raw_identifier`
+	#[serde(default)]
+	pub lowercase_identifier: bool,
+	/// Escaping of colons in the identifier, which would make the text format ambiguous
+	#[serde(default)]
+	pub identifier_colons: IdentifierColonPolicy,
+	/// Pad or truncate the unit name column to this many characters in the text format
+	#[serde(default, alias = "pad_hostname")]
+	pub pad_unit: Option<usize>,
+	/// Layout of the text format
+	#[serde(default)]
+	pub text_template: crate::template::Template,
+	/// `strftime` format of `{utc_time}` and `{local_time}` instead of RFC 3339, e.g.
+	/// `%Y-%m-%d %H:%M:%S%.3f`
+	#[serde(default)]
+	pub timestamp_format: Option<crate::template::TimestampFormat>,
+	/// Zone of the timestamp of the `zoned_text` format: `local`, `UTC` or an offset like
+	/// `+02:00`. Its offset is appended to a `timestamp_format` without one.
+	#[serde(default)]
+	pub timezone: crate::template::Timezone,
+	/// Replace `_HOSTNAME` of all entries in the output with this name, e.g. a logical name
+	/// of the host in a fleet
+	#[serde(default)]
+	pub force_hostname: Option<String>,
+	/// Journal field shown in the unit name column of the text format, `_HOSTNAME` is used
+	/// if the entry doesn't have it
+	#[serde(default = "default_unit_field")]
+	pub unit_field: String,
+	/// Fields tried in order as the message in the text format if `MESSAGE` is missing or
+	/// empty
+	#[serde(default)]
+	pub message_fallback_fields: Vec<String>,
+	/// Append `(CODE_FILE:CODE_LINE)` to the message in the text format, if present
+	#[serde(default)]
+	pub include_code_location: bool,
+	/// Write `{message}` of the text format in double quotes, with `"`, `\` and line breaks
+	/// escaped by a backslash
+	#[serde(default)]
+	pub quote_message: bool,
+	/// Write start and stop events of the daemon into all sinks, marked with `_meta=lifecycle`
+	#[serde(default)]
+	pub lifecycle_events: bool,
+	/// Add `CONTAINER_NAME`, `CONTAINER_ID` and `IMAGE_NAME` as `container_name`,
+	/// `container_id` and `image_name` to the json formats, and use `CONTAINER_NAME` as
+	/// identifier if `SYSLOG_IDENTIFIER` is missing
+	#[serde(default)]
+	pub container_enrichment: bool,
+	/// Add the time the entry was processed by the forwarder as `ingested_at` to the json
+	/// formats, so the forwarding delay can be measured downstream
+	#[serde(default)]
+	pub ingestion_timestamp: bool,
+	/// Add `_TRANSPORT` as `transport` to the json formats, empty if the entry has none, e.g.
+	/// to tell stdout of services from kernel messages in a merged file
+	#[serde(default)]
+	pub include_transport: bool,
+	/// Add the priority as `severity` object to the json formats, with its number as `code`,
+	/// the syslog `keyword`, the verbose `name` and the OpenTelemetry number as `otel`
+	#[serde(default)]
+	pub rich_severity: bool,
+	/// Add a stable hash of the message as `msg_hash` to the json formats and `{msg_hash}`,
+	/// to group identical messages downstream
+	#[serde(default)]
+	pub message_hash: Option<crate::fingerprint::MessageHashConfig>,
+	/// Add `_CMDLINE` as `cmdline` to the json formats, with its NUL separated arguments
+	/// joined by spaces and quoted where needed to split them again. A value already joined
+	/// by spaces is kept.
+	#[serde(default)]
+	pub include_cmdline: bool,
+	/// Add the number of entries filtered out since the previous record as
+	/// `_skipped_since_last` to the json formats. Only entries dropped by the forwarder count,
+	/// e.g. by `change_only_fields`, `shard_count` or `on_oversized_entry: skip`, those not
+	/// matching the journal matches like `match_expression` are never read.
+	#[serde(default)]
+	pub skipped_since_last: bool,
+	/// Annotate each entry with the filters it passed as `_match` in the json formats and
+	/// the `{match}` placeholder, for debugging filter configs
+	#[serde(default)]
+	pub annotate_match: bool,
+	/// Number the forwarded records as `seq` in the json formats and the `{seq}`
+	/// placeholder, counting up by one per record. The number is persisted with the cursor, so
+	/// entries forwarded again after a restart get the same numbers.
+	#[serde(default)]
+	pub forwarder_seq: bool,
+	/// Add `timestamp_tiebreaker` to the json formats, so records with the same
+	/// `timestamp` can be sorted by (`timestamp`, `timestamp_tiebreaker`) in their original
+	/// order
+	#[serde(default)]
+	pub timestamp_tiebreaker: Option<Tiebreaker>,
+	/// Periodically write the number of entries skipped, deduplicated or dropped into all
+	/// sinks, marked with `_meta=suppression_report`
+	#[serde(default)]
+	pub suppression_reports: bool,
+	/// Write a marker with `_meta: gap` before an entry whose sequence number doesn't follow
+	/// the one of the previous entry read, e.g. after entries were rotated or vacuumed before
+	/// being forwarded. Sequence numbers are compared per seqnum id. As the entries left out
+	/// by `match_expression` and the other journal matches would be counted as missing, it can't
+	/// be combined with them.
+	#[serde(default)]
+	pub emit_gap_markers: bool,
+	/// Minimum time between two suppression reports, which are only written with entries
+	#[serde(
+		default = "default_suppression_report_interval",
+		with = "humantime_serde"
+	)]
+	pub suppression_report_interval: Duration,
+	/// Forward with this many threads, each handling the entries of a hash partition of the
+	/// units, with its own cursor file and files (suffixed and prefixed with `shard<n>`)
+	#[serde(default = "default_shard_count")]
+	pub shard_count: usize,
+	/// Partition the entries among the shards by these expressions in `journalctl` syntax
+	/// instead of by unit, one per shard, so each shard only reads its own entries rather than
+	/// all of them. Entries matching none of them are not forwarded.
+	#[serde(default)]
+	pub shard_matches: Vec<crate::match_expr::MatchExpression>,
+	/// Index of the shard this config is used for, set by `shard_count`
+	#[serde(skip)]
+	pub shard: Option<usize>,
+	/// The expression of `shard_matches` of this shard
+	#[serde(skip)]
+	pub shard_match: Option<crate::match_expr::MatchExpression>,
+	/// Additional outputs, each receiving all entries in its own format
+	#[serde(default)]
+	pub sinks: Vec<crate::sink::SinkConfig>,
+	/// Replace control characters (except tab) in the message with `\xNN` escapes, only
+	/// applies to the text format
+	#[serde(default)]
+	pub sanitize_control_chars: bool,
+}
+
+fn default_unit_field() -> String {
+	"_SYSTEMD_UNIT".to_string()
+}
+
+fn default_suppression_report_interval() -> Duration {
+	Duration::from_secs(60)
+}
+
+fn default_csv_columns() -> Vec<String> {
+	["timestamp", "_HOSTNAME", "SYSLOG_IDENTIFIER", "MESSAGE"]
+		.iter()
+		.map(|column| column.to_string())
+		.collect()
+}
+
+fn default_shard_count() -> usize {
+	1
+}
+
+fn default_true() -> bool {
+	true
+}
+
+fn default_max_open_writers() -> usize {
+	64
+}
+
+fn default_reconnect_max_delay() -> Duration {
+	Duration::from_secs(60)
+}
+
+fn default_overflow_retry_interval() -> Duration {
+	Duration::from_secs(60)
+}
+
+fn default_healthcheck_max_cursor_age() -> Duration {
+	Duration::from_secs(10 * 60)
+}
+
+/// Replace `instance_id: auto` with the id persisted next to the cursor file, which is
+/// generated if it doesn't exist yet.
+pub fn resolve_instance_id(config: &mut Config) -> Result<()> {
+	if config.instance_id.as_deref() != Some("auto") {
+		return Ok(());
+	}
+
+	let path = config.cursor_file.with_file_name("instance_id");
+	let instance_id = match std::fs::read_to_string(&path) {
+		Ok(instance_id) => instance_id.trim().to_string(),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			let instance_id = random_uuid()?;
+			if let Some(dir) = path.parent() {
+				std::fs::create_dir_all(dir)
+					.with_context(|| format!("Creating instance id directory {}", dir.display()))?;
+			}
+			std::fs::write(&path, &instance_id)
+				.with_context(|| format!("Writing instance id to {}", path.display()))?;
+			log::info!("generated instance id {}", instance_id);
+			instance_id
+		}
+		Err(e) => return Err(e).with_context(|| format!("Reading instance id {}", path.display())),
+	};
+	config.instance_id = Some(instance_id);
+
+	Ok(())
+}
+
+/// A random version 4 UUID
+fn random_uuid() -> Result<String> {
+	let mut bytes = [0u8; 16];
+	std::fs::File::open("/dev/urandom")
+		.and_then(|mut urandom| urandom.read_exact(&mut bytes))
+		.context("Reading /dev/urandom")?;
+	bytes[6] = (bytes[6] & 0x0f) | 0x40;
+	bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+	let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+	Ok(format!(
+		"{}-{}-{}-{}-{}",
+		&hex[..8],
+		&hex[8..12],
+		&hex[12..16],
+		&hex[16..20],
+		&hex[20..]
+	))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPosition {
+	Head,
+	Tail,
+}
+
+impl Default for StartPosition {
+	fn default() -> Self {
+		StartPosition::Tail
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tiebreaker {
+	/// The sequence number of the entry in its journal files, from the `i=` field of the
+	/// cursor
+	Seqnum,
+	/// Counting from 0 for each timestamp, as rendered after clamping
+	Counter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SighupAction {
+	/// Flush and exit, as on SIGTERM
+	Exit,
+	/// Flush and reopen the output files with the next entry, for external rotation like
+	/// logrotate, keeping the reader and cursor
+	ReopenOutput,
+}
+
+impl Default for SighupAction {
+	fn default() -> Self {
+		SighupAction::Exit
+	}
+}
+
+/// When the cursor of an entry is persisted, relative to writing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+	/// After the entry is written and flushed, so entries written since the last cursor
+	/// update are forwarded again after a crash
+	AtLeastOnce,
+	/// Before the entry is written, so a crash while writing loses the entry instead. Costs
+	/// a cursor write per entry, and `async_cursor` is ignored.
+	AtMostOnce,
+}
+
+impl Default for Delivery {
+	fn default() -> Self {
+		Delivery::AtLeastOnce
+	}
+}
+
+/// How timestamps are rendered after the clock jumped back, see `clock_jump_threshold`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockJumpPolicy {
+	/// Only warn, rendering the reception times as they are
+	Keep,
+	/// Derive the timestamps of entries of the same boot from their monotonic time, until the
+	/// clock passed the time before the jump again
+	Monotonic,
+}
+
+impl Default for ClockJumpPolicy {
+	fn default() -> Self {
+		ClockJumpPolicy::Keep
+	}
+}
+
+/// What to do with an entry the journal returned without `__CURSOR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingCursorPolicy {
+	/// Warn and forward the entry, leaving the persisted cursor at the previous entry
+	Continue,
+	/// Stop forwarding with an error
+	Abort,
+}
+
+impl Default for MissingCursorPolicy {
+	fn default() -> Self {
+		MissingCursorPolicy::Continue
+	}
+}
+
+/// What to do when another process replaced the cursor file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CursorReplacedPolicy {
+	/// Overwrite it with the cursor of the forwarder
+	Keep,
+	/// Reopen the journal and continue after the cursor in the new file
+	Reload,
+}
+
+impl Default for CursorReplacedPolicy {
+	fn default() -> Self {
+		CursorReplacedPolicy::Keep
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+	/// Stop forwarding and exit with an error
+	Abort,
+	/// Log the error and continue with the next entry
+	Skip,
+}
+
+impl Default for ErrorPolicy {
+	fn default() -> Self {
+		ErrorPolicy::Abort
+	}
+}
+
+/// How a colon in the identifier is written by the text and syslog formats, where `: `
+/// ends the identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentifierColonPolicy {
+	/// Write the identifier as is
+	Keep,
+	/// Write `:` as `%3A`, and `%` as `%25` so the identifier can be decoded
+	PercentEncode,
+	/// Put identifiers containing a colon in double quotes, escaping `"` and `\`
+	Quote,
+	/// Replace each `:` by `_`
+	Replace,
+}
+
+impl Default for IdentifierColonPolicy {
+	fn default() -> Self {
+		IdentifierColonPolicy::Keep
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FutureTimestampPolicy {
+	/// Write the timestamp as is
+	Keep,
+	/// Render the current time instead
+	ClampToNow,
+	/// Don't forward the entry
+	Skip,
+}
+
+impl Default for FutureTimestampPolicy {
+	fn default() -> Self {
+		FutureTimestampPolicy::Keep
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingTimestampPolicy {
+	/// Use the time the entry is forwarded at
+	UseNow,
+	/// Don't forward the entry
+	Skip,
+}
+
+impl Default for MissingTimestampPolicy {
+	fn default() -> Self {
+		MissingTimestampPolicy::UseNow
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedEntryPolicy {
+	/// Don't forward the entry
+	Skip,
+	/// Shorten the message, so the entry fits if possible
+	Truncate,
+}
+
+impl Default for OversizedEntryPolicy {
+	fn default() -> Self {
+		OversizedEntryPolicy::Skip
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequiredFieldsCheck {
+	/// Fail on startup if the newest matching entry misses one
+	Startup,
+	/// Fail writing each entry missing one, handled according to `on_error`
+	PerEntry,
+}
+
+impl Default for RequiredFieldsCheck {
+	fn default() -> Self {
+		RequiredFieldsCheck::Startup
+	}
+}
+
+/// Missing `required_fields` of the entry, as error.
+pub(crate) fn check_required_fields(entry: &journald::JournalEntry, config: &Config) -> Result<()> {
+	let missing: Vec<&str> = config
+		.required_fields
+		.iter()
+		.map(String::as_str)
+		.filter(|field| entry.get_field(field).is_none())
+		.collect();
+	if !missing.is_empty() {
+		bail!("Entry misses required fields {}", missing.join(", "));
+	}
+
+	Ok(())
+}
+
+/// Replace `${VAR}` with the value of the environment variable `VAR`.
+///
+/// `${VAR:-default}` uses `default` if `VAR` is unset or empty, `$$` is a literal `$`. A
+/// reference to an unset variable without default is an error. Interpolation is done on the
+/// raw file, so values have to be quoted as they would be if written into the file directly.
+/// With `comments`, the `#` comments of YAML and TOML are left as they are, also inside
+/// block scalars, whose `#` lines can't be told apart from comments.
+///
+/// `${HOSTNAME}` falls back to the system's hostname, as the variable is usually only set by
+/// interactive shells and container runtimes.
+fn interpolate_env(input: &str, comments: bool) -> Result<String> {
+	let mut output = String::with_capacity(input.len());
+	for line in input.split_inclusive('\n') {
+		let (content, comment) = if comments {
+			line.split_at(comment_start(line))
+		} else {
+			(line, "")
+		};
+		interpolate_line(content, &mut output)?;
+		output.push_str(comment);
+	}
+
+	Ok(output)
+}
+
+/// Position of the `#` starting a comment in the line, outside of quoted values and at the
+/// start or after whitespace, or the length of the line.
+fn comment_start(line: &str) -> usize {
+	let mut quote = None;
+	let mut escaped = false;
+	let mut previous = None;
+	for (i, c) in line.char_indices() {
+		match (quote, c) {
+			(Some('"'), '\\') if !escaped => {
+				escaped = true;
+				previous = Some(c);
+				continue;
+			}
+			(Some(open), _) if c == open && !escaped => quote = None,
+			(Some(_), _) => {}
+			// an apostrophe within a plain value doesn't start a quote
+			(None, '"') | (None, '\'') if previous.map_or(true, opens_value) => quote = Some(c),
+			(None, '#') if previous.map_or(true, char::is_whitespace) => return i,
+			(None, _) => {}
+		}
+		escaped = false;
+		previous = Some(c);
+	}
+
+	line.len()
+}
+
+fn opens_value(previous: char) -> bool {
+	previous.is_whitespace() || "[{,:=".contains(previous)
+}
+
+fn interpolate_line(input: &str, output: &mut String) -> Result<()> {
+	let mut rest = input;
+	while let Some(pos) = rest.find('$') {
+		output.push_str(&rest[..pos]);
+		rest = &rest[pos..];
+
+		if rest.starts_with("$$") {
+			output.push('$');
+			rest = &rest[2..];
+		} else if rest.starts_with("${") {
+			let end = match rest.find('}') {
+				Some(end) => end,
+				None => bail!("Unterminated variable reference {}", rest.trim_end()),
+			};
+			let reference = &rest[2..end];
+			let (name, default) = match reference.find(":-") {
+				Some(i) => (&reference[..i], Some(&reference[i + 2..])),
+				None => (reference, None),
+			};
+
+			let value = env_var(name).filter(|value| !value.is_empty() || default.is_none());
+			match (value, default) {
+				(Some(value), _) => output.push_str(&value),
+				(None, Some(default)) => output.push_str(default),
+				(None, None) => bail!(
+					"Environment variable {} referenced in config is not set",
+					name
+				),
+			}
+			rest = &rest[end + 1..];
+		} else {
+			output.push('$');
+			rest = &rest[1..];
+		}
+	}
+	output.push_str(rest);
+
+	Ok(())
+}
+
+fn env_var(name: &str) -> Option<String> {
+	let value = std::env::var(name).ok();
+	if value.is_some() || name != "HOSTNAME" {
+		return value;
+	}
+
+	let mut buf = [0u8; 256];
+	nix::unistd::gethostname(&mut buf)
+		.ok()
+		.map(|hostname| hostname.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn edit_distances() {
+		assert_eq!(edit_distance("cursor_file", "cursor_file"), 0);
+		assert_eq!(edit_distance("cursor_fiel", "cursor_file"), 2);
+		assert_eq!(edit_distance("prefx", "prefix"), 1);
+		assert_eq!(edit_distance("", "tail"), 4);
+	}
+
+	#[test]
+	fn unknown_field_suggests_the_closest_name() {
+		let error = serde_yaml::from_str::<Config>("cursor_fiel: /tmp/cursor\n")
+			.map_err(describe_parse_error)
+			.unwrap_err();
+		assert!(
+			error.to_string().ends_with("did you mean `cursor_file`?"),
+			"{}",
+			error
+		);
+
+		// nothing close enough
+		let error = serde_yaml::from_str::<Config>("frobnicate: true\n")
+			.map_err(describe_parse_error)
+			.unwrap_err();
+		assert!(!error.to_string().contains("did you mean"), "{}", error);
+	}
+
+	#[test]
+	fn interpolation_skips_comments() {
+		std::env::set_var("JOURNALD_WRITER_TEST_DIR", "/srv");
+		let config = "dir: \"${JOURNALD_WRITER_TEST_DIR}/logs\" # not ${UNSET_IN_TESTS}\n\
+			# e.g. ${UNSET_IN_TESTS}\n\
+			tag: \"a#${JOURNALD_WRITER_TEST_DIR:-x}\"\n\
+			price: \"$$5\"\n";
+		assert_eq!(
+			interpolate_env(config, true).unwrap(),
+			"dir: \"/srv/logs\" # not ${UNSET_IN_TESTS}\n\
+			# e.g. ${UNSET_IN_TESTS}\n\
+			tag: \"a#/srv\"\n\
+			price: \"$5\"\n"
+		);
+		assert!(interpolate_env("# ${UNSET_IN_TESTS}", false).is_err());
+		assert!(interpolate_env("dir: ${UNSET_IN_TESTS}", true).is_err());
+	}
+}
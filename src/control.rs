@@ -0,0 +1,64 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::status;
+
+/// Serve the control socket at `path` in a dedicated thread.
+///
+/// The protocol is line based: each request is a single command, answered with a single
+/// line of JSON. The only command is `status`. The socket is only accessible by its owner.
+pub(crate) fn spawn(path: &Path) -> Result<()> {
+	if path.exists() {
+		debug!("removing stale control socket {}", path.display());
+		fs::remove_file(path)
+			.with_context(|| format!("Removing stale control socket {}", path.display()))?;
+	}
+
+	let listener = UnixListener::bind(path)
+		.with_context(|| format!("Binding control socket {}", path.display()))?;
+	fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+		.with_context(|| format!("Restricting permissions of {}", path.display()))?;
+
+	thread::Builder::new()
+		.name("control".to_string())
+		.spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => {
+						if let Err(e) = handle(stream) {
+							debug!("control client failed: {:?}", e);
+						}
+					}
+					Err(e) => warn!("failed to accept control client: {}", e),
+				}
+			}
+		})
+		.context("Spawning control socket thread")?;
+
+	Ok(())
+}
+
+fn handle(stream: UnixStream) -> Result<()> {
+	// a stuck client must not block others for long
+	stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+	let mut writer = &stream;
+
+	for line in BufReader::new(&stream).lines() {
+		let response = match line?.trim() {
+			"" => continue,
+			"status" => status::snapshot(),
+			other => serde_json::json!({ "error": format!("unknown command {}", other) }),
+		};
+		writeln!(writer, "{}", response)?;
+	}
+
+	Ok(())
+}
@@ -0,0 +1,89 @@
+use std::fs::{rename, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use journald::JournalEntry;
+use log::*;
+
+use crate::writer;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorSinkConfig {
+	/// File skipped entries are recorded in
+	pub path: PathBuf,
+	/// Size after which the file is rotated to `<path>.1`, replacing an older rotated file
+	#[serde(default = "default_max_size")]
+	pub max_size: u64,
+}
+
+fn default_max_size() -> u64 {
+	16 * 1024 * 1024
+}
+
+/// Records entries which could not be written, together with the error.
+///
+/// Each record is the entry in the journal export format, preceded by a comment line
+/// containing the error.
+pub(crate) struct DeadLetter {
+	config: ErrorSinkConfig,
+	file: File,
+	size: u64,
+}
+
+impl DeadLetter {
+	pub(crate) fn open(config: ErrorSinkConfig) -> Result<Self> {
+		let file = open(&config.path)?;
+		let size = file
+			.metadata()
+			.with_context(|| format!("Reading metadata of {}", config.path.display()))?
+			.len();
+
+		Ok(Self { config, file, size })
+	}
+
+	pub(crate) fn record(&mut self, entry: &JournalEntry, error: &anyhow::Error) {
+		if let Err(e) = self.try_record(entry, error) {
+			warn!("failed to record skipped entry in error sink: {:?}", e);
+		}
+	}
+
+	fn try_record(&mut self, entry: &JournalEntry, error: &anyhow::Error) -> Result<()> {
+		let mut record = Vec::new();
+		writeln!(record, "# error: {:#}", error)?;
+		writer::write_export(entry, &mut record, None, None)?;
+
+		if self.size > 0 && self.size + record.len() as u64 > self.config.max_size {
+			self.rotate()?;
+		}
+
+		self.file
+			.write_all(&record)
+			.context("Writing to error sink")?;
+		self.file.flush().context("Flushing error sink")?;
+		self.size += record.len() as u64;
+
+		Ok(())
+	}
+
+	fn rotate(&mut self) -> Result<()> {
+		let mut rotated = self.config.path.clone().into_os_string();
+		rotated.push(".1");
+		rename(&self.config.path, &rotated).context("Rotating error sink")?;
+
+		self.file = open(&self.config.path)?;
+		self.size = 0;
+		debug!("rotated error sink {}", self.config.path.display());
+
+		Ok(())
+	}
+}
+
+fn open(path: &Path) -> Result<File> {
+	OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.with_context(|| format!("Open error sink: {}", path.display()))
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashSet, VecDeque};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+/// The cursors of the most recently forwarded entries, see `dedup_cache_size`.
+///
+/// Only 64 bit hashes of the cursors are kept, so memory is bounded by the capacity at
+/// about 24 bytes per entry. Once full, the oldest cursor is evicted for each new one. The
+/// order is by forwarding, not by use: a hit doesn't keep a cursor longer, as the cache
+/// holds the last forwarded entries, whichever were read again.
+#[derive(Debug)]
+pub(crate) struct RecentCursors {
+	capacity: usize,
+	/// Hashes in the order they were inserted, the oldest first
+	order: VecDeque<u64>,
+	hashes: HashSet<u64>,
+}
+
+impl RecentCursors {
+	pub(crate) fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			order: VecDeque::with_capacity(capacity),
+			hashes: HashSet::with_capacity(capacity),
+		}
+	}
+
+	/// Forget all entries, so they are forwarded again when read.
+	pub(crate) fn clear(&mut self) {
+		self.order.clear();
+		self.hashes.clear();
+	}
+
+	/// Whether the entry of `cursor` is among the recently forwarded ones.
+	pub(crate) fn contains(&self, cursor: &str) -> bool {
+		self.hashes.contains(&xxh3_64(cursor.as_bytes()))
+	}
+
+	/// Remember the entry of `cursor` as forwarded.
+	pub(crate) fn insert(&mut self, cursor: &str) {
+		let hash = xxh3_64(cursor.as_bytes());
+		if self.capacity == 0 || !self.hashes.insert(hash) {
+			return;
+		}
+		self.order.push_back(hash);
+		if self.order.len() > self.capacity {
+			if let Some(oldest) = self.order.pop_front() {
+				self.hashes.remove(&oldest);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_the_oldest_forwarded() {
+		let mut recent = RecentCursors::new(2);
+		recent.insert("s=1;i=1");
+		recent.insert("s=1;i=2");
+		assert!(recent.contains("s=1;i=1"));
+		assert!(recent.contains("s=1;i=2"));
+
+		// a hit doesn't refresh the cursor
+		recent.insert("s=1;i=1");
+		recent.insert("s=1;i=3");
+		assert!(!recent.contains("s=1;i=1"));
+		assert!(recent.contains("s=1;i=2"));
+		assert!(recent.contains("s=1;i=3"));
+	}
+
+	#[test]
+	fn clear_and_zero_capacity() {
+		let mut recent = RecentCursors::new(2);
+		recent.insert("s=1;i=1");
+		recent.clear();
+		assert!(!recent.contains("s=1;i=1"));
+
+		let mut disabled = RecentCursors::new(0);
+		disabled.insert("s=1;i=1");
+		assert!(!disabled.contains("s=1;i=1"));
+	}
+}
@@ -0,0 +1,169 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::*;
+
+/// What to do with an entry the `enrich_command` failed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnrichFailurePolicy {
+	/// Write the entry unmodified
+	Open,
+	/// Fail writing the entry, handled according to `on_error`
+	Closed,
+}
+
+impl Default for EnrichFailurePolicy {
+	fn default() -> Self {
+		EnrichFailurePolicy::Open
+	}
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnrichConfig {
+	/// Program and arguments, started once and restarted if it exits
+	pub command: Vec<String>,
+	/// Time to wait for the answer to an entry, the command is restarted after a timeout
+	#[serde(default = "default_timeout", with = "humantime_serde")]
+	pub timeout: Duration,
+	#[serde(default)]
+	pub on_failure: EnrichFailurePolicy,
+}
+
+fn default_timeout() -> Duration {
+	Duration::from_secs(1)
+}
+
+/// A long running command transforming entries, see `enrich_command`.
+///
+/// Each entry is written to its stdin as one line of the json format, and the command
+/// answers with one line containing a JSON object, which replaces the fields of the entry in
+/// the structured formats.
+#[derive(Debug)]
+pub(crate) struct Enricher {
+	config: EnrichConfig,
+	running: Option<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+	child: Child,
+	/// Entries written to stdin by a thread, so a command not reading them is timed out too
+	entries: Sender<Vec<u8>>,
+	lines: Receiver<std::io::Result<String>>,
+}
+
+impl Enricher {
+	pub(crate) fn new(config: EnrichConfig) -> Self {
+		Self {
+			config,
+			running: None,
+		}
+	}
+
+	/// The fields returned for the entry serialized as `json`, `None` if the command failed
+	/// and `on_failure` is `open`.
+	pub(crate) fn enrich(
+		&mut self,
+		json: &[u8],
+	) -> Result<Option<serde_json::Map<String, serde_json::Value>>> {
+		match self.exchange(json) {
+			Ok(fields) => Ok(Some(fields)),
+			Err(e) => {
+				self.stop();
+				match self.config.on_failure {
+					EnrichFailurePolicy::Open => {
+						warn!("enrich_command failed, writing entry unmodified: {:#}", e);
+						Ok(None)
+					}
+					EnrichFailurePolicy::Closed => Err(e.context("Enriching entry")),
+				}
+			}
+		}
+	}
+
+	fn exchange(&mut self, json: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>> {
+		let timeout = self.config.timeout;
+		let running = self.start()?;
+		if running.entries.send(json.to_vec()).is_err() {
+			bail!("Writing to enrich_command failed");
+		}
+
+		let line = match running.lines.recv_timeout(timeout) {
+			Ok(line) => line.context("Reading from enrich_command")?,
+			Err(RecvTimeoutError::Timeout) => {
+				bail!("enrich_command took longer than {:?}", timeout)
+			}
+			Err(RecvTimeoutError::Disconnected) => bail!("enrich_command exited"),
+		};
+		match serde_json::from_str(&line).context("Parsing answer of enrich_command")? {
+			serde_json::Value::Object(fields) => Ok(fields),
+			_ => bail!("enrich_command answered with something else than a JSON object"),
+		}
+	}
+
+	fn start(&mut self) -> Result<&mut Running> {
+		if self.running.is_none() {
+			let (program, args) = match self.config.command.split_first() {
+				Some(command) => command,
+				None => bail!("enrich_command is empty"),
+			};
+			let mut child = Command::new(program)
+				.args(args)
+				.stdin(Stdio::piped())
+				.stdout(Stdio::piped())
+				.spawn()
+				.with_context(|| format!("Starting enrich_command {}", program))?;
+			let mut stdin = child.stdin.take().unwrap();
+			let stdout = child.stdout.take().unwrap();
+
+			let (entries, received) = mpsc::channel::<Vec<u8>>();
+			thread::spawn(move || {
+				for entry in received {
+					if let Err(e) = stdin.write_all(&entry).and_then(|_| stdin.flush()) {
+						debug!("writing to enrich_command failed: {}", e);
+						break;
+					}
+				}
+			});
+
+			// lines are read on a thread, so waiting for them can time out
+			let (sender, lines) = mpsc::channel();
+			thread::spawn(move || {
+				for line in BufReader::new(stdout).lines() {
+					if sender.send(line).is_err() {
+						break;
+					}
+				}
+			});
+			debug!("started enrich_command {}", program);
+
+			self.running = Some(Running {
+				child,
+				entries,
+				lines,
+			});
+		}
+
+		Ok(self.running.as_mut().unwrap())
+	}
+
+	/// Kill the command, so it is restarted for the next entry and answers stay in order.
+	fn stop(&mut self) {
+		if let Some(mut running) = self.running.take() {
+			let _ = running.child.kill();
+			let _ = running.child.wait();
+		}
+	}
+}
+
+impl Drop for Enricher {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
@@ -0,0 +1,101 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use log::*;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc;
+
+use crate::metrics;
+
+/// What to do while no process has the named pipe open for reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FifoPolicy {
+	/// Wait for a reader
+	Block,
+	/// Drop lines, counted in `fifo_dropped`
+	Drop,
+}
+
+impl Default for FifoPolicy {
+	fn default() -> Self {
+		FifoPolicy::Block
+	}
+}
+
+/// Writing end of a named pipe, reopened when the reader went away.
+pub(crate) struct Fifo {
+	path: PathBuf,
+	policy: FifoPolicy,
+	file: Option<File>,
+}
+
+impl Fifo {
+	pub(crate) fn new(path: PathBuf, policy: FifoPolicy) -> Self {
+		Self {
+			path,
+			policy,
+			file: None,
+		}
+	}
+
+	pub(crate) fn write(&mut self, data: &[u8]) -> Result<()> {
+		loop {
+			let file = match self.open()? {
+				Some(file) => file,
+				None => {
+					metrics::FIFO_DROPPED.inc();
+					return Ok(());
+				}
+			};
+
+			match file.write_all(data) {
+				Ok(()) => return Ok(()),
+				Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+					debug!("reader of {} went away", self.path.display());
+					self.file = None;
+					if self.policy == FifoPolicy::Drop {
+						metrics::FIFO_DROPPED.inc();
+						return Ok(());
+					}
+				}
+				Err(e) => {
+					return Err(e).with_context(|| format!("Writing to {}", self.path.display()))
+				}
+			}
+		}
+	}
+
+	/// The open pipe, or `None` if there is no reader and lines are dropped.
+	fn open(&mut self) -> Result<Option<&mut File>> {
+		if self.file.is_none() {
+			let mut options = OpenOptions::new();
+			options.write(true);
+			if self.policy == FifoPolicy::Drop {
+				// fails with ENXIO instead of blocking if there is no reader
+				options.custom_flags(libc::O_NONBLOCK);
+			}
+
+			let file = match options.open(&self.path) {
+				Ok(file) => file,
+				Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+				Err(e) => {
+					return Err(e).with_context(|| format!("Opening {}", self.path.display()))
+				}
+			};
+			if self.policy == FifoPolicy::Drop {
+				// only the open is non blocking, writes wait for the reader to catch up
+				fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))
+					.with_context(|| format!("Setting {} to blocking", self.path.display()))?;
+			}
+			debug!("opened {}", self.path.display());
+			self.file = Some(file);
+		}
+
+		Ok(self.file.as_mut())
+	}
+}
@@ -0,0 +1,66 @@
+use xxhash_rust::{xxh3, xxh64};
+
+use crate::writer::Record;
+
+/// Hash function of `message_hash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+	/// 64 bit xxHash
+	Xxh64,
+	/// 64 bit XXH3, faster for short messages
+	Xxh3,
+	/// 64 bit FNV-1a, for consumers without an xxHash implementation
+	Fnv1a,
+}
+
+impl Default for HashAlgorithm {
+	fn default() -> Self {
+		HashAlgorithm::Xxh64
+	}
+}
+
+/// What goes into the `msg_hash` of a record
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageHashConfig {
+	#[serde(default)]
+	pub algorithm: HashAlgorithm,
+	/// Hash `SYSLOG_IDENTIFIER` too, so identical messages of different programs differ
+	#[serde(default)]
+	pub include_identifier: bool,
+	/// Hash `PRIORITY` too
+	#[serde(default)]
+	pub include_priority: bool,
+}
+
+/// Stable hash of the message, after `message_transforms` and `redact`, as 16 hex digits.
+///
+/// The hashed fields are separated by NUL, so the same values always give the same hash
+/// independent of the host and time of the entry.
+pub(crate) fn message_hash(record: &Record<'_>, config: &MessageHashConfig) -> String {
+	let log = record.entry;
+	let mut input = Vec::new();
+	if config.include_identifier {
+		input.extend_from_slice(log.get_field("SYSLOG_IDENTIFIER").unwrap_or("").as_bytes());
+		input.push(0);
+	}
+	if config.include_priority {
+		input.push(b'0' + record.priority as u8);
+		input.push(0);
+	}
+	input.extend_from_slice(log.get_message().unwrap_or("").as_bytes());
+
+	let hash = match config.algorithm {
+		HashAlgorithm::Xxh64 => xxh64::xxh64(&input, 0),
+		HashAlgorithm::Xxh3 => xxh3::xxh3_64(&input),
+		HashAlgorithm::Fnv1a => fnv1a(&input),
+	};
+	format!("{:016x}", hash)
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+	data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+		(hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+	})
+}
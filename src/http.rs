@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::*;
+
+/// Endpoint and batching of a `target: http` sink
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+	/// URL the batches are POSTed to, `http://` or `https://`
+	pub url: String,
+	/// Additional request headers, e.g. `Authorization`
+	#[serde(default)]
+	pub headers: BTreeMap<String, String>,
+	#[serde(default = "default_content_type")]
+	pub content_type: String,
+	/// POST once this many entries are buffered
+	#[serde(default = "default_batch_size")]
+	pub batch_size: usize,
+	/// POST buffered entries with the next entry after this time, and whenever the cursor is
+	/// persisted
+	#[serde(default = "default_flush_interval", with = "humantime_serde")]
+	pub flush_interval: Duration,
+	#[serde(default = "default_timeout", with = "humantime_serde")]
+	pub timeout: Duration,
+	/// Retries of a failed POST, backing off from 1s, before the sink fails and the batch is
+	/// dropped. With `write_timeout`, retries stop in time for the last POST to end before it.
+	#[serde(default = "default_max_retries")]
+	pub max_retries: u32,
+}
+
+fn default_content_type() -> String {
+	"application/x-ndjson".to_string()
+}
+
+fn default_batch_size() -> usize {
+	100
+}
+
+fn default_flush_interval() -> Duration {
+	Duration::from_secs(5)
+}
+
+fn default_timeout() -> Duration {
+	Duration::from_secs(10)
+}
+
+fn default_max_retries() -> u32 {
+	5
+}
+
+/// Call `send` until it succeeds, retrying up to `max_retries` times while backing off from
+/// 1s. Retries stop before `deadline`, and with `flush_limit` in time for the last attempt,
+/// which may take up to `timeout`, to end within it.
+pub(crate) fn send_with_retries(
+	config: &HttpConfig,
+	flush_limit: Option<Duration>,
+	deadline: Option<Instant>,
+	mut send: impl FnMut() -> Result<()>,
+) -> Result<()> {
+	let limit = flush_limit.map(|limit| Instant::now() + limit.saturating_sub(config.timeout));
+	let may_retry = |delay: Duration| {
+		let retry_at = Instant::now() + delay;
+		deadline.map_or(true, |deadline| retry_at < deadline)
+			&& limit.map_or(true, |limit| retry_at < limit)
+	};
+	let mut delay = Duration::from_secs(1);
+	let mut attempt = 0;
+	loop {
+		match send() {
+			Ok(()) => return Ok(()),
+			Err(e) if attempt < config.max_retries && may_retry(delay) => {
+				attempt += 1;
+				warn!(
+					"sending to {} failed, retrying in {:?} ({} of {}): {:#}",
+					config.url, delay, attempt, config.max_retries, e
+				);
+				std::thread::sleep(delay);
+				delay = (delay * 2).min(Duration::from_secs(60));
+			}
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Framing of the records of a batch, for protocols expecting one document per request
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Envelope {
+	pub(crate) prefix: &'static str,
+	pub(crate) separator: &'static str,
+	pub(crate) suffix: &'static str,
+}
+
+/// Batches records in the sink's format and POSTs them as one body.
+///
+/// The batch is bounded by `batch_size`. A POST is retried until it is acknowledged with a
+/// 2xx status, so the cursor, which is only persisted after flushing all sinks, never covers
+/// entries that weren't delivered. Once out of retries the batch is dropped, as the failed
+/// flush keeps the cursor before its entries.
+pub(crate) struct HttpSink {
+	config: HttpConfig,
+	agent: ureq::Agent,
+	envelope: Option<Envelope>,
+	batch: Vec<u8>,
+	entries: usize,
+	last_post: Instant,
+	/// Time a flush may take including its retries, see `write_timeout`
+	flush_limit: Option<Duration>,
+}
+
+impl HttpSink {
+	pub(crate) fn new(config: HttpConfig) -> Self {
+		let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+		Self {
+			config,
+			agent,
+			envelope: None,
+			batch: Vec::new(),
+			entries: 0,
+			last_post: Instant::now(),
+			flush_limit: None,
+		}
+	}
+
+	/// Stop retrying in time for a flush to end within `write_timeout`, as the watchdog
+	/// reports a flush taking longer as blocked.
+	pub(crate) fn with_write_timeout(mut self, write_timeout: Option<Duration>) -> Self {
+		self.flush_limit = write_timeout;
+		self
+	}
+
+	/// POST the records of a batch wrapped in the envelope, instead of concatenated.
+	pub(crate) fn with_envelope(config: HttpConfig, envelope: Envelope) -> Self {
+		Self {
+			envelope: Some(envelope),
+			..Self::new(config)
+		}
+	}
+
+	/// Add a record to the batch, and POST it if it is full or due.
+	pub(crate) fn write(&mut self, data: &[u8]) -> Result<()> {
+		match self.envelope {
+			Some(envelope) if self.entries > 0 => {
+				self.batch.extend_from_slice(envelope.separator.as_bytes())
+			}
+			_ => {}
+		}
+		self.batch.extend_from_slice(data);
+		self.entries += 1;
+		if self.entries >= self.config.batch_size.max(1)
+			|| self.last_post.elapsed() >= self.config.flush_interval
+		{
+			self.flush()?;
+		}
+
+		Ok(())
+	}
+
+	/// POST the buffered entries, if any.
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		self.flush_before(None)
+	}
+
+	/// `flush`, without retrying past `deadline`, see `shutdown_ack_timeout`. A POST started
+	/// before the deadline may still take up to `timeout`.
+	pub(crate) fn flush_before(&mut self, deadline: Option<Instant>) -> Result<()> {
+		if self.entries == 0 {
+			return Ok(());
+		}
+
+		let sent = send_with_retries(&self.config, self.flush_limit, deadline, || self.post());
+		if let Err(e) = sent {
+			// so the batch doesn't grow without bound, e.g. with `on_error: skip`
+			warn!(
+				"dropping {} entries not acknowledged by {}",
+				self.entries, self.config.url
+			);
+			self.batch.clear();
+			self.entries = 0;
+			self.last_post = Instant::now();
+			return Err(e);
+		}
+		debug!("posted {} entries to {}", self.entries, self.config.url);
+		self.batch.clear();
+		self.entries = 0;
+		self.last_post = Instant::now();
+
+		Ok(())
+	}
+
+	/// Whether entries are buffered that still have to be POSTed.
+	pub(crate) fn has_pending(&self) -> bool {
+		self.entries > 0
+	}
+
+	fn post(&self) -> Result<()> {
+		let mut request = self
+			.agent
+			.post(&self.config.url)
+			.set("Content-Type", &self.config.content_type);
+		for (name, value) in &self.config.headers {
+			request = request.set(name, value);
+		}
+		let framed;
+		let body = match self.envelope {
+			Some(envelope) => {
+				framed = [
+					envelope.prefix.as_bytes(),
+					&self.batch,
+					envelope.suffix.as_bytes(),
+				]
+				.concat();
+				&framed
+			}
+			None => &self.batch,
+		};
+		match request.send_bytes(body) {
+			Ok(_) => Ok(()),
+			Err(ureq::Error::Status(status, response)) => bail!(
+				"POST to {} answered with {} {}",
+				self.config.url,
+				status,
+				response.status_text()
+			),
+			Err(e) => Err(anyhow::Error::new(e).context(format!("POST to {}", self.config.url))),
+		}
+	}
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::manifest;
+use crate::trailer;
+use crate::writer::Record;
+
+/// Suffix of the index next to each output file
+const INDEX_SUFFIX: &str = ".idx";
+
+/// Sidecar files mapping byte offsets in the output files to the cursor and time of the entry
+/// starting there.
+///
+/// The files are rotated inside log-writer, so the file written to is taken to be the most
+/// recently modified one with the writer's prefix. A line `<offset>\t<epoch_us>\t<cursor>` is
+/// appended to `<file>.idx` every `interval`, so each file has its own index.
+pub(crate) struct Index {
+	interval: Duration,
+	/// Time of the last index line, per directory and prefix of a writer
+	last: HashMap<(PathBuf, String), Instant>,
+}
+
+impl Index {
+	pub(crate) fn new(interval: Duration) -> Self {
+		Self {
+			interval,
+			last: HashMap::new(),
+		}
+	}
+
+	pub(crate) fn due(&self, dir: &Path, prefix: &str) -> bool {
+		match self.last.get(&(dir.to_path_buf(), prefix.to_string())) {
+			Some(last) => last.elapsed() >= self.interval,
+			None => true,
+		}
+	}
+
+	/// Index the entry just written and flushed as the last `len` bytes of the current file
+	/// of the writer at `dir` and `prefix`.
+	pub(crate) fn record(
+		&mut self,
+		dir: &Path,
+		prefix: &str,
+		record: &Record<'_>,
+		len: usize,
+	) -> Result<()> {
+		self.last
+			.insert((dir.to_path_buf(), prefix.to_string()), Instant::now());
+		let (file, size) = match current_file(dir, prefix)? {
+			Some(current) => current,
+			None => return Ok(()),
+		};
+
+		let cursor = record.entry.get_field("__CURSOR").unwrap_or("");
+		let offset = size.saturating_sub(len as u64);
+		let mut path = file.into_os_string();
+		path.push(INDEX_SUFFIX);
+		let path = PathBuf::from(path);
+		let mut index = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&path)
+			.with_context(|| format!("Opening index {}", path.display()))?;
+		writeln!(index, "{}\t{}\t{}", offset, record.received_us, cursor)
+			.with_context(|| format!("Writing index {}", path.display()))?;
+		trace!("indexed offset {} in {}", offset, path.display());
+
+		Ok(())
+	}
+}
+
+/// The file each writer of an output writes to, looked up again once it may have been rotated
+/// inside log_writer by `max_file_size`.
+pub(crate) struct CurrentFiles {
+	max_file_size: u64,
+	/// File and its size, per directory and prefix of a writer
+	files: HashMap<(PathBuf, String), (Option<PathBuf>, u64)>,
+}
+
+impl CurrentFiles {
+	pub(crate) fn new(max_file_size: u64) -> Self {
+		Self {
+			max_file_size,
+			files: HashMap::new(),
+		}
+	}
+
+	/// Record `len` bytes written to the writer at `location`, and whether they went into
+	/// another file than the previous ones, as it was opened or rotated. `flush` is called
+	/// before looking up the file, so that it exists and has its size.
+	pub(crate) fn written(
+		&mut self,
+		location: &(PathBuf, String),
+		len: usize,
+		flush: impl FnOnce() -> Result<()>,
+	) -> Result<bool> {
+		let previous = match self.files.get_mut(location) {
+			Some((path, size)) => {
+				*size += len as u64;
+				if *size < self.max_file_size {
+					return Ok(false);
+				}
+				path.clone()
+			}
+			None => None,
+		};
+
+		flush()?;
+		let (dir, prefix) = location;
+		let (path, size) = match current_file(dir, prefix)? {
+			Some((path, size)) => (Some(path), size),
+			None => (None, 0),
+		};
+		let changed = !self.files.contains_key(location) || path != previous;
+		self.files.insert(location.clone(), (path, size));
+
+		Ok(changed)
+	}
+
+	/// The file the writer at `location` writes to, if known.
+	pub(crate) fn path(&self, location: &(PathBuf, String)) -> Option<&Path> {
+		self.files.get(location)?.0.as_deref()
+	}
+
+	/// Forget the file of the writer at `location`, which was closed.
+	pub(crate) fn closed(&mut self, location: &(PathBuf, String)) {
+		self.files.remove(location);
+	}
+}
+
+/// The most recently modified file of the writer with `prefix` in `dir` and its size, not
+/// counting indexes, trailers and manifests.
+pub(crate) fn current_file(dir: &Path, prefix: &str) -> Result<Option<(PathBuf, u64)>> {
+	let mut current: Option<(PathBuf, u64, SystemTime)> = None;
+	for entry in fs::read_dir(dir).with_context(|| format!("Reading {}", dir.display()))? {
+		let entry = entry.with_context(|| format!("Reading {}", dir.display()))?;
+		let name = entry.file_name();
+		let name = name.to_string_lossy();
+		if !is_file_of(&name, prefix)
+			|| name.ends_with(INDEX_SUFFIX)
+			|| name.ends_with(trailer::SIDECAR_SUFFIX)
+			|| name.ends_with(manifest::MANIFEST_SUFFIX)
+		{
+			continue;
+		}
+		let metadata = entry
+			.metadata()
+			.with_context(|| format!("Reading metadata of {}", entry.path().display()))?;
+		if !metadata.is_file() {
+			continue;
+		}
+		let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+		if current.as_ref().map_or(true, |(_, _, m)| modified >= *m) {
+			current = Some((entry.path(), metadata.len(), modified));
+		}
+	}
+
+	Ok(current.map(|(path, size, _)| (path, size)))
+}
+
+/// Whether `name` is a file of the writer with `prefix`: log_writer names its files with the
+/// prefix followed by the time they were started at, starting with the date. This tells them
+/// apart from those of writers whose prefix starts with `prefix`, e.g. of shards or boots.
+fn is_file_of(name: &str, prefix: &str) -> bool {
+	let stamp = match name.strip_prefix(prefix) {
+		Some(stamp) => stamp,
+		None => return false,
+	};
+	// the date, with or without dashes
+	let date_len = stamp
+		.find(|c: char| !c.is_ascii_digit() && c != '-')
+		.unwrap_or(stamp.len());
+	let (date, rest) = stamp.split_at(date_len);
+	date.chars().filter(char::is_ascii_digit).count() >= 8
+		&& !rest.starts_with(|c: char| c.is_ascii_alphanumeric() && c != 'T')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn file_of_matches_the_exact_prefix() {
+		assert!(is_file_of("journal-2026-10-14T08:15:00Z.log", "journal-"));
+		assert!(is_file_of("journal-20261014T081500Z.log", "journal-"));
+		// other writers' files sharing the prefix
+		assert!(!is_file_of(
+			"journal-shard0-2026-10-14T08:15:00Z.log",
+			"journal-"
+		));
+		assert!(!is_file_of(
+			"journal-0123cdef-20261014T081500Z+2026-10-14T08:15:00Z.log",
+			"journal-"
+		));
+		assert!(!is_file_of(
+			"journal-01234567cdef89ab0123456789abcdef-20261014T081500Z+2026-10-14T08:15:00Z.log",
+			"journal-"
+		));
+		assert!(is_file_of(
+			"journal-foo+2026-10-14T08:15:00Z.log",
+			"journal-foo+"
+		));
+		assert!(!is_file_of(
+			"journal-foo-bar+2026-10-14T08:15:00Z.log",
+			"journal-foo+"
+		));
+		assert!(!is_file_of("journal-", "journal-"));
+		assert!(!is_file_of("other-2026-10-14T08:15:00Z.log", "journal-"));
+	}
+}
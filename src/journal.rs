@@ -0,0 +1,897 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use journald::reader::{JournalFiles, JournalReader, JournalReaderConfig, JournalSeek};
+use log::*;
+use nix::libc;
+
+use crate::config::{Config, StartPosition};
+
+/// Values of the journal's `_TRANSPORT` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+	Audit,
+	Driver,
+	Syslog,
+	Journal,
+	Stdout,
+	Kernel,
+}
+
+impl Transport {
+	pub(crate) fn as_str(&self) -> &'static str {
+		match self {
+			Transport::Audit => "audit",
+			Transport::Driver => "driver",
+			Transport::Syslog => "syslog",
+			Transport::Journal => "journal",
+			Transport::Stdout => "stdout",
+			Transport::Kernel => "kernel",
+		}
+	}
+}
+
+/// How an error returned while iterating over the journal affects the reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IterErrorKind {
+	/// Only the current entry could not be read, the reader can continue with the next one
+	Entry,
+	/// The reader itself failed and has to be reopened
+	Reader,
+	/// The journal files or the reader's descriptors went away, e.g. as systemd-journald
+	/// restarted, so the reader has to be reopened
+	Restart,
+}
+
+pub(crate) fn classify_error(error: &(dyn std::error::Error + 'static)) -> IterErrorKind {
+	let mut source = Some(error);
+	while let Some(e) = source {
+		if let Some(e) = e.downcast_ref::<std::io::Error>() {
+			return match e.raw_os_error() {
+				// corrupt or oversized entry data
+				Some(libc::EBADMSG)
+				| Some(libc::ENOBUFS)
+				| Some(libc::E2BIG)
+				| Some(libc::EPROTONOSUPPORT) => IterErrorKind::Entry,
+				Some(libc::EBADF) | Some(libc::ESTALE) | Some(libc::ENOENT) | Some(libc::EIDRM) => {
+					IterErrorKind::Restart
+				}
+				_ => IterErrorKind::Reader,
+			};
+		}
+		source = e.source();
+	}
+
+	// not caused by a syscall, e.g. entry data which could not be decoded
+	IterErrorKind::Entry
+}
+
+/// Delay before reopening a failed reader, doubling with each consecutive failure up to
+/// `max`, plus a random jitter of up to `jitter` so a fleet doesn't reconnect at once.
+///
+/// After `max_reconnect_attempts` consecutive failures without reading an entry, the failure
+/// is considered permanent.
+pub(crate) struct ReconnectBackoff {
+	next: Duration,
+	max: Duration,
+	jitter: Option<Duration>,
+	failures: u32,
+	max_failures: Option<u32>,
+}
+
+impl ReconnectBackoff {
+	const INITIAL: Duration = Duration::from_secs(1);
+
+	pub(crate) fn new(config: &Config) -> Self {
+		Self {
+			next: Self::INITIAL,
+			max: config.reconnect_max_delay.max(Self::INITIAL),
+			jitter: config.reconnect_jitter,
+			failures: 0,
+			max_failures: config.max_reconnect_attempts,
+		}
+	}
+
+	/// The delay before the next attempt, or an error once out of attempts.
+	pub(crate) fn delay(&mut self) -> Result<Duration> {
+		self.failures += 1;
+		if let Some(max_failures) = self.max_failures {
+			if self.failures > max_failures {
+				bail!(
+					"Giving up after {} consecutive reconnects without reading an entry",
+					max_failures
+				);
+			}
+		}
+
+		let mut delay = self.next;
+		self.next = (self.next * 2).min(self.max);
+		if let Some(jitter) = self.jitter {
+			let random = RandomState::new().build_hasher().finish();
+			delay += Duration::from_micros(random % (jitter.as_micros() as u64 + 1));
+		}
+		Ok(delay)
+	}
+
+	/// Start over with the initial delay, once reading succeeded.
+	pub(crate) fn reset(&mut self) {
+		self.next = Self::INITIAL;
+		self.failures = 0;
+	}
+}
+
+/// Where an opened reader has been positioned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Position {
+	/// Right after the entry of the persisted cursor
+	Cursor,
+	/// Before the entry of the persisted cursor, see `reread_entries`
+	Reread {
+		/// Entries up to the persisted cursor have already been forwarded
+		cursor: Seqnum,
+	},
+	/// Skipped ahead of the persisted cursor, see `max_backfill`
+	Backfill {
+		/// Sequence number of the persisted cursor, to count the entries skipped
+		cursor: Option<Seqnum>,
+	},
+	/// At the start position, as there is no cursor file
+	Start,
+	/// At the start position, as the persisted cursor could not be seeked to
+	Fallback {
+		/// Realtime timestamp of the entry referenced by the cursor, if known
+		cursor_timestamp_us: Option<i64>,
+	},
+}
+
+/// Open the reader at the cursor in `cursor_file`, or at `memory_cursor` if the cursor is
+/// only kept in memory, see `cursor_required`.
+pub(crate) fn open_reader(
+	config: &Config,
+	memory_cursor: Option<&str>,
+) -> Result<(JournalReader, Position)> {
+	let reader = open_filtered(config)?;
+
+	find_cursor(&config.cursor_file, memory_cursor, reader, config)
+}
+
+/// Sequence number of an entry within the journal files of one seqnum id, from the `s=`
+/// and `i=` fields of its cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Seqnum {
+	id: String,
+	num: u64,
+}
+
+impl Seqnum {
+	pub(crate) fn of_cursor(cursor: &str) -> Option<Self> {
+		let mut id = None;
+		let mut num = None;
+		for field in cursor.trim().split(';') {
+			if let Some(value) = field.strip_prefix("s=") {
+				id = Some(value.to_string());
+			} else if let Some(value) = field.strip_prefix("i=") {
+				num = u64::from_str_radix(value, 16).ok();
+			}
+		}
+
+		Some(Self { id: id?, num: num? })
+	}
+
+	pub(crate) fn id(&self) -> &str {
+		&self.id
+	}
+
+	pub(crate) fn num(&self) -> u64 {
+		self.num
+	}
+
+	/// Number of sequence numbers skipped between this one and `next`, if any. Sequence
+	/// numbers of another seqnum id, or not following this one, can't be compared.
+	pub(crate) fn gap_to(&self, next: &Seqnum) -> Option<u64> {
+		if next.id != self.id || next.num <= self.num + 1 {
+			return None;
+		}
+
+		Some(next.num - self.num - 1)
+	}
+
+	/// Whether the entry comes before or is the one of this sequence number. Entries of
+	/// other journal files, with another seqnum id, are never covered.
+	pub(crate) fn covers(&self, entry: &journald::JournalEntry) -> bool {
+		match entry.get_field("__CURSOR").and_then(Seqnum::of_cursor) {
+			Some(seqnum) => seqnum.id == self.id && seqnum.num <= self.num,
+			None => false,
+		}
+	}
+}
+
+/// Cursor of the newest entry matching the configured filters, if any.
+pub(crate) fn tail_cursor(config: &Config) -> Result<Option<String>> {
+	let mut reader = open_filtered(config)?;
+	reader
+		.seek(JournalSeek::Tail)
+		.context("Seeking to journald tail")?;
+	let entry = reader
+		.previous_entry()
+		.context("Getting previous journald entry")?;
+
+	Ok(entry.and_then(|entry| entry.get_field("__CURSOR").map(str::to_string)))
+}
+
+/// Fail if the newest entry matching the configured filters misses any of `required_fields`.
+pub(crate) fn check_required_fields(config: &Config) -> Result<()> {
+	let mut reader = open_filtered(config)?;
+	reader
+		.seek(JournalSeek::Tail)
+		.context("Seeking to journald tail")?;
+	match reader
+		.previous_entry()
+		.context("Getting previous journald entry")?
+	{
+		Some(entry) => crate::config::check_required_fields(&entry, config)
+			.context("Checking required_fields against the newest entry"),
+		None => Ok(()),
+	}
+}
+
+/// Open the journal, with matches for all configured filters.
+fn open_filtered(config: &Config) -> Result<JournalReader> {
+	let mut reader = open_unfiltered(config.only_local)?;
+
+	// first, so that its conjunction applies the matches below to all of its groups
+	if let Some(expression) = &config.match_expression {
+		expression.apply(&mut reader)?;
+	}
+	if let Some(expression) = &config.shard_match {
+		expression.apply(&mut reader)?;
+	}
+
+	if config.current_boot_only {
+		let boot_id = current_boot_id()?;
+		debug!("only reading entries of boot {}", boot_id);
+		reader
+			.add_filter(&format!("_BOOT_ID={}", boot_id))
+			.context("Adding boot id match")?;
+	}
+
+	if let Some(boot_id) = &config.boot_id {
+		reader
+			.add_filter(&format!("_BOOT_ID={}", boot_id))
+			.context("Adding boot_id match")?;
+	}
+
+	for transport in &config.transports {
+		reader
+			.add_filter(&format!("_TRANSPORT={}", transport.as_str()))
+			.context("Adding transport match")?;
+	}
+
+	for uid in &config.uids {
+		reader
+			.add_filter(&format!("_UID={}", uid))
+			.context("Adding uid match")?;
+	}
+
+	for gid in &config.gids {
+		reader
+			.add_filter(&format!("_GID={}", gid))
+			.context("Adding gid match")?;
+	}
+
+	Ok(reader)
+}
+
+/// The filters an entry passed, for `annotate_match`: e.g. `match_expression[1] transport=kernel`
+/// for the second group of `match_expression`, or `all` without any filters.
+pub(crate) fn matched_filters(entry: &journald::JournalEntry, config: &Config) -> String {
+	let mut rules = Vec::new();
+	if let Some(expression) = &config.match_expression {
+		if let Some(group) = expression.matching_group(entry) {
+			rules.push(format!("match_expression[{}]", group));
+		}
+	}
+	if config.current_boot_only {
+		rules.push("current_boot_only".to_string());
+	}
+	if config.boot_id.is_some() {
+		rules.push("boot_id".to_string());
+	}
+	for (field, name, configured) in &[
+		("_TRANSPORT", "transport", !config.transports.is_empty()),
+		("_UID", "uid", !config.uids.is_empty()),
+		("_GID", "gid", !config.gids.is_empty()),
+	] {
+		if *configured {
+			let value = entry.get_field(field).unwrap_or("");
+			rules.push(format!("{}={}", name, value));
+		}
+	}
+	if let Some(shard) = config.shard {
+		rules.push(format!("shard={}", shard));
+	}
+	if !config.change_only_fields.is_empty() {
+		rules.push("change_only_fields".to_string());
+	}
+
+	if rules.is_empty() {
+		"all".to_string()
+	} else {
+		rules.join(" ")
+	}
+}
+
+/// Resolve a symlinked cursor file if `follow_cursor_symlink` is set, create the cursor
+/// directory and check that it's writable, to fail early with a clear error.
+pub(crate) fn prepare_cursor_file(config: &mut Config) -> Result<()> {
+	let path = &config.cursor_file;
+	if is_symlink(path) {
+		if config.follow_cursor_symlink {
+			let target = resolve_symlinks(path)?;
+			debug!(
+				"cursor file {} links to {}",
+				path.display(),
+				target.display()
+			);
+			config.cursor_file = target;
+		} else {
+			info!(
+				"cursor file {} is a symlink, which will be replaced by the cursor",
+				path.display()
+			);
+		}
+	}
+
+	if config.cursor_readonly {
+		return Ok(());
+	}
+	match prepare_cursor_dir(&config.cursor_file) {
+		Err(e) if !config.cursor_required => {
+			warn!(
+				"not persisting the cursor, as cursor_required is false: {:#}",
+				e
+			);
+			config.cursor_readonly = true;
+			config.cursor_in_memory = true;
+			Ok(())
+		}
+		result => result,
+	}
+}
+
+fn prepare_cursor_dir(cursor_file: &Path) -> Result<()> {
+	let dir = match cursor_file.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	fs::create_dir_all(dir)
+		.with_context(|| format!("Creating cursor directory {}", dir.display()))?;
+	nix::unistd::access(dir, nix::unistd::AccessFlags::W_OK).with_context(|| {
+		format!(
+			"Cursor directory {} is not writable, e.g. on a read-only filesystem",
+			dir.display()
+		)
+	})?;
+
+	Ok(())
+}
+
+/// Replace `boot_offset` with the `boot_id` of that boot, resolved once at startup so that
+/// a reboot meanwhile doesn't change the boot read.
+pub(crate) fn resolve_boot_offset(config: &mut Config) -> Result<()> {
+	if config.boot_id.is_some() && config.current_boot_only {
+		// both match _BOOT_ID, which the journal would take as alternatives
+		bail!("boot_id can't be combined with current_boot_only");
+	}
+	let offset = match config.boot_offset {
+		Some(offset) => offset,
+		None => return Ok(()),
+	};
+	if config.boot_id.is_some() || config.current_boot_only {
+		bail!("boot_offset can't be combined with boot_id or current_boot_only");
+	}
+
+	let boot_id = boot_id_at(offset, config.only_local)?;
+	info!("boot offset {} is boot {}", offset, boot_id);
+	config.boot_id = Some(boot_id);
+
+	Ok(())
+}
+
+/// The boot at `offset` as counted by `journalctl -b`: 0 is the newest boot in the journal,
+/// -1 the one before, and 1 the oldest one.
+///
+/// Boots are found by seeking from the first or last entry of a boot to the entry right
+/// before or after it.
+fn boot_id_at(offset: i32, only_local: bool) -> Result<String> {
+	let mut reader = open_unfiltered(only_local)?;
+	let missing = || anyhow::anyhow!("There is no boot at boot_offset {}", offset);
+	let mut entry = if offset <= 0 {
+		reader
+			.seek(JournalSeek::Tail)
+			.context("Seeking to journald tail")?;
+		reader
+			.previous_entry()
+			.context("Getting previous journald entry")?
+	} else {
+		reader
+			.seek(JournalSeek::Head)
+			.context("Seeking to journald head")?;
+		reader.next_entry().context("Getting next journald entry")?
+	}
+	.ok_or_else(missing)?;
+
+	let steps = if offset <= 0 { -offset } else { offset - 1 };
+	for _ in 0..steps {
+		let boot_id = entry_boot_id(&entry)?;
+		// the first or last entry of the boot, to step over its boundary from
+		let mut boot = open_unfiltered(only_local)?;
+		boot.add_filter(&format!("_BOOT_ID={}", boot_id))
+			.context("Adding boot id match")?;
+		let edge = if offset <= 0 {
+			boot.seek(JournalSeek::Head)
+				.context("Seeking to journald head")?;
+			boot.next_entry().context("Getting next journald entry")?
+		} else {
+			boot.seek(JournalSeek::Tail)
+				.context("Seeking to journald tail")?;
+			boot.previous_entry()
+				.context("Getting previous journald entry")?
+		};
+		let cursor = edge
+			.as_ref()
+			.and_then(|edge| edge.get_field("__CURSOR"))
+			.with_context(|| format!("Finding the boundary of boot {}", boot_id))?;
+
+		reader
+			.seek(JournalSeek::Cursor(cursor.to_string()))
+			.context("Seeking to boot boundary")?;
+		// the first step returns the entry at the cursor itself
+		entry = if offset <= 0 {
+			reader
+				.previous_entry()
+				.and_then(|_| reader.previous_entry())
+				.context("Getting previous journald entry")?
+		} else {
+			reader
+				.next_entry()
+				.and_then(|_| reader.next_entry())
+				.context("Getting next journald entry")?
+		}
+		.ok_or_else(missing)?;
+	}
+
+	entry_boot_id(&entry)
+}
+
+fn entry_boot_id(entry: &journald::JournalEntry) -> Result<String> {
+	entry
+		.get_field("_BOOT_ID")
+		.map(str::to_string)
+		.context("Journal entry without _BOOT_ID")
+}
+
+fn open_unfiltered(only_local: bool) -> Result<JournalReader> {
+	let reader_config = JournalReaderConfig {
+		files: JournalFiles::All,
+		only_volatile: false,
+		only_local,
+	};
+	JournalReader::open(&reader_config).context("Opening journal")
+}
+
+/// Read the id of this machine if remote entries are forwarded, to tell them apart by their
+/// `_MACHINE_ID`.
+pub(crate) fn resolve_machine_id(config: &mut Config) -> Result<()> {
+	if config.only_local {
+		return Ok(());
+	}
+	let machine_id = fs::read_to_string("/etc/machine-id").context("Reading machine id")?;
+	config.machine_id = Some(machine_id.trim().to_string());
+
+	Ok(())
+}
+
+fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink())
+}
+
+/// Follow a chain of symlinks to the final target, which may not exist yet.
+fn resolve_symlinks(path: &Path) -> Result<PathBuf> {
+	// the limit of the kernel, for a loop of links
+	const MAX_LINKS: usize = 40;
+	let mut path = path.to_path_buf();
+	for _ in 0..MAX_LINKS {
+		if !is_symlink(&path) {
+			return Ok(path);
+		}
+		let target = fs::read_link(&path)
+			.with_context(|| format!("Reading cursor symlink {}", path.display()))?;
+		// relative targets are relative to the directory of the link
+		path = match path.parent() {
+			Some(dir) => dir.join(target),
+			None => target,
+		};
+	}
+
+	bail!(
+		"More than {} symlinks resolving the cursor file, ending at {}",
+		MAX_LINKS,
+		path.display()
+	)
+}
+
+/// Boot id of the running system, formatted like the journal's `_BOOT_ID` field.
+fn current_boot_id() -> Result<String> {
+	let boot_id =
+		fs::read_to_string("/proc/sys/kernel/random/boot_id").context("Reading current boot id")?;
+	Ok(boot_id.trim().replace('-', ""))
+}
+
+fn find_cursor<P: AsRef<Path>>(
+	path: P,
+	memory_cursor: Option<&str>,
+	mut reader: JournalReader,
+	config: &Config,
+) -> Result<(JournalReader, Position)> {
+	let start = config.start_position;
+	let cursor = match memory_cursor {
+		Some(cursor) => {
+			debug!("resuming from cursor kept in memory: {}", cursor);
+			cursor.to_string()
+		}
+		None => {
+			if let Some(path) = path.as_ref().parent() {
+				if !path.exists() && !config.cursor_in_memory {
+					trace!("creating cursor directory");
+					std::fs::create_dir_all(path)
+						.with_context(|| format!("Creating cursor directory {}", path.display()))?;
+				}
+			}
+
+			if !path.as_ref().exists() {
+				debug!("no cursor file, seeking to {:?}", start);
+				seek_start(&mut reader, start)?;
+				return Ok((reader, Position::Start));
+			}
+
+			let cursor = String::from_utf8_lossy(
+				&std::fs::read(path.as_ref()).context("reading old cursor")?,
+			)
+			.into_owned();
+			debug!("recovered cursor: {}", cursor);
+			crate::writer::remember_cursor_file(path.as_ref());
+			cursor
+		}
+	};
+	let cursor_timestamp_us = cursor_timestamp(&cursor);
+	let seqnum = Seqnum::of_cursor(&cursor);
+	if !seek_cursor(&mut reader, cursor, config.reposition_after_seek, start)? {
+		return Ok((
+			reader,
+			Position::Fallback {
+				cursor_timestamp_us,
+			},
+		));
+	}
+
+	if let (Some(max_backfill), Some(cursor_us)) = (config.max_backfill, cursor_timestamp_us) {
+		let earliest_us = chrono::Utc::now().timestamp_micros() - max_backfill.as_micros() as i64;
+		if cursor_us < earliest_us {
+			warn!(
+				"cursor is more than {:?} behind, skipping entries from {} to {}",
+				max_backfill,
+				format_us(cursor_us),
+				format_us(earliest_us)
+			);
+			reader
+				.seek(JournalSeek::ClockRealtime {
+					time: earliest_us as u64,
+				})
+				.context("Seeking to max_backfill")?;
+			return Ok((reader, Position::Backfill { cursor: seqnum }));
+		}
+	}
+
+	let cursor = match seqnum {
+		Some(cursor) if config.reread_entries > 0 || config.reread_window.is_some() => cursor,
+		_ => return Ok((reader, Position::Cursor)),
+	};
+	match (config.reread_window, cursor_timestamp_us) {
+		(Some(window), Some(cursor_us)) => {
+			debug!(
+				"re-reading entries received within {:?} before the cursor",
+				window
+			);
+			reader
+				.seek(JournalSeek::ClockRealtime {
+					time: (cursor_us - window.as_micros() as i64).max(0) as u64,
+				})
+				.context("Seeking to reread_window")?;
+		}
+		_ => {
+			debug!(
+				"re-reading {} entries before the cursor",
+				config.reread_entries
+			);
+			for _ in 0..config.reread_entries {
+				let entry = reader
+					.previous_entry()
+					.context("Getting previous journald entry")?;
+				if entry.is_none() {
+					break;
+				}
+			}
+		}
+	}
+
+	Ok((reader, Position::Reread { cursor }))
+}
+
+fn format_us(timestamp_us: i64) -> String {
+	chrono::NaiveDateTime::from_timestamp(timestamp_us.div_euclid(1_000_000), 0).to_string()
+}
+
+/// Realtime timestamp of the entry a cursor references, from its `t=` field.
+pub(crate) fn cursor_timestamp(cursor: &str) -> Option<i64> {
+	cursor
+		.trim()
+		.split(';')
+		.find_map(|field| field.strip_prefix("t="))
+		.and_then(|t| i64::from_str_radix(t, 16).ok())
+}
+
+/// Seek to the first entry received at or after `timestamp_us`.
+pub(crate) fn seek_realtime(reader: &mut JournalReader, timestamp_us: i64) -> Result<()> {
+	reader
+		.seek(JournalSeek::ClockRealtime {
+			time: timestamp_us.max(0) as u64,
+		})
+		.context("Seeking to start time")
+}
+
+/// Positioning of a journal reader, so seeking can be tested without a journal.
+pub(crate) trait Seekable {
+	fn seek_to(&mut self, seek: JournalSeek) -> Result<()>;
+
+	/// Step onto the previous entry, returning whether there is one.
+	fn step_back(&mut self) -> Result<bool>;
+}
+
+impl Seekable for JournalReader {
+	fn seek_to(&mut self, seek: JournalSeek) -> Result<()> {
+		Ok(self.seek(seek)?)
+	}
+
+	fn step_back(&mut self) -> Result<bool> {
+		Ok(self.previous_entry()?.is_some())
+	}
+}
+
+/// Seek to `cursor`, with `reposition` onto its entry so reading continues after it, or to
+/// `start` if that fails. Returns whether the reader is at the cursor.
+fn seek_cursor<R: Seekable>(
+	reader: &mut R,
+	cursor: String,
+	reposition: bool,
+	start: StartPosition,
+) -> Result<bool> {
+	let seeked = reader.seek_to(JournalSeek::Cursor(cursor)).and_then(|_| {
+		if !reposition {
+			// the next entry read is the one of the cursor, forwarded again
+			return Ok(());
+		}
+		if !reader.step_back()? {
+			debug!("cursor entry not found after seeking, reading forward from the seek point");
+		}
+		Ok(())
+	});
+	match seeked {
+		Ok(()) => Ok(true),
+		Err(e) => {
+			// the cursor may reference a journal file that has been vacuumed in the meantime
+			warn!(
+				"failed to seek to recovered cursor, seeking to {:?} instead: {:#}",
+				start, e
+			);
+			seek_start(reader, start)?;
+			Ok(false)
+		}
+	}
+}
+
+fn seek_start<R: Seekable>(reader: &mut R, start: StartPosition) -> Result<()> {
+	match start {
+		StartPosition::Head => {
+			reader
+				.seek_to(JournalSeek::Head)
+				.context("Seeking to journald head")?;
+		}
+		StartPosition::Tail => {
+			reader
+				.seek_to(JournalSeek::Tail)
+				.context("Seeking to journald tail")?;
+			// step onto the last entry, so only newer ones are read
+			let last = reader
+				.step_back()
+				.context("Getting previous journald entry")?;
+			if !last {
+				debug!("journal is empty, reading entries from the first one written");
+			}
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A journal of `len` entries, numbered from 0, with the cursors `i=<n>`.
+	struct FakeJournal {
+		len: usize,
+		/// Entry read next
+		next: usize,
+		/// Entry `step_back` steps onto after the last seek
+		landing: Option<usize>,
+		seeks: Vec<&'static str>,
+	}
+
+	impl FakeJournal {
+		fn new(len: usize) -> Self {
+			Self {
+				len,
+				next: 0,
+				landing: None,
+				seeks: Vec::new(),
+			}
+		}
+	}
+
+	impl Seekable for FakeJournal {
+		fn seek_to(&mut self, seek: JournalSeek) -> Result<()> {
+			match seek {
+				JournalSeek::Head => {
+					self.seeks.push("head");
+					self.next = 0;
+					self.landing = None;
+				}
+				JournalSeek::Tail => {
+					self.seeks.push("tail");
+					self.next = self.len;
+					self.landing = self.len.checked_sub(1);
+				}
+				JournalSeek::Cursor(cursor) => {
+					self.seeks.push("cursor");
+					match cursor.strip_prefix("i=").and_then(|n| n.parse().ok()) {
+						Some(n) if n < self.len => {
+							self.next = n;
+							self.landing = Some(n);
+						}
+						_ => bail!("no entry with cursor {}", cursor),
+					}
+				}
+				seek => bail!("unexpected seek {:?}", seek),
+			}
+			Ok(())
+		}
+
+		fn step_back(&mut self) -> Result<bool> {
+			match self.landing.take() {
+				Some(entry) => {
+					self.next = entry + 1;
+					Ok(true)
+				}
+				None => Ok(false),
+			}
+		}
+	}
+
+	#[test]
+	fn seek_cursor_continues_after_its_entry() {
+		let mut journal = FakeJournal::new(3);
+		assert!(seek_cursor(&mut journal, "i=1".to_string(), true, StartPosition::Tail).unwrap());
+		assert_eq!(journal.next, 2);
+		assert_eq!(journal.seeks, ["cursor"]);
+	}
+
+	#[test]
+	fn unknown_cursor_falls_back_to_the_start_position() {
+		let mut journal = FakeJournal::new(3);
+		assert!(!seek_cursor(&mut journal, "i=7".to_string(), true, StartPosition::Head).unwrap());
+		assert_eq!(journal.next, 0);
+		assert_eq!(journal.seeks, ["cursor", "head"]);
+
+		let mut journal = FakeJournal::new(3);
+		assert!(!seek_cursor(&mut journal, "i=7".to_string(), true, StartPosition::Tail).unwrap());
+		assert_eq!(journal.next, 3);
+		assert_eq!(journal.seeks, ["cursor", "tail"]);
+	}
+
+	#[test]
+	fn seek_to_head_reads_the_first_entry() {
+		let mut journal = FakeJournal::new(3);
+		seek_start(&mut journal, StartPosition::Head).unwrap();
+		assert_eq!(journal.next, 0);
+	}
+
+	#[test]
+	fn seek_to_tail_of_an_empty_journal() {
+		let mut journal = FakeJournal::new(0);
+		seek_start(&mut journal, StartPosition::Tail).unwrap();
+		assert_eq!(journal.next, 0);
+
+		let mut journal = FakeJournal::new(3);
+		seek_start(&mut journal, StartPosition::Tail).unwrap();
+		assert_eq!(journal.next, 3);
+	}
+
+	#[test]
+	fn seek_cursor_without_reposition_reads_its_entry_again() {
+		let mut journal = FakeJournal::new(3);
+		assert!(seek_cursor(&mut journal, "i=1".to_string(), false, StartPosition::Tail).unwrap());
+		assert_eq!(journal.next, 1);
+	}
+
+	#[test]
+	fn classify_iteration_errors() {
+		let os_error = |errno| std::io::Error::from_raw_os_error(errno);
+		assert_eq!(
+			classify_error(&os_error(libc::EBADMSG)),
+			IterErrorKind::Entry
+		);
+		assert_eq!(classify_error(&os_error(libc::E2BIG)), IterErrorKind::Entry);
+		assert_eq!(
+			classify_error(&os_error(libc::ESTALE)),
+			IterErrorKind::Restart
+		);
+		assert_eq!(
+			classify_error(&os_error(libc::EBADF)),
+			IterErrorKind::Restart
+		);
+		assert_eq!(classify_error(&os_error(libc::EIO)), IterErrorKind::Reader);
+		// without a syscall error, e.g. undecodable entry data
+		assert_eq!(classify_error(&std::fmt::Error), IterErrorKind::Entry);
+
+		let wrapped = anyhow::Error::new(os_error(libc::ENOENT)).context("Reading entry");
+		assert_eq!(classify_error(wrapped.as_ref()), IterErrorKind::Restart);
+	}
+
+	#[test]
+	fn seqnum_gaps_within_one_seqnum_id() {
+		let seqnum = |cursor| Seqnum::of_cursor(cursor).unwrap();
+		let first = seqnum("s=ab;i=10;b=cd;m=1;t=2;x=3");
+		assert_eq!(first.id(), "ab");
+		assert_eq!(first.num(), 0x10);
+		assert_eq!(first.gap_to(&seqnum("s=ab;i=11")), None);
+		assert_eq!(first.gap_to(&seqnum("s=ab;i=14")), Some(3));
+		// read again, or of another journal
+		assert_eq!(first.gap_to(&seqnum("s=ab;i=f")), None);
+		assert_eq!(first.gap_to(&seqnum("s=ef;i=14")), None);
+		assert!(Seqnum::of_cursor("b=cd;m=1").is_none());
+	}
+
+	#[test]
+	fn resolves_a_chain_of_symlinks() {
+		let dir = tempfile::tempdir().unwrap();
+		let target = dir.path().join("state").join("cursor");
+		std::os::unix::fs::symlink("state/cursor", dir.path().join("second")).unwrap();
+		std::os::unix::fs::symlink(dir.path().join("second"), dir.path().join("cursor")).unwrap();
+
+		// the final target doesn't exist before the first cursor is written
+		assert_eq!(
+			resolve_symlinks(&dir.path().join("cursor")).unwrap(),
+			target
+		);
+		assert_eq!(resolve_symlinks(&target).unwrap(), target);
+
+		std::os::unix::fs::symlink("loop", dir.path().join("loop")).unwrap();
+		assert!(resolve_symlinks(&dir.path().join("loop")).is_err());
+	}
+}
@@ -0,0 +1,105 @@
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::metrics;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LatencyConfig {
+	/// Time from reading an entry until it is written, and flushed if due, above which the
+	/// write is reported as slow
+	#[serde(with = "humantime_serde")]
+	pub threshold: Duration,
+	/// Program and arguments run once this many consecutive writes were slow, with the
+	/// latency of the last one in `JOURNALD_WRITER_LATENCY_MS`
+	#[serde(default)]
+	pub command: Vec<String>,
+	#[serde(default = "default_sustained")]
+	pub sustained: u32,
+	/// Minimum time between two runs of `command`
+	#[serde(default = "default_command_interval", with = "humantime_serde")]
+	pub command_interval: Duration,
+}
+
+fn default_sustained() -> u32 {
+	10
+}
+
+fn default_command_interval() -> Duration {
+	Duration::from_secs(300)
+}
+
+/// Measures the write latency of each entry, see `write_latency`.
+pub(crate) struct LatencyMonitor {
+	config: LatencyConfig,
+	/// Number of slow writes in a row
+	consecutive: u32,
+	last_command: Option<Instant>,
+}
+
+impl LatencyMonitor {
+	pub(crate) fn new(config: LatencyConfig) -> Self {
+		Self {
+			config,
+			consecutive: 0,
+			last_command: None,
+		}
+	}
+
+	/// Record an entry read at `read_at` as written now.
+	pub(crate) fn written(&mut self, read_at: Instant) {
+		let latency = read_at.elapsed();
+		if latency <= self.config.threshold {
+			self.consecutive = 0;
+			return;
+		}
+
+		metrics::SLOW_WRITES.inc();
+		self.consecutive += 1;
+		warn!(
+			"writing entry took {:?}, more than write_latency threshold {:?}",
+			latency, self.config.threshold
+		);
+		if self.consecutive >= self.config.sustained.max(1)
+			&& self
+				.last_command
+				.map_or(true, |last| last.elapsed() >= self.config.command_interval)
+		{
+			self.run_command(latency);
+		}
+	}
+
+	/// Start `command` without waiting for it, so it can't slow down forwarding further.
+	fn run_command(&mut self, latency: Duration) {
+		let (program, args) = match self.config.command.split_first() {
+			Some(command) => command,
+			None => return,
+		};
+		self.last_command = Some(Instant::now());
+		warn!(
+			"{} consecutive slow writes, running {}",
+			self.consecutive, program
+		);
+		let spawned = Command::new(program)
+			.args(args)
+			.env(
+				"JOURNALD_WRITER_LATENCY_MS",
+				latency.as_millis().to_string(),
+			)
+			.spawn();
+		match spawned {
+			// reaped on a thread, so it doesn't linger as zombie
+			Ok(mut child) => {
+				thread::spawn(move || {
+					if let Err(e) = child.wait() {
+						warn!("waiting for write_latency command failed: {}", e);
+					}
+				});
+			}
+			Err(e) => warn!("starting write_latency command {} failed: {}", program, e),
+		}
+	}
+}
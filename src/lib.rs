@@ -0,0 +1,2093 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use getopts::Options;
+use journald::JournalEntry;
+use log::*;
+use nix::sys::signal;
+use nix::sys::signal::{SigHandler, Signal};
+
+use config::{
+	ClockJumpPolicy, Config, CursorReplacedPolicy, Delivery, ErrorPolicy, FutureTimestampPolicy,
+	MissingCursorPolicy, MissingTimestampPolicy, OversizedEntryPolicy, RequiredFieldsCheck,
+	SighupAction, Tiebreaker,
+};
+use journal::IterErrorKind;
+
+mod archive;
+#[doc(hidden)]
+pub mod bench;
+mod budget;
+mod config;
+mod control;
+mod dead_letter;
+mod dedup;
+mod enrich;
+mod fifo;
+mod fingerprint;
+mod http;
+mod index;
+mod journal;
+mod latency;
+mod lifecycle;
+mod loopback;
+mod manifest;
+mod match_expr;
+mod metrics;
+mod otlp;
+#[cfg(feature = "otlp-grpc")]
+mod otlp_grpc;
+mod overflow;
+mod persister;
+mod pid_file;
+mod redact;
+mod reload;
+mod router;
+mod sink;
+mod status;
+mod syslog;
+mod template;
+mod trailer;
+mod transform;
+mod watchdog;
+mod writer;
+
+static EXIT_FLAG: AtomicBool = AtomicBool::new(false);
+/// Incremented for every checkpoint request, so that each shard notices it
+static CHECKPOINT_GENERATION: AtomicU64 = AtomicU64::new(0);
+/// SIGHUP reopens the output instead of exiting, see `on_sighup`
+static REOPEN_ON_SIGHUP: AtomicBool = AtomicBool::new(false);
+/// Incremented for every SIGHUP with `on_sighup: reopen_output`
+static REOPEN_GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Time to wait before polling the journal again once all entries were read
+const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Only sets flags polled by the forwarder, as anything else isn't async-signal-safe.
+extern "C" fn handle_sig(signal: nix::libc::c_int) {
+	match Signal::try_from(signal) {
+		Ok(Signal::SIGHUP) if REOPEN_ON_SIGHUP.load(Ordering::Relaxed) => {
+			REOPEN_GENERATION.fetch_add(1, Ordering::Relaxed);
+		}
+		Ok(Signal::SIGTERM) | Ok(Signal::SIGHUP) => EXIT_FLAG.store(true, Ordering::Relaxed),
+		// flush the writer and persist the cursor, without exiting
+		Ok(Signal::SIGUSR1) => {
+			CHECKPOINT_GENERATION.fetch_add(1, Ordering::Relaxed);
+		}
+		_ => {}
+	}
+	// TODO: flush fd from cookie file
+}
+
+fn print_usage(program: &str, opts: Options) {
+	let brief = format!(
+		"Usage: {} CONFIG [options]\n\nCONFIG is the path of a YAML, TOML or JSON file, or - to read it from stdin",
+		program
+	);
+	print!("{}", opts.usage(&brief));
+}
+
+/// Initialize the logger for the daemon's own output, which is plain text unless
+/// `JOURNALD_WRITER_LOG_FORMAT=json` is set.
+fn init_logger() {
+	let mut builder = env_logger::Builder::from_default_env();
+
+	if std::env::var("JOURNALD_WRITER_LOG_FORMAT").map_or(false, |format| format == "json") {
+		builder.format(|buf, record| {
+			let line = serde_json::json!({
+				"timestamp": chrono::Utc::now().to_rfc3339(),
+				"level": record.level().to_string(),
+				"target": record.target(),
+				"message": record.args().to_string(),
+			});
+			writeln!(buf, "{}", line)
+		});
+	}
+
+	builder.init();
+}
+
+/// Entry point of the `journald-writer` binary, kept in the library so that `benches/` can
+/// reach the formats.
+pub fn main() {
+	if let Err(e) = main_err() {
+		eprintln!("Error:");
+		eprintln!("{:?}", e);
+		std::process::exit(1);
+	}
+}
+
+fn main_err() -> Result<()> {
+	// init logger
+	init_logger();
+
+	// declare signal handler
+	let handler = SigHandler::Handler(handle_sig);
+	// SAFETY: result is not used. There as this function is a save ffi call.
+	unsafe { signal::signal(Signal::SIGTERM, handler) }
+		.context("Failed to install signal handler.")?;
+	// SAFETY: see above
+	unsafe { signal::signal(Signal::SIGUSR1, handler) }
+		.context("Failed to install SIGUSR1 handler.")?;
+	// SAFETY: see above
+	unsafe { signal::signal(Signal::SIGHUP, handler) }
+		.context("Failed to install SIGHUP handler.")?;
+
+	let args: Vec<String> = std::env::args().collect();
+	let program = args[0].clone();
+
+	let mut opts = Options::new();
+	opts.optflag("h", "help", "Display this help text and exit");
+	opts.optopt(
+		"",
+		"format",
+		"Syntax of CONFIG, detected from the file extension by default",
+		"yaml|toml|json",
+	);
+	opts.optflag(
+		"",
+		"print-config",
+		"Print the effective configuration, with defaults applied, and exit",
+	);
+	opts.optflag(
+		"",
+		"healthcheck",
+		"Exit successfully if the cursor file has been updated within healthcheck_max_cursor_age",
+	);
+	opts.optopt(
+		"",
+		"max-entries",
+		"Stop after forwarding N entries, persisting the cursor",
+		"N",
+	);
+	opts.optopt(
+		"",
+		"max-runtime",
+		"Stop after running this long, e.g. 10min, persisting the cursor",
+		"DURATION",
+	);
+	opts.optopt(
+		"",
+		"follow",
+		"With false, exit once all entries present at startup are forwarded, persisting the cursor",
+		"true|false",
+	);
+	opts.optopt(
+		"",
+		"start-time",
+		"Start at entries received at or after TIME (RFC 3339), unless the cursor is already past it",
+		"TIME",
+	);
+	opts.optopt(
+		"",
+		"end-time",
+		"Stop at the first entry received after TIME (RFC 3339), persisting the cursor",
+		"TIME",
+	);
+	opts.optflag(
+		"",
+		"dry-run",
+		"Write entries to stdout instead of the sinks, without writing the cursor",
+	);
+	opts.optflag(
+		"",
+		"reset-cursor",
+		"Delete the cursor file, so forwarding starts at start_position, and exit",
+	);
+	opts.optopt(
+		"",
+		"seek-to-cursor",
+		"Write CURSOR to the cursor file, so forwarding continues after it, and exit",
+		"CURSOR",
+	);
+	opts.optflag(
+		"",
+		"force",
+		"Confirm modifying the cursor file with --reset-cursor or --seek-to-cursor",
+	);
+
+	let matches = match opts.parse(&args[1..]) {
+		Ok(m) => m,
+		Err(f) => {
+			panic!("{}", f)
+		}
+	};
+
+	if matches.opt_present("h") {
+		print_usage(&program, opts);
+		return Ok(());
+	}
+
+	let config_path = if !matches.free.is_empty() {
+		matches.free[0].clone()
+	} else {
+		print_usage(&program, opts);
+		return Ok(());
+	};
+	info!("reading config file {}", config_path);
+
+	let format = match matches.opt_str("format") {
+		Some(format) => Some(config::ConfigFormat::from_name(&format)?),
+		None => None,
+	};
+	let mut config = config::load(&config_path, format)?;
+
+	if matches.opt_present("print-config") {
+		let config_str = serde_yaml::to_string(&config).context("Serializing config")?;
+		print!("{}", config_str);
+		return Ok(());
+	}
+
+	if matches.opt_present("healthcheck") {
+		return healthcheck(&config);
+	}
+
+	let seek_to_cursor = matches.opt_str("seek-to-cursor");
+	if matches.opt_present("reset-cursor") || seek_to_cursor.is_some() {
+		if !matches.opt_present("force") {
+			anyhow::bail!(
+				"Refusing to modify cursor file {} without --force",
+				config.cursor_file.display()
+			);
+		}
+		return reposition_cursor(&config, seek_to_cursor.as_deref());
+	}
+
+	config::resolve_instance_id(&mut config)?;
+	journal::resolve_boot_offset(&mut config)?;
+	journal::resolve_machine_id(&mut config)?;
+	info!("using configuration: {:?}", config);
+
+	info!(
+		"writing logs to {}, with cursor: {}",
+		config.log_writer_config.target_dir.display(),
+		config.cursor_file.display(),
+	);
+
+	let _pid_file = match &config.pid_file {
+		Some(path) => Some(pid_file::PidFile::create(path)?),
+		None => None,
+	};
+
+	let options = RunOptions {
+		max_entries: match matches.opt_str("max-entries") {
+			Some(n) => Some(n.parse().context("Parsing --max-entries")?),
+			None => None,
+		},
+		max_runtime: match matches.opt_str("max-runtime") {
+			Some(duration) => {
+				Some(humantime::parse_duration(&duration).context("Parsing --max-runtime")?)
+			}
+			None => None,
+		},
+		start_time: parse_time_option(&matches, "start-time")?,
+		end_time: parse_time_option(&matches, "end-time")?,
+		dry_run: matches.opt_present("dry-run"),
+		exit_at_tail: match matches.opt_str("follow").as_deref() {
+			None | Some("true") => false,
+			Some("false") => true,
+			Some(other) => anyhow::bail!("Expected true or false for --follow, got {}", other),
+		},
+	};
+	if let (Some(start), Some(end)) = (options.start_time, options.end_time) {
+		if start >= end {
+			anyhow::bail!(
+				"--start-time {} is not before --end-time {}",
+				start.to_rfc3339(),
+				end.to_rfc3339()
+			);
+		}
+	}
+	if options.dry_run {
+		config.cursor_readonly = true;
+	}
+	REOPEN_ON_SIGHUP.store(
+		config.on_sighup == SighupAction::ReopenOutput,
+		Ordering::Relaxed,
+	);
+	journal::prepare_cursor_file(&mut config)?;
+	if !config.required_fields.is_empty()
+		&& config.required_fields_check == RequiredFieldsCheck::Startup
+	{
+		journal::check_required_fields(&config)?;
+	}
+
+	if !config.shard_matches.is_empty() {
+		let shards = config.shard_matches.len();
+		if shards < 2 || (config.shard_count != 1 && config.shard_count != shards) {
+			anyhow::bail!(
+				"shard_matches needs an expression for each of at least two shards, got {} for shard_count {}",
+				shards,
+				config.shard_count
+			);
+		}
+		config.shard_count = shards;
+	}
+	check_gap_markers(&config)?;
+	check_shard_cursors(&config)?;
+	if config.shard_count > 1 {
+		run_shards(config, options)?;
+	} else {
+		supervise(config, options)?;
+	}
+
+	Ok(())
+}
+
+/// Refuse `emit_gap_markers` with matches of the journal reader, as the entries they leave
+/// out would be reported as lost.
+fn check_gap_markers(config: &Config) -> Result<()> {
+	if !config.emit_gap_markers {
+		return Ok(());
+	}
+	let matches = [
+		("match_expression", config.match_expression.is_some()),
+		("shard_matches", !config.shard_matches.is_empty()),
+		("current_boot_only", config.current_boot_only),
+		("boot_id", config.boot_id.is_some()),
+		("transports", !config.transports.is_empty()),
+		("uids", !config.uids.is_empty()),
+		("gids", !config.gids.is_empty()),
+	];
+	let configured: Vec<_> = matches
+		.iter()
+		.filter(|(_, configured)| *configured)
+		.map(|(name, _)| *name)
+		.collect();
+	if !configured.is_empty() {
+		anyhow::bail!(
+			"emit_gap_markers can't tell the entries left out by {} from lost ones",
+			configured.join(", ")
+		);
+	}
+
+	Ok(())
+}
+
+fn parse_time_option(
+	matches: &getopts::Matches,
+	name: &str,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+	match matches.opt_str(name) {
+		Some(time) => {
+			let time = chrono::DateTime::parse_from_rfc3339(&time)
+				.with_context(|| format!("Parsing --{}", name))?;
+			Ok(Some(time.with_timezone(&chrono::Utc)))
+		}
+		None => Ok(None),
+	}
+}
+
+/// Check that the cursor has been persisted recently.
+fn healthcheck(config: &Config) -> Result<()> {
+	let modified = std::fs::metadata(&config.cursor_file)
+		.and_then(|metadata| metadata.modified())
+		.with_context(|| format!("Reading cursor file {}", config.cursor_file.display()))?;
+	// a modification time in the future counts as fresh
+	let age = modified.elapsed().unwrap_or_default();
+	if age > config.healthcheck_max_cursor_age {
+		anyhow::bail!(
+			"cursor file {} not updated for {}s",
+			config.cursor_file.display(),
+			age.as_secs()
+		);
+	}
+
+	println!("healthy, cursor updated {}s ago", age.as_secs());
+	Ok(())
+}
+
+/// Write `cursor` to the cursor file, or delete it if `None`.
+fn reposition_cursor(config: &Config, cursor: Option<&str>) -> Result<()> {
+	match cursor {
+		Some(cursor) => {
+			writer::write_cursor(cursor, &config.cursor_file)?;
+			println!("wrote cursor to {}", config.cursor_file.display());
+		}
+		None => match std::fs::remove_file(&config.cursor_file) {
+			Ok(()) => println!("deleted {}", config.cursor_file.display()),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				println!("{} does not exist", config.cursor_file.display())
+			}
+			Err(e) => {
+				return Err(e).with_context(|| {
+					format!("Deleting cursor file {}", config.cursor_file.display())
+				})
+			}
+		},
+	}
+
+	Ok(())
+}
+
+/// Options given on the command line
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+	/// Forwarding stops once `max_entries` or `max_runtime` is reached
+	pub max_entries: Option<u64>,
+	pub max_runtime: Option<Duration>,
+	/// Realtime range of the entries to forward, for time bounded exports
+	pub start_time: Option<chrono::DateTime<chrono::Utc>>,
+	pub end_time: Option<chrono::DateTime<chrono::Utc>>,
+	/// Write to stdout instead of the sinks
+	pub dry_run: bool,
+	/// Exit once the entries present at startup have been forwarded, instead of following
+	/// new ones
+	pub exit_at_tail: bool,
+}
+
+impl RunOptions {
+	fn reached(&self, processed: u64, started: std::time::Instant) -> bool {
+		self.max_entries.map_or(false, |max| processed >= max)
+			|| self
+				.max_runtime
+				.map_or(false, |max| started.elapsed() >= max)
+	}
+}
+
+/// Run `shard_count` forwarders in parallel, each handling the units hashed to it with its
+/// own cursor and output files.
+fn run_shards(config: Config, options: RunOptions) -> Result<()> {
+	let shard_count = config.shard_count;
+	let mut handles = Vec::with_capacity(shard_count);
+	for index in 0..shard_count {
+		let mut shard = config.clone();
+		shard.shard = Some(index);
+		shard.shard_match = config.shard_matches.get(index).cloned();
+		shard.cursor_file = shard_path(&config.cursor_file, index);
+		shard_outputs(&mut shard, index);
+		if index > 0 {
+			// only one shard can listen on the socket
+			shard.control_socket = None;
+		}
+
+		let options = options.clone();
+		let handle = std::thread::Builder::new()
+			.name(format!("shard-{}", index))
+			.spawn(move || supervise(shard, options))
+			.context("Spawning shard thread")?;
+		handles.push(handle);
+	}
+
+	let mut result = Ok(());
+	for (index, handle) in handles.into_iter().enumerate() {
+		let shard_result = match handle.join() {
+			Ok(shard_result) => shard_result.with_context(|| format!("Shard {}", index)),
+			Err(_) => Err(anyhow::anyhow!("Shard {} panicked", index)),
+		};
+		if let Err(e) = shard_result {
+			// stop the other shards as well
+			EXIT_FLAG.store(true, Ordering::Relaxed);
+			if result.is_ok() {
+				result = Err(e);
+			}
+		}
+	}
+
+	result
+}
+
+/// Fail if the cursor files left by a previous run belong to another `shard_count`, as the
+/// entries would be forwarded again from `start_position` or partitioned differently.
+fn check_shard_cursors(config: &Config) -> Result<()> {
+	let shard_count = config.shard_count;
+	let unsharded = config.cursor_file.exists();
+	let first_shard = shard_path(&config.cursor_file, 0).exists();
+	if shard_count <= 1 && first_shard {
+		anyhow::bail!(
+			"{} is left by a run with shard_count > 1, restore shard_count or remove the shard cursor files",
+			shard_path(&config.cursor_file, 0).display()
+		);
+	}
+	if shard_count > 1 && unsharded && !first_shard {
+		anyhow::bail!(
+			"{} is left by a run without shards, set shard_count to 1 or remove it to start anew",
+			config.cursor_file.display()
+		);
+	}
+	if shard_count > 1 && shard_path(&config.cursor_file, shard_count).exists() {
+		anyhow::bail!(
+			"{} is left by a run with more shards than shard_count {}, restore it or remove the shard cursor files",
+			shard_path(&config.cursor_file, shard_count).display(),
+			shard_count
+		);
+	}
+
+	Ok(())
+}
+
+/// Give the output files of the shard their own prefix.
+fn shard_outputs(config: &mut Config, index: usize) {
+	config.log_writer_config.prefix = format!("{}shard{}-", config.log_writer_config.prefix, index);
+	for sink in config.sinks.iter_mut() {
+		if let Some(log_writer_config) = sink.log_writer_config.as_mut() {
+			log_writer_config.prefix = format!("{}shard{}-", log_writer_config.prefix, index);
+		}
+	}
+}
+
+/// `path` with `.shard<index>` appended
+fn shard_path(path: &std::path::Path, index: usize) -> std::path::PathBuf {
+	let mut path = path.as_os_str().to_os_string();
+	path.push(format!(".shard{}", index));
+	path.into()
+}
+
+/// Whether the entry belongs to the shard, by the hash of its unit. With `shard_matches` the
+/// reader only returns the entries of the shard.
+fn in_shard(entry: &JournalEntry, config: &Config, index: usize) -> bool {
+	config.shard_match.is_some()
+		|| router::unit_hash(entry, &config.unit_field) % config.shard_count as u64 == index as u64
+}
+
+/// Run the forwarder, restarting it in process after a failure up to `max_restarts` times.
+fn supervise(mut config: Config, options: RunOptions) -> Result<()> {
+	let mut restarts = 0;
+	let mut delay = Duration::from_secs(1);
+	loop {
+		let e = match run(config.clone(), options.clone()) {
+			Ok(()) => return Ok(()),
+			Err(e) => e,
+		};
+		if restarts >= config.max_restarts || EXIT_FLAG.load(Ordering::Relaxed) {
+			return Err(e);
+		}
+
+		restarts += 1;
+		error!(
+			"forwarding failed, restarting in {:?} ({} of {} restarts): {:#}",
+			delay, restarts, config.max_restarts, e
+		);
+		std::thread::sleep(delay);
+		delay = (delay * 2).min(config.reconnect_max_delay);
+		// still served by the thread spawned by the first run
+		config.control_socket = None;
+	}
+}
+
+pub fn run(mut config: Config, options: RunOptions) -> Result<()> {
+	// the last entry forwarded without following
+	let end_cursor = if options.exit_at_tail {
+		match journal::tail_cursor(&config)? {
+			// already forwarded by a previous run
+			Some(cursor) if read_cursor(&config).as_deref() == Some(cursor.as_str()) => {
+				info!("no new entries to forward");
+				return Ok(());
+			}
+			Some(cursor)
+				if options.start_time.map_or(false, |start| {
+					journal::cursor_timestamp(&cursor)
+						.map_or(false, |tail_us| tail_us < start.timestamp_micros())
+				}) =>
+			{
+				info!("no entries received after --start-time");
+				return Ok(());
+			}
+			Some(cursor) => Some(cursor),
+			None => {
+				info!("no entries to forward");
+				return Ok(());
+			}
+		}
+	} else {
+		None
+	};
+
+	let mut sinks = open_sinks(&config, &options)?;
+
+	let mut dead_letter = match &config.error_sink {
+		Some(error_sink) => Some(dead_letter::DeadLetter::open(error_sink.clone())?),
+		None => None,
+	};
+
+	if let Some(path) = &config.control_socket {
+		control::spawn(path)?;
+	}
+
+	if config.lifecycle_events {
+		write_lifecycle_event(&mut sinks, lifecycle::EventKind::Start, &config)?;
+	}
+
+	let mut suppression_reporter = if config.suppression_reports {
+		Some(lifecycle::SuppressionReporter::new(
+			config.suppression_report_interval,
+		))
+	} else {
+		None
+	};
+
+	let mut state = State {
+		cursor_grace_until: config
+			.cursor_initial_delay
+			.map(|delay| std::time::Instant::now() + delay),
+		cursor_grace_entries: config.cursor_initial_entries,
+		enricher: config.enrich_command.clone().map(enrich::Enricher::new),
+		// at most once writes the cursor before each entry itself
+		persister: if config.async_cursor
+			&& !config.cursor_readonly
+			&& config.delivery == Delivery::AtLeastOnce
+		{
+			Some(persister::CursorPersister::spawn(
+				config.cursor_file.clone(),
+			)?)
+		} else {
+			None
+		},
+		recent_cursors: match config.dedup_cache_size {
+			0 => None,
+			size => Some(dedup::RecentCursors::new(size)),
+		},
+		last_seqnums: read_cursor(&config)
+			.as_deref()
+			.and_then(journal::Seqnum::of_cursor)
+			.map(|seqnum| (seqnum.id().to_string(), seqnum))
+			.into_iter()
+			.collect(),
+		seq: if config.forwarder_seq {
+			writer::read_seq(read_cursor(&config).as_deref(), &config.cursor_file)?
+		} else {
+			0
+		},
+		..State::default()
+	};
+
+	let forwarded = forward(
+		&mut config,
+		&options,
+		end_cursor.as_deref(),
+		&mut sinks,
+		&mut state,
+		&mut dead_letter,
+		&mut suppression_reporter,
+	);
+	if let Err(e) = forwarded {
+		if config.max_restarts > 0 {
+			persist_written(&mut sinks, &config, &mut state, "before restarting");
+		}
+		return Err(e);
+	}
+
+	match config.shutdown_ack_timeout {
+		Some(timeout) => drain_before_exit(&mut sinks, &config, &mut state, timeout),
+		None => {
+			if options.end_time.is_some() {
+				persist_written(&mut sinks, &config, &mut state, "at --end-time");
+			}
+			for sink in sinks.iter_mut() {
+				sink.flush()?;
+			}
+		}
+	}
+	if let Some(persister) = &state.persister {
+		persister.flush()?;
+	}
+	if config.lifecycle_events {
+		write_lifecycle_event(&mut sinks, lifecycle::EventKind::Stop, &config)?;
+	}
+	for sink in sinks.iter_mut() {
+		sink.finish()?;
+	}
+	log_oversized(&config);
+	metrics::log_summary();
+
+	Ok(())
+}
+
+/// Forward entries until the end of the journal or a limit is reached, or the exit flag is
+/// set.
+fn forward(
+	config: &mut Config,
+	options: &RunOptions,
+	end_cursor: Option<&str>,
+	sinks: &mut Vec<sink::Sink>,
+	state: &mut State,
+	dead_letter: &mut Option<dead_letter::DeadLetter>,
+	suppression_reporter: &mut Option<lifecycle::SuppressionReporter>,
+) -> Result<()> {
+	let watchdog = match config.write_timeout {
+		Some(timeout) => Some(watchdog::Watchdog::spawn(timeout, config.on_error)?),
+		None => None,
+	};
+	let mut latency_monitor = config
+		.write_latency
+		.clone()
+		.map(latency::LatencyMonitor::new);
+
+	let started = std::time::Instant::now();
+	let mut processed = 0;
+	let mut checkpoint_generation = CHECKPOINT_GENERATION.load(Ordering::Relaxed);
+	let mut reopen_generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+	let mut cursor_update_last = std::time::Instant::now();
+	// reading the journal, see `idle_flush_after`
+	let mut last_entry_at = std::time::Instant::now();
+	let mut idle_flushed = false;
+	// entries read since the journal was last drained, see `max_drain_per_wakeup`
+	let mut drained = 0;
+	let mut backoff = journal::ReconnectBackoff::new(config);
+	let mut config_watcher = reload::ConfigWatcher::new(config)?;
+
+	let mut reconnecting = false;
+
+	'reader: loop {
+		let (mut reader, position) =
+			match journal::open_reader(config, state.memory_cursor.as_deref()) {
+				Ok(opened) => opened,
+				// e.g. journald still restarting
+				Err(e) if reconnecting => {
+					let delay = match backoff.delay() {
+						Ok(delay) => delay,
+						Err(giving_up) => return Err(e.context(giving_up)),
+					};
+					warn!(
+						"reopening the journal failed, retrying in {:?}: {:#}",
+						delay, e
+					);
+					std::thread::sleep(delay);
+					continue 'reader;
+				}
+				Err(e) => return Err(e),
+			};
+		state.reread_cursor = None;
+		match position {
+			journal::Position::Fallback {
+				cursor_timestamp_us,
+			} if config.dedup_on_reconnect => {
+				state.dedup_until_us = state.last_forwarded_us.or(cursor_timestamp_us);
+			}
+			journal::Position::Reread { cursor } => state.reread_cursor = Some(cursor),
+			journal::Position::Backfill { cursor } => state.backfill_cursor = cursor,
+			_ => {}
+		}
+		if let Some(start) = options.start_time {
+			let start_us = start.timestamp_micros();
+			// a previous run or reconnect already forwarded entries of the range
+			let resumed = read_cursor(config)
+				.as_deref()
+				.and_then(journal::cursor_timestamp)
+				.map_or(false, |cursor_us| cursor_us >= start_us);
+			if !resumed {
+				journal::seek_realtime(&mut reader, start_us)?;
+				state.reread_cursor = None;
+				state.dedup_until_us = None;
+			}
+		}
+		// Entries are polled, so that the end of the entries present at startup is noticed
+		loop {
+			let entry = match reader.next_entry() {
+				Ok(Some(entry)) => entry,
+				Ok(None) => {
+					if EXIT_FLAG.load(Ordering::Relaxed) {
+						info!("obeying exit flag");
+						break 'reader;
+					}
+					if end_cursor.is_some() {
+						// also if `end_cursor` was skipped or stepped onto by the start position
+						info!("forwarded all entries present at startup");
+						break 'reader;
+					}
+					if options.reached(processed, started) {
+						info!("reached --max-runtime after {} entries", processed);
+						persist_written(sinks, config, state, "at --max-runtime");
+						break 'reader;
+					}
+					// entries received from now on are past it
+					if options
+						.end_time
+						.map_or(false, |end| chrono::Utc::now() > end)
+					{
+						info!("reached --end-time after {} entries", processed);
+						break 'reader;
+					}
+					drained = 0;
+					// rotation tools wait for the files to be reopened
+					reopen_on_sighup(&mut reopen_generation, sinks)?;
+					if let Some(reloaded) = config_watcher
+						.as_mut()
+						.and_then(reload::ConfigWatcher::poll)
+					{
+						reload_config(reloaded, sinks, config, options, state)?;
+					}
+					let generation = CHECKPOINT_GENERATION.load(Ordering::Relaxed);
+					if generation != checkpoint_generation {
+						checkpoint_generation = generation;
+						let persisted = state.persisted_cursor.clone();
+						force_checkpoint(sinks, config, state, persisted)?;
+					}
+					let idle = config
+						.idle_flush_after
+						.map_or(false, |after| last_entry_at.elapsed() >= after);
+					if idle && !idle_flushed {
+						idle_flush(sinks, config, state)?;
+						idle_flushed = true;
+					}
+					std::thread::sleep(WAIT_INTERVAL);
+					continue;
+				}
+				Err(e) => match journal::classify_error(&e) {
+					IterErrorKind::Entry if config.on_error == ErrorPolicy::Skip => {
+						let e = anyhow::Error::new(e);
+						status::set_error(&e);
+						let e = count_consecutive_error(e, config, state)?;
+						warn!("skipping unreadable journal entry: {}", e);
+						metrics::ENTRIES_SKIPPED.inc();
+						continue;
+					}
+					IterErrorKind::Entry => {
+						return Err(e).context("iterate over Journal entries");
+					}
+					kind => {
+						// resume from the persisted cursor
+						let e = anyhow::Error::new(e);
+						let delay = match backoff.delay() {
+							Ok(delay) => delay,
+							Err(giving_up) => return Err(e.context(giving_up)),
+						};
+						if kind == IterErrorKind::Restart {
+							warn!(
+								"journal went away, systemd-journald likely restarted, reopening in {:?}: {:#}",
+								delay, e
+							);
+						} else {
+							warn!("journal reader failed, reopening in {:?}: {:#}", delay, e);
+						}
+						status::set_error(&e);
+						// the reader is reopened at the persisted cursor
+						persist_written(sinks, config, state, "before reopening the journal");
+						std::thread::sleep(delay);
+						reconnecting = true;
+						continue 'reader;
+					}
+				},
+			};
+			trace!("found entry: {:?}", entry);
+			let read_at = std::time::Instant::now();
+			last_entry_at = read_at;
+			idle_flushed = false;
+			backoff.reset();
+			if entry.get_field("__CURSOR").is_none() {
+				missing_cursor(config)?;
+			}
+			if config.emit_gap_markers {
+				report_gap(&entry, sinks, config, state)?;
+			}
+			let at_end = end_cursor.is_some() && entry.get_field("__CURSOR") == end_cursor;
+
+			if let Some(end) = options.end_time {
+				match entry.get_reception_wallclock_time() {
+					Ok(time) if time.timestamp_us > end.timestamp_micros() => {
+						if processed == 0 {
+							info!("no entries received before --end-time");
+						} else {
+							info!("reached --end-time after {} entries", processed);
+						}
+						break 'reader;
+					}
+					_ => {}
+				}
+			}
+
+			if let Some(cursor) = state.backfill_cursor.take() {
+				report_backfill(&cursor, &entry);
+			}
+
+			if let Some(until_us) = state.dedup_until_us {
+				match entry.get_reception_wallclock_time() {
+					Ok(time) if time.timestamp_us <= until_us => {
+						trace!("skipping entry already forwarded before reconnecting");
+						metrics::DUPLICATES_SKIPPED.inc();
+						if at_end {
+							break 'reader;
+						}
+						continue;
+					}
+					_ => state.dedup_until_us = None,
+				}
+			}
+
+			if let Some(cursor) = &state.reread_cursor {
+				if cursor.covers(&entry) {
+					trace!("skipping re-read entry already forwarded");
+					metrics::DUPLICATES_SKIPPED.inc();
+					if at_end {
+						break 'reader;
+					}
+					continue;
+				}
+			}
+
+			if let Some(recent) = &state.recent_cursors {
+				if entry
+					.get_field("__CURSOR")
+					.map_or(false, |cursor| recent.contains(cursor))
+				{
+					trace!("skipping entry forwarded recently");
+					metrics::DUPLICATES_SKIPPED.inc();
+					if at_end {
+						break 'reader;
+					}
+					continue;
+				}
+			}
+
+			if let Some(index) = config.shard {
+				if !in_shard(&entry, config, index) {
+					state.skipped_since_last += 1;
+					if at_end {
+						break 'reader;
+					}
+					continue;
+				}
+			}
+
+			if !config.change_only_fields.is_empty() && !state.changed(&entry, config) {
+				trace!("skipping entry without changed change_only_fields");
+				metrics::UNCHANGED_SKIPPED.inc();
+				state.skipped_since_last += 1;
+				if at_end {
+					break 'reader;
+				}
+				continue;
+			}
+
+			processed += 1;
+			let limit_reached = at_end || options.reached(processed, started);
+			let generation = CHECKPOINT_GENERATION.load(Ordering::Relaxed);
+			let checkpoint = generation != checkpoint_generation;
+			checkpoint_generation = generation;
+			let cursor_update =
+				checkpoint || limit_reached || cursor_update_last.elapsed().as_secs() > 30;
+			if cursor_update {
+				cursor_update_last = std::time::Instant::now();
+			}
+
+			drained += 1;
+			if config
+				.max_drain_per_wakeup
+				.map_or(true, |max| drained >= max)
+			{
+				drained = 0;
+				reopen_on_sighup(&mut reopen_generation, sinks)?;
+				if let Some(reloaded) = config_watcher
+					.as_mut()
+					.and_then(reload::ConfigWatcher::poll)
+				{
+					reload_config(reloaded, sinks, config, options, state)?;
+				}
+			}
+
+			// to tell whether the checkpoint wrote a cursor
+			let persisted = if checkpoint {
+				state.persisted_cursor.clone()
+			} else {
+				None
+			};
+			let result = {
+				let _armed = watchdog.as_ref().map(watchdog::Watchdog::arm);
+				write_entry(&entry, sinks, config, cursor_update, state)
+			};
+			if let (Ok(()), Some(monitor)) = (&result, latency_monitor.as_mut()) {
+				monitor.written(read_at);
+			}
+			match result {
+				Ok(()) => {
+					state.consecutive_errors = 0;
+					if let (Some(recent), Some(cursor)) =
+						(state.recent_cursors.as_mut(), entry.get_field("__CURSOR"))
+					{
+						recent.insert(cursor);
+					}
+				}
+				Err(e) => {
+					status::set_error(&e);
+					match config.on_error {
+						ErrorPolicy::Abort => return Err(e),
+						ErrorPolicy::Skip => {
+							let e = count_consecutive_error(e, config, state)?;
+							warn!("skipping entry: {:?}", e);
+							metrics::ENTRIES_SKIPPED.inc();
+							if let Some(dead_letter) = dead_letter.as_mut() {
+								dead_letter.record(&entry, &e);
+							}
+						}
+					}
+				}
+			}
+			if std::mem::take(&mut state.reload_cursor) {
+				reconnecting = true;
+				continue 'reader;
+			}
+			if let Some(reporter) = suppression_reporter.as_mut() {
+				if let Some(report) = reporter.due(read_cursor(config)) {
+					write_event(sinks, &report, config)?;
+				}
+			}
+			if checkpoint {
+				force_checkpoint(sinks, config, state, persisted)?;
+			}
+
+			if EXIT_FLAG.load(Ordering::Relaxed) {
+				info!("obeying exit flag");
+				break 'reader;
+			}
+			if limit_reached {
+				info!("stopping after {} entries", processed);
+				break 'reader;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Reopen the output files if SIGHUP was received since `reopen_generation`, see `on_sighup`.
+fn reopen_on_sighup(reopen_generation: &mut u64, sinks: &mut [sink::Sink]) -> Result<()> {
+	let generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+	if generation == *reopen_generation {
+		return Ok(());
+	}
+
+	*reopen_generation = generation;
+	info!("reopening output files after SIGHUP");
+	for sink in sinks.iter_mut() {
+		sink.reopen()?;
+	}
+
+	Ok(())
+}
+
+/// The sinks of the config, or stdout with `--dry-run`.
+fn open_sinks(config: &Config, options: &RunOptions) -> Result<Vec<sink::Sink>> {
+	if options.dry_run {
+		Ok(vec![sink::open_stdout(config)])
+	} else {
+		sink::open_all(config)
+	}
+}
+
+/// Apply the config file changed on disk with `watch_config`: persist the cursor of the
+/// entries written so far, then replace the sinks with those of the reloaded output, format
+/// and entry filter options. The current sinks stay if the new ones can't be opened.
+fn reload_config(
+	reloaded: Config,
+	sinks: &mut Vec<sink::Sink>,
+	config: &mut Config,
+	options: &RunOptions,
+	state: &mut State,
+) -> Result<()> {
+	let mut candidate = config.clone();
+	reload::apply(&mut candidate, reloaded);
+	if let Some(index) = config.shard {
+		shard_outputs(&mut candidate, index);
+	}
+
+	persist_written(sinks, config, state, "before applying the reloaded config");
+	for sink in sinks.iter_mut() {
+		sink.flush()?;
+	}
+	let opened = match open_sinks(&candidate, options) {
+		Ok(opened) => opened,
+		Err(e) => {
+			warn!(
+				"keeping the current sinks, opening those of the reloaded config failed: {:#}",
+				e
+			);
+			return Ok(());
+		}
+	};
+	for sink in sinks.iter_mut() {
+		sink.finish()?;
+	}
+	*sinks = opened;
+	*config = candidate;
+	// so the next entry opens the files of its boot in the new sinks with file_per_boot
+	state.boot_id = None;
+	info!("applied reloaded config");
+
+	Ok(())
+}
+
+/// Check whether another process replaced the cursor file, and whether to continue from its
+/// cursor instead of overwriting it, see `on_cursor_replaced`.
+fn cursor_file_replaced(config: &Config) -> bool {
+	if !writer::cursor_file_replaced(&config.cursor_file) {
+		return false;
+	}
+
+	let path = config.cursor_file.display();
+	if !config.cursor_file.exists() {
+		warn!(
+			"cursor file {} was deleted by another process, writing it again",
+			path
+		);
+		return false;
+	}
+	match config.on_cursor_replaced {
+		CursorReplacedPolicy::Keep => {
+			warn!(
+				"cursor file {} was replaced by another process, overwriting it with our cursor",
+				path
+			);
+			false
+		}
+		CursorReplacedPolicy::Reload => {
+			warn!(
+				"cursor file {} was replaced by another process, continuing from its cursor",
+				path
+			);
+			true
+		}
+	}
+}
+
+/// Handle an entry without `__CURSOR` as configured by `on_missing_cursor`. It can still be
+/// written, but the persisted cursor can't advance to it.
+fn missing_cursor(config: &Config) -> Result<()> {
+	metrics::MISSING_CURSORS.inc();
+	match config.on_missing_cursor {
+		MissingCursorPolicy::Continue => {
+			warn!("journal entry without __CURSOR, forwarding it without advancing the cursor");
+			Ok(())
+		}
+		MissingCursorPolicy::Abort => Err(anyhow::anyhow!(
+			"Journal entry without __CURSOR, the cursor can't be persisted"
+		)),
+	}
+}
+
+/// Write a marker for the entries missing between the previous entry read and this one by
+/// their sequence numbers, see `emit_gap_markers`. Like lifecycle events it doesn't advance
+/// the cursor. Entries of journal files with another seqnum id, e.g. of another machine, are
+/// interleaved, so each id is compared with its own last entry.
+fn report_gap(
+	entry: &JournalEntry,
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	state: &mut State,
+) -> Result<()> {
+	let seqnum = match entry
+		.get_field("__CURSOR")
+		.and_then(journal::Seqnum::of_cursor)
+	{
+		Some(seqnum) => seqnum,
+		None => return Ok(()),
+	};
+	let previous = match state
+		.last_seqnums
+		.insert(seqnum.id().to_string(), seqnum.clone())
+	{
+		Some(previous) => previous,
+		None => return Ok(()),
+	};
+	let lost = match previous.gap_to(&seqnum) {
+		Some(lost) => lost,
+		None => return Ok(()),
+	};
+
+	warn!(
+		"{} journal entries missing between seqnum {} and {}",
+		lost,
+		previous.num(),
+		seqnum.num()
+	);
+	let mut event = lifecycle::LifecycleEvent::new(lifecycle::EventKind::Gap, read_cursor(config));
+	event.counts = vec![
+		("last_seqnum", previous.num()),
+		("next_seqnum", seqnum.num()),
+		("lost", lost),
+	];
+	write_event(sinks, &event, config)
+}
+
+/// Count an entry skipped by `on_error: skip`, escalating to an abort once more than
+/// `max_consecutive_errors` failed in a row.
+fn count_consecutive_error(
+	e: anyhow::Error,
+	config: &Config,
+	state: &mut State,
+) -> Result<anyhow::Error> {
+	state.consecutive_errors += 1;
+	match config.max_consecutive_errors {
+		Some(max) if state.consecutive_errors > max => {
+			error!(
+				"{} consecutive entries failed, more than max_consecutive_errors {}, aborting instead of skipping",
+				state.consecutive_errors, max
+			);
+			Err(e.context(format!(
+				"{} consecutive entries failed",
+				state.consecutive_errors
+			)))
+		}
+		_ => Ok(e),
+	}
+}
+
+/// Wait up to `shutdown_ack_timeout` for the pending batches of network sinks to be
+/// acknowledged, and persist the cursor of the last entry only if all of them were, so the
+/// unacknowledged ones are sent again after a restart.
+///
+/// Every sink is flushed, also after another one failed. Best effort sinks with
+/// `on_error: skip` only count their failure and don't hold back the cursor, as while
+/// forwarding.
+fn drain_before_exit(
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	state: &mut State,
+	timeout: Duration,
+) {
+	let deadline = std::time::Instant::now() + timeout;
+	let failures: Vec<_> = sinks
+		.iter_mut()
+		.filter_map(|sink| sink.flush_before(deadline).err())
+		.collect();
+	if failures.is_empty() {
+		persist_written(sinks, config, state, "on shutdown");
+		return;
+	}
+
+	for e in &failures {
+		warn!(
+			"entries not acknowledged within shutdown_ack_timeout: {:#}",
+			e
+		);
+	}
+	warn!(
+		"{} sinks failed to flush within shutdown_ack_timeout {:?}, keeping the previous cursor",
+		failures.len(),
+		timeout
+	);
+}
+
+/// Flush all sinks, including batches below their thresholds, and persist the cursor of the
+/// last entry written, once no entry arrived for `idle_flush_after`.
+fn idle_flush(sinks: &mut [sink::Sink], config: &Config, state: &mut State) -> Result<()> {
+	if state.unflushed || sinks.iter().any(sink::Sink::has_pending) {
+		debug!("no entries for {:?}, flushing", config.idle_flush_after);
+		for sink in sinks.iter_mut() {
+			sink.flush()?;
+		}
+		state.unflushed = false;
+	}
+	persist_written(sinks, config, state, "after idle_flush_after");
+
+	Ok(())
+}
+
+/// Persist the cursor of the last entry written for SIGUSR1, both while entries are forwarded
+/// and while waiting for them, and log whether a cursor other than `persisted` was written.
+fn force_checkpoint(
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	state: &mut State,
+	persisted: Option<String>,
+) -> Result<()> {
+	// the entry just written may have failed, or been held back by `cursor_initial_delay`
+	persist_written(sinks, config, state, "for the forced checkpoint");
+	if let Some(persister) = &state.persister {
+		persister.flush()?;
+	}
+	if state.persisted_cursor.is_some() && state.persisted_cursor != persisted {
+		info!("forced checkpoint completed");
+	} else {
+		info!("forced checkpoint wrote no new cursor");
+	}
+	metrics::log_summary();
+
+	Ok(())
+}
+
+/// Persist the cursor of the last entry written to all sinks, once they are flushed, before
+/// restarting after a failure, reopening the journal, applying a reloaded config or starting
+/// the files of a new boot, for a forced checkpoint or with `idle_flush_after`, after stopping at `--end-time` or at `--max-runtime` while
+/// waiting for entries, or on shutdown with `shutdown_ack_timeout`. `reason` is logged.
+fn persist_written(sinks: &mut [sink::Sink], config: &Config, state: &mut State, reason: &str) {
+	let cursor = match state.unpersisted_cursor.take() {
+		Some(cursor) => cursor,
+		None => return,
+	};
+	if config.cursor_readonly
+		|| state.in_cursor_grace()
+		|| state.persisted_cursor.as_ref() == Some(&cursor)
+	{
+		return;
+	}
+
+	// so that a cursor still pending on the thread doesn't overwrite this one
+	if let Some(persister) = &state.persister {
+		if let Err(e) = persister.flush() {
+			warn!("failed to persist pending cursor: {:#}", e);
+		}
+	}
+	let flushed = sinks.iter_mut().try_for_each(|sink| sink.flush());
+	if rewind_lost(sinks, state) {
+		return;
+	}
+	let persisted = flushed
+		.and_then(|_| writer::write_cursor(&cursor, &config.cursor_file))
+		.and_then(|_| {
+			if config.forwarder_seq {
+				writer::write_seq(state.seq, &cursor, &config.cursor_file)
+			} else {
+				Ok(())
+			}
+		});
+	match persisted {
+		Ok(()) => {
+			debug!("persisted cursor {}", reason);
+			state.persisted_cursor = Some(cursor);
+		}
+		Err(e) => warn!("failed to persist cursor {}: {:#}", reason, e),
+	}
+}
+
+/// Forward the entries after the persisted cursor again if a sink lost them with its
+/// vanished target directory, see `recreate_target_dir`, instead of persisting a cursor past
+/// them. Returns whether the reader has to be reopened at the persisted cursor.
+fn rewind_lost(sinks: &mut [sink::Sink], state: &mut State) -> bool {
+	let lost = sinks
+		.iter_mut()
+		.fold(false, |lost, sink| sink.take_lost() || lost);
+	if !lost {
+		return false;
+	}
+
+	warn!("entries written since the last persisted cursor were lost with the target directory, forwarding them again");
+	state.unpersisted_cursor = None;
+	state.unflushed = false;
+	state.reload_cursor = true;
+	// so the entries read again aren't taken for duplicates
+	if let Some(recent) = state.recent_cursors.as_mut() {
+		recent.clear();
+	}
+	true
+}
+
+/// Switch to the files of the entry's boot with `file_per_boot`, if it differs from the boot
+/// of the previous entry.
+fn start_boot(
+	entry: &JournalEntry,
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	state: &mut State,
+) -> Result<()> {
+	let boot_id = match entry.get_field("_BOOT_ID") {
+		Some(boot_id) if state.boot_id.as_deref() != Some(boot_id) => boot_id,
+		// entries without boot id stay in the files of the current boot
+		_ => return Ok(()),
+	};
+	if let Some(previous) = state.boot_id.take() {
+		info!(
+			"boot changed from {} to {}, starting new files",
+			previous, boot_id
+		);
+		// the cursor has to point into the files of the previous boot once they are closed
+		persist_written(
+			sinks,
+			config,
+			state,
+			"before starting the files of the new boot",
+		);
+	}
+
+	let name = router::boot_name(entry);
+	for sink in sinks.iter_mut() {
+		sink.start_boot(&name)?;
+	}
+	state.boot_id = Some(boot_id.to_string());
+
+	Ok(())
+}
+
+fn write_lifecycle_event(
+	sinks: &mut [sink::Sink],
+	kind: lifecycle::EventKind,
+	config: &Config,
+) -> Result<()> {
+	let event = lifecycle::LifecycleEvent::new(kind, read_cursor(config));
+	write_event(sinks, &event, config)
+}
+
+fn write_event(
+	sinks: &mut [sink::Sink],
+	event: &lifecycle::LifecycleEvent,
+	config: &Config,
+) -> Result<()> {
+	for sink in sinks.iter_mut() {
+		sink.write_event(event, config)
+			.with_context(|| format!("Writing {:?} event", event.kind))?;
+	}
+
+	Ok(())
+}
+
+/// The persisted cursor, for synthetic events
+fn read_cursor(config: &Config) -> Option<String> {
+	std::fs::read_to_string(&config.cursor_file).ok()
+}
+
+/// Detect the reception time going back by more than `clock_jump_threshold`, and with
+/// `on_clock_jump: monotonic` derive the timestamps of the entries of the same boot from
+/// their monotonic time until the clock passed the time before the jump again.
+fn handle_clock_jump(
+	entry: &JournalEntry,
+	record: &mut writer::Record<'_>,
+	config: &Config,
+	threshold: Duration,
+	state: &mut State,
+) {
+	let boot_id = entry.get_field("_BOOT_ID");
+	let monotonic_us = record.monotonic_us().map(|us| us as i64);
+
+	match (&state.clock_jump, state.last_forwarded_us) {
+		(None, Some(last_us)) if last_us - record.received_us > threshold.as_micros() as i64 => {
+			warn!(
+				"clock jumped back by {:?}, from reception time {} to {}",
+				Duration::from_micros((last_us - record.received_us) as u64),
+				last_us,
+				record.received_us
+			);
+			if config.on_clock_jump == ClockJumpPolicy::Monotonic {
+				state.clock_jump =
+					state
+						.clock_offset
+						.clone()
+						.map(|(boot_id, offset_us)| ClockJump {
+							boot_id,
+							offset_us,
+							until_us: last_us,
+						});
+			}
+		}
+		(Some(jump), _) if record.received_us >= jump.until_us => {
+			info!("clock caught up after jumping back, using reception times again");
+			state.clock_jump = None;
+		}
+		_ => {}
+	}
+
+	match (&state.clock_jump, boot_id, monotonic_us) {
+		(Some(jump), Some(boot_id), Some(monotonic_us)) if jump.boot_id == boot_id => {
+			// unless the timestamp comes from timestamp_field
+			if record.timestamp_us == record.received_us {
+				record.timestamp_us = monotonic_us + jump.offset_us;
+			}
+		}
+		(None, Some(boot_id), Some(monotonic_us)) => {
+			let offset_us = record.received_us - monotonic_us;
+			match &mut state.clock_offset {
+				Some((last_boot_id, last_offset_us)) if last_boot_id == boot_id => {
+					*last_offset_us = offset_us
+				}
+				clock_offset => *clock_offset = Some((boot_id.to_string(), offset_us)),
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Summarize the entries larger than `max_entry_bytes`, at exit.
+fn log_oversized(config: &Config) {
+	let skipped = metrics::OVERSIZED_SKIPPED.get();
+	let truncated = metrics::OVERSIZED_TRUNCATED.get();
+	if let (Some(max_entry_bytes), true) = (config.max_entry_bytes, skipped + truncated > 0) {
+		warn!(
+			"{} entries were larger than max_entry_bytes ({} bytes): {} skipped, {} truncated",
+			skipped + truncated,
+			max_entry_bytes,
+			skipped,
+			truncated
+		);
+	}
+}
+
+/// Log the number of entries `max_backfill` skipped, from the persisted cursor to the first
+/// entry read after skipping ahead. It is only known within the same journal file, and
+/// includes entries not matching the filters.
+fn report_backfill(cursor: &journal::Seqnum, entry: &JournalEntry) {
+	match entry
+		.get_field("__CURSOR")
+		.and_then(journal::Seqnum::of_cursor)
+	{
+		Some(next) if next.id() == cursor.id() => info!(
+			"max_backfill skipped {} journal entries",
+			next.num().saturating_sub(cursor.num() + 1)
+		),
+		_ => info!("max_backfill skipped entries of other journal files, their number is unknown"),
+	}
+}
+
+/// A backward jump of the clock, during which timestamps are derived from monotonic time
+#[derive(Debug)]
+struct ClockJump {
+	boot_id: String,
+	/// Reception time minus monotonic time of the last entry before the jump
+	offset_us: i64,
+	/// Reception time of the last entry before the jump
+	until_us: i64,
+}
+
+/// State carried from one entry to the next
+#[derive(Debug, Default)]
+struct State {
+	last_timestamp_us: Option<i64>,
+	/// Reception time of the last entry written, before any clamping
+	last_forwarded_us: Option<i64>,
+	/// After a fallback seek, entries up to this reception time have already been forwarded
+	dedup_until_us: Option<i64>,
+	/// After re-reading before the persisted cursor, entries of its journal file up to it
+	/// have already been forwarded
+	reread_cursor: Option<journal::Seqnum>,
+	/// After skipping ahead for `max_backfill`, the persisted cursor, to report the number of
+	/// entries skipped with the first entry read
+	backfill_cursor: Option<journal::Seqnum>,
+	/// Far behind the wall clock, so writers are only flushed with cursor updates
+	catching_up: bool,
+	/// Entries have been written without flushing
+	unflushed: bool,
+	/// The cursor is not persisted before this time, see `cursor_initial_delay`
+	cursor_grace_until: Option<std::time::Instant>,
+	/// Entries still to be written before the cursor is persisted, see
+	/// `cursor_initial_entries`
+	cursor_grace_entries: u64,
+	/// Last seen value of each of `change_only_fields`
+	last_values: HashMap<String, String>,
+	/// Running `enrich_command`
+	enricher: Option<enrich::Enricher>,
+	/// Cursor of the last entry written but not yet persisted, see `persist_written`
+	unpersisted_cursor: Option<String>,
+	/// Cursor last written to the cursor file, not written again if unchanged
+	persisted_cursor: Option<String>,
+	/// `forwarder_seq` of the last record written
+	seq: u64,
+	/// Timestamp of the last record and its `timestamp_tiebreaker: counter`
+	last_tiebreak: Option<(i64, u64)>,
+	/// `_BOOT_ID` and reception minus monotonic time of the last entry, for `on_clock_jump`
+	clock_offset: Option<(String, i64)>,
+	clock_jump: Option<ClockJump>,
+	/// Entries failed in a row with `on_error: skip`
+	consecutive_errors: u64,
+	/// Cursors of the last forwarded entries, with `dedup_cache_size`
+	recent_cursors: Option<dedup::RecentCursors>,
+	/// Sequence number of the last entry read of each seqnum id, starting with the one of the
+	/// cursor, for `emit_gap_markers`
+	last_seqnums: HashMap<String, journal::Seqnum>,
+	/// `_BOOT_ID` of the files currently written with `file_per_boot`
+	boot_id: Option<String>,
+	/// Entries filtered out since the last one written, see `skipped_since_last`
+	skipped_since_last: u64,
+	/// The cursor file was replaced and the reader has to continue from its cursor, see
+	/// `on_cursor_replaced`
+	reload_cursor: bool,
+	/// Thread writing the cursor with `async_cursor`
+	persister: Option<persister::CursorPersister>,
+	/// Cursor of the last entry written to all sinks, to reopen the reader at when it can't
+	/// be persisted, see `cursor_required`
+	memory_cursor: Option<String>,
+}
+
+impl State {
+	/// Whether the cursor is held back after startup, until both `cursor_initial_delay` has
+	/// passed and `cursor_initial_entries` were written.
+	fn in_cursor_grace(&self) -> bool {
+		self.cursor_grace_entries > 0
+			|| self
+				.cursor_grace_until
+				.map_or(false, |until| std::time::Instant::now() < until)
+	}
+
+	/// Whether any of `change_only_fields` of the entry differs from its last seen value,
+	/// remembering the new values. Entries without any of them count as changed.
+	fn changed(&mut self, entry: &JournalEntry, config: &Config) -> bool {
+		let mut present = false;
+		let mut changed = false;
+		for field in &config.change_only_fields {
+			if let Some(value) = entry.get_field(field) {
+				present = true;
+				if self.last_values.get(field).map(String::as_str) != Some(value) {
+					changed = true;
+					self.last_values.insert(field.clone(), value.to_string());
+				}
+			}
+		}
+
+		changed || !present
+	}
+}
+
+/// Write the entry to all sinks, the cursor is only advanced once all of them are flushed.
+fn write_entry(
+	entry: &JournalEntry,
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	cursor_update: bool,
+	state: &mut State,
+) -> Result<()> {
+	if config.required_fields_check == RequiredFieldsCheck::PerEntry {
+		config::check_required_fields(entry, config)?;
+	}
+	if entry.get_field(loopback::MARKER_FIELD).is_some()
+		&& config
+			.sinks
+			.iter()
+			.any(|sink| sink.target == sink::SinkTarget::Journal)
+	{
+		debug!("skipping entry sent back into the journal by a journal sink");
+		metrics::ENTRIES_SKIPPED.inc();
+		state.skipped_since_last += 1;
+		return Ok(());
+	}
+	if passthrough(config, sinks) {
+		return write_passthrough(entry, sinks, config, cursor_update, state);
+	}
+
+	let transformed;
+	let entry = if config.message_transforms.is_empty() {
+		entry
+	} else {
+		transformed = transform::apply(&config.message_transforms, entry);
+		&transformed
+	};
+	let redacted;
+	let entry = match &config.redact {
+		Some(redact) => {
+			redacted = redact.apply(entry);
+			&redacted
+		}
+		None => entry,
+	};
+
+	let mut record = match writer::Record::new(entry, config)? {
+		Some(record) => record,
+		None => {
+			debug!("skipping entry without timestamp");
+			metrics::ENTRIES_SKIPPED.inc();
+			state.skipped_since_last += 1;
+			return Ok(());
+		}
+	};
+
+	if let Some(max_entry_bytes) = config.max_entry_bytes {
+		let size = writer::entry_size(entry);
+		if size > max_entry_bytes {
+			let identifier = entry.get_field("SYSLOG_IDENTIFIER").unwrap_or("");
+			match config.on_oversized_entry {
+				OversizedEntryPolicy::Skip => {
+					warn!(
+						"skipping entry of {} with {} bytes, more than max_entry_bytes",
+						identifier, size
+					);
+					metrics::ENTRIES_SKIPPED.inc();
+					metrics::OVERSIZED_SKIPPED.inc();
+					state.skipped_since_last += 1;
+					return Ok(());
+				}
+				OversizedEntryPolicy::Truncate => {
+					warn!(
+						"truncating message of {} with {} bytes, more than max_entry_bytes",
+						identifier, size
+					);
+					metrics::OVERSIZED_TRUNCATED.inc();
+					let message_len = entry.get_message().map_or(0, str::len);
+					record.max_message_bytes =
+						Some(message_len.saturating_sub(size - max_entry_bytes));
+				}
+			}
+		}
+	}
+
+	let now_us = chrono::Utc::now().timestamp_micros();
+	if record.timestamp_us > now_us {
+		match config.future_timestamp_policy {
+			FutureTimestampPolicy::Keep => {}
+			FutureTimestampPolicy::ClampToNow => {
+				debug!(
+					"clamping future timestamp {} to now {}",
+					record.timestamp_us, now_us
+				);
+				record.timestamp_us = now_us;
+			}
+			FutureTimestampPolicy::Skip => {
+				debug!(
+					"skipping entry with future timestamp {}",
+					record.timestamp_us
+				);
+				metrics::ENTRIES_SKIPPED.inc();
+				state.skipped_since_last += 1;
+				return Ok(());
+			}
+		}
+	}
+
+	if let Some(threshold) = config.clock_jump_threshold {
+		handle_clock_jump(entry, &mut record, config, threshold, state);
+	}
+
+	if config.enforce_monotonic_timestamps {
+		record.clamp_monotonic(&mut state.last_timestamp_us);
+	}
+
+	// counted once the entry is written, so a failed entry is written again with its number
+	let seq = state.seq + 1;
+	if config.forwarder_seq {
+		record.seq = Some(seq);
+	}
+	if config.skipped_since_last {
+		record.skipped_since_last = Some(state.skipped_since_last);
+	}
+
+	record.tiebreaker = match config.timestamp_tiebreaker {
+		Some(Tiebreaker::Seqnum) => entry
+			.get_field("__CURSOR")
+			.and_then(journal::Seqnum::of_cursor)
+			.map(|seqnum| seqnum.num()),
+		Some(Tiebreaker::Counter) => {
+			let count = match state.last_tiebreak {
+				Some((timestamp_us, count)) if timestamp_us == record.timestamp_us => count + 1,
+				_ => 0,
+			};
+			state.last_tiebreak = Some((record.timestamp_us, count));
+			Some(count)
+		}
+		None => None,
+	};
+
+	if config.annotate_match {
+		record.matched = Some(journal::matched_filters(entry, config));
+	}
+
+	if let Some(enricher) = state.enricher.as_mut() {
+		// the command isn't even started if no sink writes its fields
+		if sinks.iter().any(sink::Sink::uses_enriched) {
+			record.enriched = enricher.enrich(&writer::json_line(&record, config)?)?;
+		}
+	}
+
+	if let Some(threshold) = config.catch_up_threshold {
+		let lag_us = now_us - record.timestamp_us;
+		let catching_up = lag_us > threshold.as_micros() as i64;
+		if catching_up != state.catching_up {
+			if catching_up {
+				info!(
+					"{}s behind, catching up without flushing every entry",
+					lag_us / 1_000_000
+				);
+			} else {
+				info!("caught up, flushing every entry again");
+			}
+			state.catching_up = catching_up;
+		}
+	}
+
+	let urgent = config
+		.always_persist_above_priority
+		.map_or(false, |threshold| record.priority <= threshold);
+	let in_grace = state.in_cursor_grace();
+	let immediate = config
+		.flush_immediately_above_priority
+		.map_or(false, |threshold| record.priority <= threshold);
+	let persist = (cursor_update || urgent || immediate) && !in_grace;
+	let flush = persist || immediate || !state.catching_up;
+
+	// without only_local the output routes each entry by its own boot instead
+	if config.file_per_boot && config.only_local {
+		start_boot(entry, sinks, config, state)?;
+	}
+
+	if config.delivery == Delivery::AtMostOnce && !config.cursor_readonly && !in_grace {
+		// a crash from here on loses the entry instead of forwarding it again
+		state.reload_cursor = cursor_file_replaced(config);
+		if let (false, Some(cursor)) = (state.reload_cursor, entry.get_field("__CURSOR")) {
+			writer::update_cursor(entry, &config.cursor_file)?;
+			if config.forwarder_seq {
+				writer::write_seq(seq, cursor, &config.cursor_file)?;
+			}
+			state.persisted_cursor = Some(cursor.to_string());
+		}
+	}
+
+	for sink in sinks.iter_mut() {
+		sink.write(&record, config, flush)?;
+	}
+	if config.forwarder_seq {
+		state.seq = seq;
+	}
+	if !entry_written(entry, sinks, config, state, flush, persist)? {
+		return Ok(());
+	}
+	if immediate {
+		// batches of network sinks are posted even while the cursor is held back
+		if !persist && sinks.iter().any(sink::Sink::has_pending) {
+			for sink in sinks.iter_mut() {
+				sink.flush()?;
+			}
+		}
+		if let Some(persister) = &state.persister {
+			persister.flush()?;
+		}
+	}
+
+	state.last_forwarded_us = Some(record.received_us);
+	state.skipped_since_last = 0;
+	metrics::ENTRIES_WRITTEN.inc();
+	status::set_timestamp(record.timestamp_us);
+
+	Ok(())
+}
+
+/// Whether entries are written as they are, without the `Record` of `write_entry` and its
+/// processing: with only `format: export` files sinks writing a single file each, and no
+/// option transforming the entries or looking at their time or priority.
+fn passthrough(config: &Config, sinks: &[sink::Sink]) -> bool {
+	config.message_transforms.is_empty()
+		&& config.redact.is_none()
+		&& config.max_entry_bytes.is_none()
+		&& config.on_missing_timestamp == MissingTimestampPolicy::UseNow
+		&& config.future_timestamp_policy != FutureTimestampPolicy::Skip
+		&& config.clock_jump_threshold.is_none()
+		&& config.catch_up_threshold.is_none()
+		&& config.always_persist_above_priority.is_none()
+		&& config.flush_immediately_above_priority.is_none()
+		&& !config.forwarder_seq
+		&& !config.file_per_boot
+		&& !config.file_events
+		&& config.delivery == Delivery::AtLeastOnce
+		&& sinks.iter().all(sink::Sink::is_passthrough)
+}
+
+/// `write_entry` with `passthrough`, copying the entry into the sinks in the export format.
+fn write_passthrough(
+	entry: &JournalEntry,
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	cursor_update: bool,
+	state: &mut State,
+) -> Result<()> {
+	let persist = cursor_update && !state.in_cursor_grace();
+	let flush = persist || !state.catching_up;
+	for sink in sinks.iter_mut() {
+		sink.write_passthrough(entry, config, flush)?;
+	}
+	if !entry_written(entry, sinks, config, state, flush, persist)? {
+		return Ok(());
+	}
+
+	// the reception time of `Record`, without asking the journal again
+	let timestamp_us = entry
+		.get_field("__REALTIME_TIMESTAMP")
+		.and_then(|us| us.parse().ok());
+	if let Some(timestamp_us) = timestamp_us {
+		state.last_forwarded_us = Some(timestamp_us);
+		status::set_timestamp(timestamp_us);
+	}
+	state.skipped_since_last = 0;
+	metrics::ENTRIES_WRITTEN.inc();
+
+	Ok(())
+}
+
+/// Account for the entry written to all sinks, and persist its cursor with `persist`.
+/// `false` if a sink lost entries and they are read again, see `rewind_lost`.
+fn entry_written(
+	entry: &JournalEntry,
+	sinks: &mut [sink::Sink],
+	config: &Config,
+	state: &mut State,
+	flush: bool,
+	persist: bool,
+) -> Result<bool> {
+	state.cursor_grace_entries = state.cursor_grace_entries.saturating_sub(1);
+	if !flush {
+		state.unflushed = true;
+	}
+	if rewind_lost(sinks, state) {
+		return Ok(false);
+	}
+	if config.cursor_in_memory {
+		if let Some(cursor) = entry.get_field("__CURSOR") {
+			state.memory_cursor = Some(cursor.to_string());
+		}
+	}
+
+	if persist {
+		if state.unflushed || sinks.iter().any(sink::Sink::has_pending) {
+			// earlier entries may have been written to other writers
+			for sink in sinks.iter_mut() {
+				sink.flush()?;
+			}
+			state.unflushed = false;
+			if rewind_lost(sinks, state) {
+				return Ok(false);
+			}
+		}
+		let cursor = entry.get_field("__CURSOR");
+		if !config.cursor_readonly && cursor != state.persisted_cursor.as_deref() {
+			let seq = if config.forwarder_seq {
+				Some(state.seq)
+			} else {
+				None
+			};
+			state.reload_cursor = cursor_file_replaced(config);
+			match (&state.persister, cursor) {
+				_ if state.reload_cursor => {}
+				(Some(persister), Some(cursor)) => persister.publish(cursor, seq)?,
+				(Some(_), None) => {}
+				(None, _) => {
+					writer::update_cursor(entry, &config.cursor_file)?;
+					if let (Some(seq), Some(cursor)) = (seq, cursor) {
+						writer::write_seq(seq, cursor, &config.cursor_file)?;
+					}
+				}
+			}
+			state.persisted_cursor = if state.reload_cursor {
+				None
+			} else {
+				cursor.map(str::to_string)
+			};
+		}
+		state.unpersisted_cursor = None;
+	} else {
+		state.unpersisted_cursor = entry.get_field("__CURSOR").map(str::to_string);
+	}
+
+	Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn missing_cursor_continues_or_aborts() {
+		let mut config = bench::config();
+		assert_eq!(config.on_missing_cursor, MissingCursorPolicy::Continue);
+		assert!(missing_cursor(&config).is_ok());
+
+		config.on_missing_cursor = MissingCursorPolicy::Abort;
+		assert!(missing_cursor(&config).is_err());
+	}
+
+	#[test]
+	fn entry_without_cursor_leaves_the_cursor_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let cursor_file = dir.path().join("cursor");
+		let mut entry = JournalEntry::new();
+		entry.set_field("MESSAGE", "no cursor");
+
+		writer::update_cursor(&entry, &cursor_file).unwrap();
+		assert!(!cursor_file.exists());
+
+		entry.set_field("__CURSOR", "s=0123;i=1f");
+		writer::update_cursor(&entry, &cursor_file).unwrap();
+		assert_eq!(
+			std::fs::read_to_string(&cursor_file).unwrap().trim(),
+			"s=0123;i=1f"
+		);
+	}
+
+	#[test]
+	fn export_entries_are_passed_through() {
+		let dir = tempfile::tempdir().unwrap();
+		let logs = dir.path().join("logs");
+		std::fs::create_dir(&logs).unwrap();
+		let config: Config = serde_yaml::from_str(&format!(
+			r#"
+cursor_file: "{}"
+format: "export"
+log_writer_config:
+  target_dir: "{}"
+  prefix: "journal-"
+  suffix: ".log"
+  min_avail_of_total: 0.0
+  warn_if_avail_reached: false
+  max_file_size: 536870912
+"#,
+			dir.path().join("cursor").display(),
+			logs.display()
+		))
+		.unwrap();
+		let mut sinks = sink::open_all(&config).unwrap();
+		assert!(passthrough(&config, &sinks));
+		let mut entry = JournalEntry::new();
+		entry.set_field("__CURSOR", "s=ab;i=1");
+		entry.set_field("__REALTIME_TIMESTAMP", "1760429700000000");
+		entry.set_field("MESSAGE", "hello");
+
+		let mut state = State::default();
+		write_entry(&entry, &mut sinks, &config, true, &mut state).unwrap();
+		assert_eq!(state.persisted_cursor.as_deref(), Some("s=ab;i=1"));
+		assert_eq!(state.last_forwarded_us, Some(1760429700000000));
+		let mut expected = Vec::new();
+		writer::write_passthrough(&entry, &mut expected, &config).unwrap();
+		let file = std::fs::read_dir(&logs).unwrap().next().unwrap().unwrap();
+		assert_eq!(std::fs::read(file.path()).unwrap(), expected);
+	}
+
+	/// The cursor persisted after writing an entry to a sink that fails, with `delivery`.
+	fn cursor_after_failed_write(delivery: &str) -> Option<String> {
+		let dir = tempfile::tempdir().unwrap();
+		let logs = dir.path().join("logs");
+		std::fs::create_dir(&logs).unwrap();
+		let config: Config = serde_yaml::from_str(&format!(
+			r#"
+cursor_file: "{}"
+delivery: "{}"
+log_writer_config:
+  target_dir: "{}"
+  prefix: "journal-"
+  suffix: ".log"
+  min_avail_of_total: 0.0
+  warn_if_avail_reached: false
+  max_file_size: 536870912
+sinks:
+  - target: "http"
+    http:
+      url: "http://127.0.0.1:1/"
+      batch_size: 1
+      timeout: "1s"
+      max_retries: 0
+"#,
+			dir.path().join("cursor").display(),
+			delivery,
+			logs.display()
+		))
+		.unwrap();
+		let mut sinks = sink::open_all(&config).unwrap();
+		let mut entry = JournalEntry::new();
+		entry.set_field("__CURSOR", "s=ab;i=1");
+		entry.set_field("__REALTIME_TIMESTAMP", "1760429700000000");
+		entry.set_field("MESSAGE", "hello");
+
+		let written = write_entry(&entry, &mut sinks, &config, true, &mut State::default());
+		assert!(written.is_err());
+		std::fs::read_to_string(dir.path().join("cursor")).ok()
+	}
+
+	#[test]
+	fn at_most_once_persists_the_cursor_before_writing() {
+		assert_eq!(
+			cursor_after_failed_write("at_most_once").as_deref(),
+			Some("s=ab;i=1")
+		);
+		assert_eq!(cursor_after_failed_write("at_least_once"), None);
+	}
+
+	#[test]
+	fn cursor_grace_ends_after_both_delay_and_entries() {
+		let mut state = State {
+			cursor_grace_entries: 1,
+			..State::default()
+		};
+		assert!(state.in_cursor_grace());
+
+		state.cursor_grace_entries = 0;
+		assert!(!state.in_cursor_grace());
+
+		state.cursor_grace_until = Some(std::time::Instant::now() + Duration::from_secs(60));
+		assert!(state.in_cursor_grace());
+
+		state.cursor_grace_until = Some(std::time::Instant::now());
+		assert!(!state.in_cursor_grace());
+	}
+}
@@ -0,0 +1,218 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::config::Config;
+use crate::metrics;
+use crate::writer::{self, OutputFormat};
+
+/// Kind of a synthetic event of the daemon itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EventKind {
+	Start,
+	Stop,
+	/// Number of entries dropped or suppressed since the previous report
+	SuppressionReport,
+	/// Entries missing between two read ones by their sequence number, see `emit_gap_markers`
+	Gap,
+}
+
+impl EventKind {
+	fn as_str(&self) -> &'static str {
+		match self {
+			EventKind::Start => "start",
+			EventKind::Stop => "stop",
+			EventKind::SuppressionReport => "report",
+			EventKind::Gap => "gap",
+		}
+	}
+
+	/// Value of the `_meta` field
+	fn meta(&self) -> &'static str {
+		match self {
+			EventKind::Start | EventKind::Stop => "lifecycle",
+			EventKind::SuppressionReport => "suppression_report",
+			EventKind::Gap => "gap",
+		}
+	}
+}
+
+/// A synthetic record written into the output next to the journal entries, marked with
+/// a `_meta` field. It never advances the cursor.
+pub(crate) struct LifecycleEvent {
+	pub(crate) kind: EventKind,
+	pub(crate) time: DateTime<Utc>,
+	/// Cursor persisted at the time of the event
+	pub(crate) cursor: Option<String>,
+	/// Additional counters, e.g. of a suppression report
+	pub(crate) counts: Vec<(&'static str, u64)>,
+}
+
+impl LifecycleEvent {
+	pub(crate) fn new(kind: EventKind, cursor: Option<String>) -> Self {
+		Self {
+			kind,
+			time: Utc::now(),
+			cursor,
+			counts: Vec::new(),
+		}
+	}
+
+	/// Fields of the event, followed by `counts`
+	fn pairs(&self) -> Vec<(&'static str, String)> {
+		let mut pairs = vec![
+			("_meta", self.kind.meta().to_string()),
+			("event", self.kind.as_str().to_string()),
+			("version", env!("CARGO_PKG_VERSION").to_string()),
+			("cursor", self.cursor.clone().unwrap_or_default()),
+		];
+		pairs.extend(
+			self.counts
+				.iter()
+				.map(|(name, count)| (*name, count.to_string())),
+		);
+		pairs
+	}
+
+	fn fields(&self) -> serde_json::Map<String, serde_json::Value> {
+		let mut object = serde_json::Map::new();
+		object.insert(
+			"timestamp".to_string(),
+			self.time
+				.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+				.into(),
+		);
+		for (key, value) in self.pairs() {
+			object.insert(key.to_string(), value.into());
+		}
+		// numbers rather than the strings of `pairs`
+		for (name, count) in &self.counts {
+			object.insert(name.to_string(), (*count).into());
+		}
+		object
+	}
+
+	fn key_values(&self) -> String {
+		let pairs: Vec<String> = self
+			.pairs()
+			.into_iter()
+			.map(|(key, value)| format!("{}={}", key, value))
+			.collect();
+		pairs.join(" ")
+	}
+
+	/// Write the event in `format`, the csv formats with the `csv_columns` of `config`.
+	pub(crate) fn write<W: Write>(
+		&self,
+		writer: &mut W,
+		format: OutputFormat,
+		config: &Config,
+	) -> Result<()> {
+		let written = match format {
+			OutputFormat::Text
+			| OutputFormat::ZonedText
+			| OutputFormat::Audit
+			| OutputFormat::Kernel => writeln!(
+				writer,
+				"{} {}",
+				self.time
+					.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+				self.key_values()
+			),
+			// syslog facility, informational
+			OutputFormat::Syslog => writeln!(
+				writer,
+				"<46>{} journald-writer: {}",
+				chrono::Local
+					.from_utc_datetime(&self.time.naive_utc())
+					.format("%b %e %H:%M:%S"),
+				self.key_values()
+			),
+			OutputFormat::Json => writeln!(writer, "{}", serde_json::Value::from(self.fields())),
+			OutputFormat::MessagePack => {
+				let data = rmp_serde::to_vec_named(&self.fields())
+					.context("Serializing lifecycle event as MessagePack")?;
+				writer::write_length_prefixed(writer, &data)?;
+				Ok(())
+			}
+			OutputFormat::Export => {
+				let mut export = format!("__REALTIME_TIMESTAMP={}\n", self.time.timestamp_micros());
+				for (key, value) in self.pairs() {
+					export.push_str(&format!("{}={}\n", key.to_uppercase(), value));
+				}
+				export.push_str(&format!(
+					"MESSAGE=journald-writer {}\n\n",
+					self.kind.as_str()
+				));
+				writer.write_all(export.as_bytes())
+			}
+			OutputFormat::Csv | OutputFormat::Tsv => {
+				writer::write_csv(writer, format, &config.csv_columns, &self.fields())?;
+				Ok(())
+			}
+			// the fields of the event as labels, which are keywords in ECS
+			OutputFormat::Ecs => {
+				let labels: serde_json::Map<String, serde_json::Value> = self
+					.pairs()
+					.into_iter()
+					.map(|(key, value)| (key.to_string(), value.into()))
+					.collect();
+				let ecs = serde_json::json!({
+					"@timestamp": self.time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+					"ecs": { "version": writer::ECS_VERSION },
+					"message": format!("journald-writer {}", self.kind.as_str()),
+					"labels": labels,
+				});
+				writeln!(writer, "{}", ecs)
+			}
+		};
+		written.context("write lifecycle event to log_writer")?;
+
+		writer.flush().context("Flushing writer")?;
+
+		Ok(())
+	}
+}
+
+/// Emits a suppression report every `interval`, if entries have been dropped since the last
+/// one.
+pub(crate) struct SuppressionReporter {
+	interval: Duration,
+	last: Instant,
+	reported: Vec<u64>,
+}
+
+impl SuppressionReporter {
+	pub(crate) fn new(interval: Duration) -> Self {
+		Self {
+			interval,
+			last: Instant::now(),
+			reported: metrics::SUPPRESSED.iter().map(|c| c.get()).collect(),
+		}
+	}
+
+	/// The report, if one is due.
+	pub(crate) fn due(&mut self, cursor: Option<String>) -> Option<LifecycleEvent> {
+		if self.last.elapsed() < self.interval {
+			return None;
+		}
+		self.last = Instant::now();
+
+		let current: Vec<u64> = metrics::SUPPRESSED.iter().map(|c| c.get()).collect();
+		let counts: Vec<(&'static str, u64)> = metrics::SUPPRESSED
+			.iter()
+			.zip(current.iter().zip(&self.reported))
+			.map(|(counter, (current, reported))| (counter.name(), current - reported))
+			.collect();
+		self.reported = current;
+		if counts.iter().all(|(_, count)| *count == 0) {
+			return None;
+		}
+
+		let mut event = LifecycleEvent::new(EventKind::SuppressionReport, cursor);
+		event.counts = counts;
+		Some(event)
+	}
+}
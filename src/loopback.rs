@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::syslog::SyslogSocket;
+use crate::writer::{self, Record};
+
+/// Field added to all entries sent back into the journal. Entries carrying it are never
+/// forwarded by a config with a `target: journal` sink, so they can't loop.
+pub(crate) const MARKER_FIELD: &str = "JOURNALD_WRITER_LOOPBACK";
+
+/// Socket and fields of a `target: journal` sink
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopbackConfig {
+	/// The native socket of journald, which `sd_journal_send` writes to
+	#[serde(default = "default_socket")]
+	pub socket: PathBuf,
+	/// Keys of the json format to send, all if empty. Keys are uppercased, and those starting
+	/// with `_` are skipped, as journald sets its trusted fields itself
+	#[serde(default)]
+	pub fields: Vec<String>,
+}
+
+impl Default for LoopbackConfig {
+	fn default() -> Self {
+		Self {
+			socket: default_socket(),
+			fields: Vec::new(),
+		}
+	}
+}
+
+fn default_socket() -> PathBuf {
+	PathBuf::from("/run/systemd/journal/socket")
+}
+
+/// Sends records, with their redacted and enriched fields, back into the journal in its
+/// native protocol, turning the forwarder into an enrichment processor.
+///
+/// Entries must fit into one datagram, larger ones fail.
+pub(crate) struct Loopback {
+	config: LoopbackConfig,
+	socket: SyslogSocket,
+}
+
+impl Loopback {
+	pub(crate) fn new(config: LoopbackConfig) -> Self {
+		let socket = SyslogSocket::new(config.socket.clone());
+		Self { config, socket }
+	}
+
+	pub(crate) fn send(
+		&mut self,
+		record: &Record<'_>,
+		buffer: &mut Vec<u8>,
+		config: &Config,
+	) -> Result<()> {
+		for (key, value) in writer::record_fields(record, config) {
+			if !self.config.fields.is_empty() && !self.config.fields.contains(&key) {
+				continue;
+			}
+			let name = field_name(&key);
+			let invalid =
+				name.is_empty() || name.starts_with(|c: char| c == '_' || c.is_ascii_digit());
+			if invalid || name == MARKER_FIELD {
+				continue;
+			}
+			let value = match value {
+				serde_json::Value::String(value) => value,
+				value => value.to_string(),
+			};
+			append_field(buffer, &name, &value);
+		}
+		append_field(buffer, MARKER_FIELD, "1");
+
+		self.socket.send(buffer)
+	}
+}
+
+/// The key as journal field name: uppercase ASCII letters, digits and `_`.
+fn field_name(key: &str) -> String {
+	key.chars()
+		.map(|c| match c {
+			'a'..='z' => c.to_ascii_uppercase(),
+			'A'..='Z' | '0'..='9' | '_' => c,
+			_ => '_',
+		})
+		.collect()
+}
+
+/// Append `NAME=value`, or for values with line breaks the name, the little endian length
+/// and the value on their own lines.
+fn append_field(buffer: &mut Vec<u8>, name: &str, value: &str) {
+	buffer.extend_from_slice(name.as_bytes());
+	if value.contains('\n') {
+		buffer.push(b'\n');
+		buffer.extend_from_slice(&(value.len() as u64).to_le_bytes());
+	} else {
+		buffer.push(b'=');
+	}
+	buffer.extend_from_slice(value.as_bytes());
+	buffer.push(b'\n');
+}
@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+use sha2::Digest;
+
+use crate::index::CurrentFiles;
+
+/// Appended to the name of an output file for its `file_manifest`
+pub(crate) const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Hash of the output files written to their `file_manifest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestAlgorithm {
+	Sha256,
+	Sha512,
+}
+
+impl ManifestAlgorithm {
+	fn name(self) -> &'static str {
+		match self {
+			ManifestAlgorithm::Sha256 => "sha256",
+			ManifestAlgorithm::Sha512 => "sha512",
+		}
+	}
+}
+
+/// Checksums of the output files of a files sink, written next to each file once it is
+/// rotated, also inside log_writer by `max_file_size`, or closed by `rotate_every_n_entries`,
+/// `max_open_writers`, `file_per_boot` or `on_sighup`. Files still open at exit get none, as
+/// they may be continued.
+pub(crate) struct Manifests {
+	algorithm: ManifestAlgorithm,
+	current: CurrentFiles,
+}
+
+impl Manifests {
+	pub(crate) fn new(algorithm: ManifestAlgorithm, max_file_size: u64) -> Self {
+		Self {
+			algorithm,
+			current: CurrentFiles::new(max_file_size),
+		}
+	}
+
+	/// Record `len` bytes written to the file at `location`, and write the manifest of the
+	/// previous file once log_writer rotated it. `flush` is called before looking up the new
+	/// file, see `CurrentFiles::written`.
+	pub(crate) fn written(
+		&mut self,
+		location: &(PathBuf, String),
+		len: usize,
+		flush: impl FnOnce() -> Result<()>,
+	) -> Result<()> {
+		let previous = self.current.path(location).map(Path::to_path_buf);
+		if !self.current.written(location, len, flush)? {
+			return Ok(());
+		}
+		match previous {
+			Some(previous) if self.current.path(location) != Some(previous.as_path()) => {
+				self.write(&previous)
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Write the manifest of the file at `location`, which was flushed and closed.
+	pub(crate) fn closed(&mut self, location: &(PathBuf, String)) -> Result<()> {
+		let path = self.current.path(location).map(Path::to_path_buf);
+		self.current.closed(location);
+		match path {
+			Some(path) => self.write(&path),
+			None => Ok(()),
+		}
+	}
+
+	fn write(&self, path: &Path) -> Result<()> {
+		// moved away by external rotation, or gone with its directory
+		if !path.exists() {
+			debug!("not writing manifest of vanished {}", path.display());
+			return Ok(());
+		}
+		let (digest, bytes, lines) = match self.algorithm {
+			ManifestAlgorithm::Sha256 => checksum::<sha2::Sha256>(path)?,
+			ManifestAlgorithm::Sha512 => checksum::<sha2::Sha512>(path)?,
+		};
+		let manifest = serde_json::json!({
+			"file": path,
+			"algorithm": self.algorithm.name(),
+			"digest": digest,
+			"bytes": bytes,
+			"lines": lines,
+		});
+
+		let mut target = path.as_os_str().to_owned();
+		target.push(MANIFEST_SUFFIX);
+		let target = PathBuf::from(target);
+		std::fs::write(&target, format!("{}\n", manifest))
+			.with_context(|| format!("Writing manifest {}", target.display()))?;
+		debug!("wrote manifest of {}", path.display());
+
+		Ok(())
+	}
+}
+
+/// Hex digest of the file at `path`, and its number of bytes and lines.
+fn checksum<D: Digest>(path: &Path) -> Result<(String, u64, u64)> {
+	let mut file =
+		File::open(path).with_context(|| format!("Opening {} for its manifest", path.display()))?;
+	let mut hasher = D::new();
+	let mut buffer = vec![0; 64 * 1024];
+	let mut bytes = 0;
+	let mut lines = 0;
+	loop {
+		let len = file
+			.read(&mut buffer)
+			.with_context(|| format!("Reading {} for its manifest", path.display()))?;
+		if len == 0 {
+			break;
+		}
+		let chunk = &buffer[..len];
+		hasher.update(chunk);
+		bytes += len as u64;
+		lines += chunk.iter().filter(|b| **b == b'\n').count() as u64;
+	}
+	let digest = hasher
+		.finalize()
+		.iter()
+		.map(|b| format!("{:02x}", b))
+		.collect();
+
+	Ok((digest, bytes, lines))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn checksum_counts_bytes_and_lines() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("journal-2026-10-14T08:15:00Z.log");
+		std::fs::write(&path, "abc\n").unwrap();
+
+		let (digest, bytes, lines) = checksum::<sha2::Sha256>(&path).unwrap();
+		// sha256sum of the same content
+		assert_eq!(
+			digest,
+			"edeaaff3f1774ad2888673770c6d64097e391bc362d7d6fb34982ddf0efd18cb"
+		);
+		assert_eq!((bytes, lines), (4, 1));
+	}
+}
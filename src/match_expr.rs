@@ -0,0 +1,115 @@
+use std::convert::TryFrom;
+
+use anyhow::{bail, Context, Result};
+use journald::reader::JournalReader;
+
+/// Journal matches in `journalctl` syntax, e.g. `_SYSTEMD_UNIT=sshd.service + _TRANSPORT=kernel`.
+///
+/// Matches of one group are combined as the journal does: matches of different fields have
+/// to apply all, matches of the same field are alternatives. `+` separates alternative
+/// groups.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MatchExpression {
+	source: String,
+	groups: Vec<Vec<String>>,
+}
+
+impl MatchExpression {
+	/// Add the matches to the reader, followed by a conjunction so that matches added
+	/// afterwards apply to all groups.
+	pub(crate) fn apply(&self, reader: &mut JournalReader) -> Result<()> {
+		for (i, group) in self.groups.iter().enumerate() {
+			if i > 0 {
+				reader
+					.add_disjunction()
+					.context("Adding match_expression disjunction")?;
+			}
+			for filter in group {
+				reader
+					.add_filter(filter)
+					.with_context(|| format!("Adding match {}", filter))?;
+			}
+		}
+		reader
+			.add_conjunction()
+			.context("Adding match_expression conjunction")?;
+
+		Ok(())
+	}
+
+	/// Values the field is matched against, if every group matches it, so the expression
+	/// only lets through entries with one of them.
+	pub(crate) fn values_of(&self, field: &str) -> Option<Vec<&str>> {
+		let mut values = Vec::new();
+		for group in &self.groups {
+			let before = values.len();
+			values.extend(
+				group
+					.iter()
+					.filter_map(|filter| filter.strip_prefix(field)?.strip_prefix('=')),
+			);
+			if values.len() == before {
+				return None;
+			}
+		}
+
+		Some(values)
+	}
+
+	/// Index of the first group the entry matches, evaluated as the journal does.
+	pub(crate) fn matching_group(&self, entry: &journald::JournalEntry) -> Option<usize> {
+		self.groups.iter().position(|group| {
+			group.iter().all(|filter| {
+				let field = &filter[..filter.find('=').unwrap()];
+				// any of the alternatives for the same field
+				group
+					.iter()
+					.filter_map(|other| other.strip_prefix(field)?.strip_prefix('='))
+					.any(|value| entry.get_field(field) == Some(value))
+			})
+		})
+	}
+}
+
+impl TryFrom<String> for MatchExpression {
+	type Error = anyhow::Error;
+
+	fn try_from(source: String) -> Result<Self> {
+		let mut groups = vec![Vec::new()];
+		for token in source.split_whitespace() {
+			if token == "+" {
+				if groups.last().map_or(true, Vec::is_empty) {
+					bail!("Empty match group before + in match_expression");
+				}
+				groups.push(Vec::new());
+				continue;
+			}
+
+			let field = match token.find('=') {
+				Some(i) => &token[..i],
+				None => bail!("Expected FIELD=VALUE in match_expression, got {}", token),
+			};
+			let valid = !field.is_empty()
+				&& !field.starts_with(|c: char| c.is_ascii_digit())
+				&& field
+					.chars()
+					.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+			if !valid {
+				bail!("Invalid field name {:?} in match_expression", field);
+			}
+			groups.last_mut().unwrap().push(token.to_string());
+		}
+		if groups.last().map_or(true, Vec::is_empty) {
+			bail!("Empty match group in match_expression");
+		}
+
+		Ok(Self { source, groups })
+	}
+}
+
+impl From<MatchExpression> for String {
+	fn from(expression: MatchExpression) -> Self {
+		expression.source
+	}
+}
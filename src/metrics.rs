@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::*;
+
+/// A named, process wide counter.
+pub(crate) struct Counter {
+	name: &'static str,
+	value: AtomicU64,
+}
+
+impl Counter {
+	const fn new(name: &'static str) -> Self {
+		Self {
+			name,
+			value: AtomicU64::new(0),
+		}
+	}
+
+	pub(crate) fn inc(&self) {
+		self.value.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn dec(&self) {
+		self.value.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn get(&self) -> u64 {
+		self.value.load(Ordering::Relaxed)
+	}
+
+	pub(crate) fn name(&self) -> &'static str {
+		self.name
+	}
+}
+
+/// Number of entries written to all sinks
+pub(crate) static ENTRIES_WRITTEN: Counter = Counter::new("entries_written");
+/// Number of entries skipped, e.g. because of `on_error: skip`
+pub(crate) static ENTRIES_SKIPPED: Counter = Counter::new("entries_skipped");
+/// Number of currently open routed writers
+pub(crate) static OPEN_WRITERS: Counter = Counter::new("open_writers");
+/// Number of routed writers closed because `max_open_writers` was reached
+pub(crate) static WRITER_EVICTIONS: Counter = Counter::new("writer_evictions");
+
+/// Number of lines dropped while no process read from a `fifo` sink
+pub(crate) static FIFO_DROPPED: Counter = Counter::new("fifo_dropped");
+
+/// Number of failed writes to sinks with `on_error: skip`
+pub(crate) static SINK_FAILURES: Counter = Counter::new("sink_failures");
+
+/// Number of entries skipped by `dedup_on_reconnect`, `reread_entries`, `reread_window` and
+/// `dedup_cache_size`
+pub(crate) static DUPLICATES_SKIPPED: Counter = Counter::new("duplicates_skipped");
+
+/// Number of entries left out by `change_only_fields` as no value changed
+pub(crate) static UNCHANGED_SKIPPED: Counter = Counter::new("unchanged_skipped");
+
+/// Number of entries whose write took longer than the `write_latency` threshold
+pub(crate) static SLOW_WRITES: Counter = Counter::new("slow_writes");
+
+/// Number of entries read without `__CURSOR`, see `on_missing_cursor`
+pub(crate) static MISSING_CURSORS: Counter = Counter::new("missing_cursors");
+
+/// Number of entries larger than `max_entry_bytes` skipped or truncated, see
+/// `on_oversized_entry`
+pub(crate) static OVERSIZED_SKIPPED: Counter = Counter::new("oversized_skipped");
+pub(crate) static OVERSIZED_TRUNCATED: Counter = Counter::new("oversized_truncated");
+
+static ALL: [&Counter; 12] = [
+	&ENTRIES_WRITTEN,
+	&ENTRIES_SKIPPED,
+	&OPEN_WRITERS,
+	&WRITER_EVICTIONS,
+	&FIFO_DROPPED,
+	&SINK_FAILURES,
+	&DUPLICATES_SKIPPED,
+	&UNCHANGED_SKIPPED,
+	&SLOW_WRITES,
+	&MISSING_CURSORS,
+	&OVERSIZED_SKIPPED,
+	&OVERSIZED_TRUNCATED,
+];
+
+/// Counters of entries or lines not forwarded, reported by `suppression_reports`
+pub(crate) static SUPPRESSED: [&Counter; 5] = [
+	&ENTRIES_SKIPPED,
+	&DUPLICATES_SKIPPED,
+	&UNCHANGED_SKIPPED,
+	&FIFO_DROPPED,
+	&SINK_FAILURES,
+];
+
+/// Current value of all counters.
+pub(crate) fn snapshot() -> Vec<(&'static str, u64)> {
+	ALL.iter().map(|c| (c.name, c.get())).collect()
+}
+
+pub(crate) fn log_summary() {
+	let summary: Vec<String> = snapshot()
+		.into_iter()
+		.map(|(name, value)| format!("{}={}", name, value))
+		.collect();
+	info!("metrics: {}", summary.join(" "));
+}
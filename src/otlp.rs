@@ -0,0 +1,76 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::http::{Envelope, HttpConfig, HttpSink};
+use crate::writer::{self, Record};
+
+/// Wraps the log records of a batch into an `ExportLogsServiceRequest`
+const ENVELOPE: Envelope = Envelope {
+	prefix: r#"{"resourceLogs":[{"resource":{},"scopeLogs":[{"scope":{"name":"journald-writer"},"logRecords":["#,
+	separator: ",",
+	suffix: "]}]}]}",
+};
+
+/// An HTTP sink POSTing batches as OTLP/HTTP requests in the JSON encoding, e.g. to
+/// `http://collector:4318/v1/logs`.
+pub(crate) fn sink(mut config: HttpConfig) -> HttpSink {
+	config.content_type = "application/json".to_string();
+	HttpSink::with_envelope(config, ENVELOPE)
+}
+
+/// The message and the other fields of the json format of the record, the attributes of
+/// its `LogRecord`.
+pub(crate) fn body_and_attributes(
+	record: &Record<'_>,
+	config: &Config,
+) -> (String, serde_json::Map<String, serde_json::Value>) {
+	let mut fields = writer::record_fields(record, config);
+	fields.remove("timestamp");
+	let body = match fields.remove("MESSAGE") {
+		Some(serde_json::Value::String(message)) => message,
+		_ => record.entry.get_message().unwrap_or("").to_string(),
+	};
+
+	(body, fields)
+}
+
+/// Write the record as OTLP `LogRecord`: the message as body, `PRIORITY` as severity and
+/// the other fields of the json format as attributes.
+pub(crate) fn write_log_record<W: Write>(
+	record: &Record<'_>,
+	writer: &mut W,
+	config: &Config,
+) -> Result<()> {
+	let (body, fields) = body_and_attributes(record, config);
+	let attributes: Vec<_> = fields
+		.into_iter()
+		.map(|(key, value)| serde_json::json!({ "key": key, "value": any_value(value) }))
+		.collect();
+
+	let log_record = serde_json::json!({
+		"timeUnixNano": (record.timestamp_us * 1000).to_string(),
+		"observedTimeUnixNano": (record.received_us * 1000).to_string(),
+		"severityNumber": record.priority.otel_severity(),
+		"severityText": record.priority.keyword(),
+		"body": { "stringValue": body },
+		"attributes": attributes,
+	});
+	serde_json::to_writer(writer, &log_record).context("Serializing OTLP log record")?;
+
+	Ok(())
+}
+
+/// The value as OTLP `AnyValue`, 64 bit integers are encoded as strings.
+fn any_value(value: serde_json::Value) -> serde_json::Value {
+	match value {
+		serde_json::Value::String(value) => serde_json::json!({ "stringValue": value }),
+		serde_json::Value::Bool(value) => serde_json::json!({ "boolValue": value }),
+		serde_json::Value::Number(number) => match number.as_i64() {
+			Some(value) => serde_json::json!({ "intValue": value.to_string() }),
+			None => serde_json::json!({ "doubleValue": number }),
+		},
+		value => serde_json::json!({ "stringValue": value.to_string() }),
+	}
+}
@@ -0,0 +1,321 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::*;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{Ascii, MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::config::Config;
+use crate::http::{self, HttpConfig};
+use crate::otlp::body_and_attributes;
+use crate::writer::Record;
+
+/// The record as protobuf `LogRecord`, mapped as by `otlp::write_log_record`.
+pub(crate) fn log_record(record: &Record<'_>, config: &Config) -> proto::LogRecord {
+	let (body, fields) = body_and_attributes(record, config);
+	let attributes = fields
+		.into_iter()
+		.map(|(key, value)| proto::KeyValue {
+			key,
+			value: Some(proto_any_value(value)),
+		})
+		.collect();
+
+	proto::LogRecord {
+		time_unix_nano: (record.timestamp_us.max(0) as u64) * 1000,
+		observed_time_unix_nano: (record.received_us.max(0) as u64) * 1000,
+		severity_number: record.priority.otel_severity().into(),
+		severity_text: record.priority.keyword().to_string(),
+		body: Some(proto::AnyValue {
+			value: Some(proto::any_value::Value::StringValue(body)),
+		}),
+		attributes,
+	}
+}
+
+fn proto_any_value(value: serde_json::Value) -> proto::AnyValue {
+	use proto::any_value::Value;
+
+	let value = match value {
+		serde_json::Value::String(value) => Value::StringValue(value),
+		serde_json::Value::Bool(value) => Value::BoolValue(value),
+		serde_json::Value::Number(number) => match number.as_i64() {
+			Some(value) => Value::IntValue(value),
+			None => Value::DoubleValue(number.as_f64().unwrap_or_default()),
+		},
+		value => Value::StringValue(value.to_string()),
+	};
+
+	proto::AnyValue { value: Some(value) }
+}
+
+/// Method of the OTLP/gRPC logs service
+const EXPORT_PATH: &str = "/opentelemetry.proto.collector.logs.v1.LogsService/Export";
+
+/// Batches log records into an `ExportLogsServiceRequest` sent to an OTLP/gRPC collector,
+/// e.g. `http://collector:4317`, with the batching and retries of the http sink.
+///
+/// The `headers` are sent as request metadata. Like a POST, an export is retried until the
+/// collector acknowledges it, so the cursor never covers entries that weren't exported.
+pub(crate) struct GrpcSink {
+	config: HttpConfig,
+	/// Runs the client on the thread of the sink
+	runtime: tokio::runtime::Runtime,
+	client: tonic::client::Grpc<Channel>,
+	metadata: MetadataMap,
+	batch: Vec<proto::LogRecord>,
+	last_export: Instant,
+	/// See `HttpSink::with_write_timeout`
+	flush_limit: Option<Duration>,
+}
+
+impl GrpcSink {
+	pub(crate) fn new(config: HttpConfig, write_timeout: Option<Duration>) -> Result<Self> {
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()
+			.context("Starting the gRPC runtime")?;
+		let mut endpoint = Endpoint::from_shared(config.url.clone())
+			.with_context(|| format!("Invalid gRPC endpoint {}", config.url))?
+			.connect_timeout(config.timeout)
+			.timeout(config.timeout);
+		if config.url.starts_with("https://") {
+			endpoint = endpoint
+				.tls_config(ClientTlsConfig::new())
+				.context("Configuring TLS")?;
+		}
+		// connected with the first export, and again after the connection failed
+		let channel = {
+			let _runtime = runtime.enter();
+			endpoint.connect_lazy()
+		};
+
+		let mut metadata = MetadataMap::new();
+		for (name, value) in &config.headers {
+			let key = MetadataKey::<Ascii>::from_bytes(name.as_bytes())
+				.with_context(|| format!("Invalid gRPC metadata key {:?}", name))?;
+			let value: MetadataValue<Ascii> = value
+				.parse()
+				.with_context(|| format!("Invalid value of gRPC metadata {:?}", name))?;
+			metadata.insert(key, value);
+		}
+
+		Ok(Self {
+			config,
+			runtime,
+			client: tonic::client::Grpc::new(channel),
+			metadata,
+			batch: Vec::new(),
+			last_export: Instant::now(),
+			flush_limit: write_timeout,
+		})
+	}
+
+	/// Add a log record to the batch, and export it if it is full or due.
+	pub(crate) fn write(&mut self, log_record: proto::LogRecord) -> Result<()> {
+		self.batch.push(log_record);
+		if self.batch.len() >= self.config.batch_size.max(1)
+			|| self.last_export.elapsed() >= self.config.flush_interval
+		{
+			self.flush()?;
+		}
+
+		Ok(())
+	}
+
+	/// Export the buffered log records, if any.
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		self.flush_before(None)
+	}
+
+	/// See `HttpSink::flush_before`.
+	pub(crate) fn flush_before(&mut self, deadline: Option<Instant>) -> Result<()> {
+		if self.batch.is_empty() {
+			return Ok(());
+		}
+
+		let Self {
+			config,
+			runtime,
+			client,
+			metadata,
+			batch,
+			flush_limit,
+			..
+		} = self;
+		let request = proto::ExportLogsServiceRequest {
+			resource_logs: vec![proto::ResourceLogs {
+				resource: Some(proto::Resource::default()),
+				scope_logs: vec![proto::ScopeLogs {
+					scope: Some(proto::InstrumentationScope {
+						name: "journald-writer".to_string(),
+						version: String::new(),
+					}),
+					log_records: std::mem::take(batch),
+				}],
+			}],
+		};
+		let sent = http::send_with_retries(config, *flush_limit, deadline, || {
+			export(runtime, client, metadata, &request, &config.url)
+		});
+		let entries = request.resource_logs[0].scope_logs[0].log_records.len();
+		self.last_export = Instant::now();
+		match sent {
+			Ok(()) => debug!("exported {} entries to {}", entries, self.config.url),
+			Err(e) => {
+				// the batch was taken, so it doesn't grow without bound
+				warn!(
+					"dropping {} entries not acknowledged by {}",
+					entries, self.config.url
+				);
+				return Err(e);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Whether log records are buffered that still have to be exported.
+	pub(crate) fn has_pending(&self) -> bool {
+		!self.batch.is_empty()
+	}
+}
+
+/// Send one `Export` call and wait for its response.
+fn export(
+	runtime: &tokio::runtime::Runtime,
+	client: &mut tonic::client::Grpc<Channel>,
+	metadata: &MetadataMap,
+	request: &proto::ExportLogsServiceRequest,
+	url: &str,
+) -> Result<()> {
+	let mut request = tonic::Request::new(request.clone());
+	*request.metadata_mut() = metadata.clone();
+	let codec = tonic::codec::ProstCodec::<
+		proto::ExportLogsServiceRequest,
+		proto::ExportLogsServiceResponse,
+	>::default();
+	let response = runtime
+		.block_on(async {
+			client
+				.ready()
+				.await
+				.map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+			client
+				.unary(request, PathAndQuery::from_static(EXPORT_PATH), codec)
+				.await
+		})
+		.with_context(|| format!("Export to {}", url))?;
+
+	// rejected records aren't accepted with a retry either
+	if let Some(partial) = response.into_inner().partial_success {
+		if partial.rejected_log_records > 0 {
+			warn!(
+				"{} rejected {} entries: {}",
+				url, partial.rejected_log_records, partial.error_message
+			);
+		}
+	}
+
+	Ok(())
+}
+
+/// The messages of `opentelemetry/proto/collector/logs/v1/logs_service.proto` and its imports
+/// used by the sink, with their field numbers.
+pub(crate) mod proto {
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct ExportLogsServiceRequest {
+		#[prost(message, repeated, tag = "1")]
+		pub resource_logs: Vec<ResourceLogs>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct ExportLogsServiceResponse {
+		#[prost(message, optional, tag = "1")]
+		pub partial_success: Option<ExportLogsPartialSuccess>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct ExportLogsPartialSuccess {
+		#[prost(int64, tag = "1")]
+		pub rejected_log_records: i64,
+		#[prost(string, tag = "2")]
+		pub error_message: String,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct ResourceLogs {
+		#[prost(message, optional, tag = "1")]
+		pub resource: Option<Resource>,
+		#[prost(message, repeated, tag = "2")]
+		pub scope_logs: Vec<ScopeLogs>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct Resource {
+		#[prost(message, repeated, tag = "1")]
+		pub attributes: Vec<KeyValue>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct ScopeLogs {
+		#[prost(message, optional, tag = "1")]
+		pub scope: Option<InstrumentationScope>,
+		#[prost(message, repeated, tag = "2")]
+		pub log_records: Vec<LogRecord>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct InstrumentationScope {
+		#[prost(string, tag = "1")]
+		pub name: String,
+		#[prost(string, tag = "2")]
+		pub version: String,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct LogRecord {
+		#[prost(fixed64, tag = "1")]
+		pub time_unix_nano: u64,
+		#[prost(fixed64, tag = "11")]
+		pub observed_time_unix_nano: u64,
+		/// `SeverityNumber`
+		#[prost(int32, tag = "2")]
+		pub severity_number: i32,
+		#[prost(string, tag = "3")]
+		pub severity_text: String,
+		#[prost(message, optional, tag = "5")]
+		pub body: Option<AnyValue>,
+		#[prost(message, repeated, tag = "6")]
+		pub attributes: Vec<KeyValue>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct KeyValue {
+		#[prost(string, tag = "1")]
+		pub key: String,
+		#[prost(message, optional, tag = "2")]
+		pub value: Option<AnyValue>,
+	}
+
+	#[derive(Clone, PartialEq, prost::Message)]
+	pub struct AnyValue {
+		#[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+		pub value: Option<any_value::Value>,
+	}
+
+	pub mod any_value {
+		#[derive(Clone, PartialEq, prost::Oneof)]
+		pub enum Value {
+			#[prost(string, tag = "1")]
+			StringValue(String),
+			#[prost(bool, tag = "2")]
+			BoolValue(bool),
+			#[prost(int64, tag = "3")]
+			IntValue(i64),
+			#[prost(double, tag = "4")]
+			DoubleValue(f64),
+		}
+	}
+}
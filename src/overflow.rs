@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::*;
+use nix::libc;
+
+use crate::budget::Budget;
+use crate::index::Index;
+use crate::router::Output;
+use crate::writer::Record;
+
+/// Secondary directory of a files sink, written to while its target directory is full or
+/// read-only, see `overflow_dir`. It has its own `max_total_bytes` budget and index.
+pub(crate) struct Overflow {
+	output: Output,
+	dir: PathBuf,
+	budget: Option<Budget>,
+	index: Option<Index>,
+	/// How often writing to the primary directory is tried again while overflowing
+	retry_interval: Duration,
+	/// Entries are currently written to the overflow directory
+	active: bool,
+	last_attempt: Instant,
+}
+
+impl Overflow {
+	pub(crate) fn new(
+		output: Output,
+		dir: PathBuf,
+		budget: Option<Budget>,
+		index: Option<Index>,
+		retry_interval: Duration,
+	) -> Self {
+		Self {
+			output,
+			dir,
+			budget,
+			index,
+			retry_interval,
+			active: false,
+			last_attempt: Instant::now(),
+		}
+	}
+
+	/// Whether to write to the primary directory, which is tried again every
+	/// `overflow_retry_interval` while overflowing.
+	pub(crate) fn use_primary(&mut self) -> bool {
+		if self.active && self.last_attempt.elapsed() < self.retry_interval {
+			return false;
+		}
+		self.last_attempt = Instant::now();
+		true
+	}
+
+	/// Record a successful write to the primary directory.
+	pub(crate) fn primary_recovered(&mut self, name: &str) {
+		if self.active {
+			info!(
+				"{} is writable again, switching back from {}",
+				name,
+				self.dir.display()
+			);
+			self.active = false;
+		}
+	}
+
+	/// Switch to the overflow directory, as the primary failed with `error`.
+	pub(crate) fn activate(&mut self, name: &str, error: &anyhow::Error) {
+		if !self.active {
+			warn!(
+				"{} failed, switching to {}: {:#}",
+				name,
+				self.dir.display(),
+				error
+			);
+			self.active = true;
+		}
+	}
+
+	pub(crate) fn write(&mut self, record: &Record<'_>, data: &[u8], flush: bool) -> Result<()> {
+		crate::sink::write_files(
+			&mut self.output,
+			&mut self.budget,
+			&mut self.index,
+			record,
+			data,
+			flush,
+		)
+		.with_context(|| format!("Writing to {}", self.dir.display()))
+	}
+
+	/// Account for `bytes` of a lifecycle event about to be written to the overflow output.
+	pub(crate) fn reserve(&mut self, bytes: u64) -> Result<()> {
+		match self.budget.as_mut() {
+			Some(budget) => budget.reserve(bytes),
+			None => Ok(()),
+		}
+	}
+
+	pub(crate) fn output(&mut self) -> &mut Output {
+		&mut self.output
+	}
+
+	/// Whether entries are currently written to the overflow directory.
+	pub(crate) fn is_active(&self) -> bool {
+		self.active
+	}
+
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		self.output.flush()
+	}
+
+	pub(crate) fn reopen(&mut self) -> Result<()> {
+		self.output.reopen()
+	}
+
+	pub(crate) fn start_boot(&mut self, boot: String) -> Result<()> {
+		self.output.start_boot(boot)
+	}
+
+	pub(crate) fn take_opened(&mut self) -> Vec<(PathBuf, String)> {
+		self.output.take_opened()
+	}
+}
+
+/// Whether the error was caused by a full or read-only file system.
+pub(crate) fn is_disk_full(error: &anyhow::Error) -> bool {
+	error.chain().any(|e| {
+		e.downcast_ref::<std::io::Error>()
+			.and_then(std::io::Error::raw_os_error)
+			.map_or(false, |code| code == libc::ENOSPC || code == libc::EROFS)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn disk_full_through_the_context() {
+		let full = anyhow::Error::new(std::io::Error::from_raw_os_error(libc::ENOSPC))
+			.context("Flushing writer");
+		assert!(is_disk_full(&full));
+		let read_only = anyhow::Error::new(std::io::Error::from_raw_os_error(libc::EROFS));
+		assert!(is_disk_full(&read_only));
+		let denied = anyhow::Error::new(std::io::Error::from_raw_os_error(libc::EACCES));
+		assert!(!is_disk_full(&denied));
+	}
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Context, Result};
+use log::*;
+
+use crate::status;
+use crate::writer;
+
+enum Message {
+	Cursor {
+		cursor: String,
+		seq: Option<u64>,
+	},
+	/// Answered once all cursors sent before are written, with the first error since the
+	/// previous flush
+	Flush(Sender<Result<()>>),
+}
+
+/// Writes the cursor on a background thread, see `async_cursor`.
+///
+/// Cursors published while a write is in progress are coalesced, so only the newest one is
+/// written next.
+#[derive(Debug)]
+pub(crate) struct CursorPersister {
+	sender: Option<Sender<Message>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl CursorPersister {
+	pub(crate) fn spawn(cursor_file: PathBuf) -> Result<Self> {
+		let (sender, receiver) = mpsc::channel();
+		let thread = thread::Builder::new()
+			.name("cursor".to_string())
+			.spawn(move || persist(&cursor_file, receiver))
+			.context("Spawning cursor thread")?;
+
+		Ok(Self {
+			sender: Some(sender),
+			thread: Some(thread),
+		})
+	}
+
+	/// Have the cursor written, together with its `forwarder_seq`, without waiting.
+	pub(crate) fn publish(&self, cursor: &str, seq: Option<u64>) -> Result<()> {
+		self.send(Message::Cursor {
+			cursor: cursor.to_string(),
+			seq,
+		})
+	}
+
+	/// Wait until all published cursors are written.
+	pub(crate) fn flush(&self) -> Result<()> {
+		let (sender, receiver) = mpsc::channel();
+		self.send(Message::Flush(sender))?;
+		receiver
+			.recv()
+			.map_err(|_| anyhow!("The cursor thread exited"))?
+	}
+
+	fn send(&self, message: Message) -> Result<()> {
+		self.sender
+			.as_ref()
+			.and_then(|sender| sender.send(message).ok())
+			.ok_or_else(|| anyhow!("The cursor thread exited"))
+	}
+}
+
+impl Drop for CursorPersister {
+	fn drop(&mut self) {
+		// the thread writes the last cursor and exits once the channel is closed
+		self.sender = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+fn persist(cursor_file: &Path, receiver: Receiver<Message>) {
+	let mut error = None;
+	while let Ok(message) = receiver.recv() {
+		let mut latest = None;
+		let mut flushes = Vec::new();
+		for message in std::iter::once(message).chain(receiver.try_iter()) {
+			match message {
+				Message::Cursor { cursor, seq } => latest = Some((cursor, seq)),
+				Message::Flush(reply) => flushes.push(reply),
+			}
+		}
+
+		if let Some((cursor, seq)) = latest {
+			let written = writer::write_cursor(&cursor, cursor_file).and_then(|_| match seq {
+				Some(seq) => writer::write_seq(seq, &cursor, cursor_file),
+				None => Ok(()),
+			});
+			match written {
+				Ok(()) => status::set_cursor(&cursor),
+				Err(e) => {
+					warn!("failed to persist cursor: {:#}", e);
+					error.get_or_insert(e);
+				}
+			}
+		}
+		for reply in flushes {
+			let result = match error.take() {
+				Some(e) => Err(e),
+				None => Ok(()),
+			};
+			let _ = reply.send(result);
+		}
+	}
+}
@@ -0,0 +1,47 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::*;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+
+/// A file containing our PID, removed again when dropped.
+pub(crate) struct PidFile {
+	path: PathBuf,
+}
+
+impl PidFile {
+	pub(crate) fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+		let path = path.as_ref().to_path_buf();
+
+		if let Ok(old) = fs::read_to_string(&path) {
+			match old.trim().parse::<i32>() {
+				Ok(pid)
+					if pid as u32 != std::process::id()
+						&& kill(Pid::from_raw(pid), None).is_ok() =>
+				{
+					bail!(
+						"PID file {} belongs to running process {}",
+						path.display(),
+						pid
+					);
+				}
+				_ => warn!("overwriting stale PID file {}", path.display()),
+			}
+		}
+
+		fs::write(&path, format!("{}\n", std::process::id()))
+			.with_context(|| format!("Writing PID file {}", path.display()))?;
+
+		Ok(Self { path })
+	}
+}
+
+impl Drop for PidFile {
+	fn drop(&mut self) {
+		if let Err(e) = fs::remove_file(&self.path) {
+			warn!("failed to remove PID file {}: {}", self.path.display(), e);
+		}
+	}
+}
@@ -0,0 +1,114 @@
+use std::convert::TryFrom;
+
+use anyhow::{Context, Result};
+use journald::JournalEntry;
+use regex::Regex;
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+/// How redacted values are replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionMethod {
+	/// Replace them with `token`
+	Mask,
+	/// Replace them with a 64 bit xxh3 hash seeded by `salt`, so equal values can still be
+	/// correlated, also across versions. The hash is not cryptographic
+	Hash,
+}
+
+impl Default for RedactionMethod {
+	fn default() -> Self {
+		RedactionMethod::Mask
+	}
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+	/// Fields whose whole value is replaced
+	#[serde(default)]
+	pub fields: Vec<String>,
+	/// Regular expressions replaced within the message, e.g. email addresses
+	#[serde(default)]
+	pub message_patterns: Vec<Pattern>,
+	#[serde(default)]
+	pub method: RedactionMethod,
+	/// Replacement of the `mask` method
+	#[serde(default = "default_token")]
+	pub token: String,
+	/// Seed of the `hash` method, so values can't be guessed by hashing candidates
+	#[serde(default)]
+	pub salt: String,
+}
+
+fn default_token() -> String {
+	"[REDACTED]".to_string()
+}
+
+impl RedactConfig {
+	/// A copy of the entry with the configured fields and patterns replaced, written in all
+	/// formats instead of the entry.
+	pub(crate) fn apply(&self, entry: &JournalEntry) -> JournalEntry {
+		let mut redacted = entry.clone();
+		for field in &self.fields {
+			if let Some(value) = entry.get_field(field) {
+				redacted.set_field(field, &self.replacement(value));
+			}
+		}
+		if !self.message_patterns.is_empty() {
+			if let Some(message) = redacted.get_message() {
+				let mut message = message.to_string();
+				for pattern in &self.message_patterns {
+					message = pattern
+						.regex()
+						.replace_all(&message, |captures: &regex::Captures<'_>| {
+							self.replacement(&captures[0])
+						})
+						.into_owned();
+				}
+				redacted.set_field("MESSAGE", &message);
+			}
+		}
+
+		redacted
+	}
+
+	fn replacement(&self, value: &str) -> String {
+		match self.method {
+			RedactionMethod::Mask => self.token.clone(),
+			RedactionMethod::Hash => {
+				let seed = xxh3_64(self.salt.as_bytes());
+				format!("hash:{:016x}", xxh3_64_with_seed(value.as_bytes(), seed))
+			}
+		}
+	}
+}
+
+/// A regular expression from the config
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern {
+	regex: Regex,
+}
+
+impl Pattern {
+	pub(crate) fn regex(&self) -> &Regex {
+		&self.regex
+	}
+}
+
+impl TryFrom<String> for Pattern {
+	type Error = anyhow::Error;
+
+	fn try_from(source: String) -> Result<Self> {
+		let regex = Regex::new(&source)
+			.with_context(|| format!("Invalid regular expression {:?}", source))?;
+		Ok(Self { regex })
+	}
+}
+
+impl From<Pattern> for String {
+	fn from(pattern: Pattern) -> Self {
+		pattern.regex.as_str().to_string()
+	}
+}
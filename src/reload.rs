@@ -0,0 +1,198 @@
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::*;
+use notify::{RecursiveMode, Watcher};
+
+use crate::config::{self, Config, ConfigFormat};
+
+/// Time without further changes to the config file before it is reloaded, so an editor or
+/// deployment writing it in several steps triggers one reload
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Watches the config file for `watch_config`.
+///
+/// The directory is watched rather than the file, so replacing it by renaming, as editors
+/// and Kubernetes ConfigMaps (through their `..data` symlink) do, is noticed as well.
+pub(crate) struct ConfigWatcher {
+	path: PathBuf,
+	format: Option<ConfigFormat>,
+	/// Stops watching when dropped
+	_watcher: notify::RecommendedWatcher,
+	changes: Receiver<()>,
+	/// Time of the last change not reloaded yet
+	changed_at: Option<Instant>,
+	/// `content_hash` of the file when it was last loaded, changes keeping it are ignored
+	content_hash: u64,
+}
+
+impl ConfigWatcher {
+	/// Watch the file the config was loaded from, `None` without `watch_config` or if it was
+	/// read from stdin.
+	pub(crate) fn new(config: &Config) -> Result<Option<Self>> {
+		if !config.watch_config {
+			return Ok(None);
+		}
+		let source = match &config.source {
+			Some(source) => source.clone(),
+			None => {
+				warn!("not watching config read from stdin for changes");
+				return Ok(None);
+			}
+		};
+
+		let path = source.path;
+		let file_name = path.file_name().map(|name| name.to_os_string());
+		let (sender, changes) = mpsc::channel();
+		let mut watcher =
+			notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+				let event = match event {
+					Ok(event) => event,
+					Err(e) => {
+						warn!("watching config file failed: {}", e);
+						return;
+					}
+				};
+				let relevant = event.paths.iter().any(|changed| match changed.file_name() {
+					Some(name) => {
+						Some(name) == file_name.as_deref()
+							|| name.to_string_lossy().starts_with("..")
+					}
+					None => false,
+				});
+				if relevant {
+					// the receiver is only gone once forwarding stopped
+					let _ = sender.send(());
+				}
+			})
+			.context("Creating config file watcher")?;
+		let dir = match path.parent() {
+			Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+			_ => PathBuf::from("."),
+		};
+		watcher
+			.watch(&dir, RecursiveMode::NonRecursive)
+			.with_context(|| format!("Watching {}", dir.display()))?;
+		info!("watching config file {} for changes", path.display());
+
+		Ok(Some(Self {
+			path,
+			format: source.format,
+			_watcher: watcher,
+			changes,
+			// picks up a change made while restarting after a failure, see `max_restarts`
+			changed_at: Some(Instant::now()),
+			content_hash: source.content_hash,
+		}))
+	}
+
+	/// The changed config, once the file was written and then left alone for `DEBOUNCE`.
+	/// A file failing to parse is reported and skipped, keeping the current config.
+	pub(crate) fn poll(&mut self) -> Option<Config> {
+		while self.changes.try_recv().is_ok() {
+			self.changed_at = Some(Instant::now());
+		}
+		match self.changed_at {
+			Some(changed_at) if changed_at.elapsed() >= DEBOUNCE => self.changed_at = None,
+			_ => return None,
+		}
+
+		let content = match std::fs::read_to_string(&self.path) {
+			Ok(content) => content,
+			Err(e) => {
+				warn!(
+					"not reloading config file {}, reading it failed: {}",
+					self.path.display(),
+					e
+				);
+				return None;
+			}
+		};
+		let hash = content_hash(&content);
+		if hash == self.content_hash {
+			return None;
+		}
+		self.content_hash = hash;
+
+		match config::load(&self.path, self.format) {
+			Ok(config) => {
+				info!("config file {} changed, reloading it", self.path.display());
+				Some(config)
+			}
+			Err(e) => {
+				warn!(
+					"keeping the current config, changed config file {} is invalid: {:#}",
+					self.path.display(),
+					e
+				);
+				None
+			}
+		}
+	}
+}
+
+/// Hash of the content of a config file, before interpolating the environment.
+pub(crate) fn content_hash(content: &str) -> u64 {
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	content.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Take the options of the output, format and entry filters from `reloaded`, the others
+/// are only applied by a restart as they concern the reader, cursor or forwarder state.
+///
+/// `match_expression`, `transports`, `uids` and `gids` are journal matches of the reader
+/// and so are not reloaded either.
+pub(crate) fn apply(config: &mut Config, reloaded: Config) {
+	// output
+	config.log_writer_config = reloaded.log_writer_config;
+	config.sinks = reloaded.sinks;
+	config.bucket_by = reloaded.bucket_by;
+	config.route_by = reloaded.route_by;
+	config.fan_out = reloaded.fan_out;
+	config.max_open_writers = reloaded.max_open_writers;
+	config.max_total_bytes = reloaded.max_total_bytes;
+	config.on_budget_exceeded = reloaded.on_budget_exceeded;
+	config.recreate_target_dir = reloaded.recreate_target_dir;
+	config.rotate_every_n_entries = reloaded.rotate_every_n_entries;
+	config.file_trailer = reloaded.file_trailer;
+	config.file_manifest = reloaded.file_manifest;
+	config.overflow_dir = reloaded.overflow_dir;
+	config.overflow_retry_interval = reloaded.overflow_retry_interval;
+	config.index_interval = reloaded.index_interval;
+
+	// format
+	config.format = reloaded.format;
+	config.max_fields = reloaded.max_fields;
+	config.max_field_bytes = reloaded.max_field_bytes;
+	config.json_pretty = reloaded.json_pretty;
+	config.sort_fields = reloaded.sort_fields;
+	config.field_order = reloaded.field_order;
+	config.csv_columns = reloaded.csv_columns;
+	config.csv_header = reloaded.csv_header;
+	config.pad_identifier = reloaded.pad_identifier;
+	config.identifier_basename = reloaded.identifier_basename;
+	config.lowercase_identifier = reloaded.lowercase_identifier;
+	config.identifier_colons = reloaded.identifier_colons;
+	config.pad_unit = reloaded.pad_unit;
+	config.text_template = reloaded.text_template;
+	config.timestamp_format = reloaded.timestamp_format;
+	config.timezone = reloaded.timezone;
+	config.message_fallback_fields = reloaded.message_fallback_fields;
+	config.include_code_location = reloaded.include_code_location;
+	config.quote_message = reloaded.quote_message;
+	config.ingestion_timestamp = reloaded.ingestion_timestamp;
+	config.include_transport = reloaded.include_transport;
+	config.rich_severity = reloaded.rich_severity;
+	config.message_hash = reloaded.message_hash;
+	config.sanitize_control_chars = reloaded.sanitize_control_chars;
+
+	// entry filters
+	config.message_transforms = reloaded.message_transforms;
+	config.redact = reloaded.redact;
+	config.max_entry_bytes = reloaded.max_entry_bytes;
+	config.on_oversized_entry = reloaded.on_oversized_entry;
+}
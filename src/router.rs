@@ -0,0 +1,768 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use journald::JournalEntry;
+use log::*;
+use log_writer::{LogWriter, LogWriterConfig};
+
+use crate::config::Config;
+use crate::metrics;
+use crate::syslog::Facility;
+use crate::writer::Record;
+
+/// Journal field an entry can be routed by into its own set of files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteBy {
+	/// `SYSLOG_IDENTIFIER`
+	Identifier,
+	/// `SYSLOG_FACILITY`, by name like `auth` or `cron`
+	Facility,
+}
+
+impl RouteBy {
+	/// All route keys entries can have, if `match_expression` restricts the field to a list
+	/// of values.
+	pub(crate) fn static_keys(&self, config: &Config) -> Option<Vec<String>> {
+		let expression = config.match_expression.as_ref()?;
+		let mut keys: Vec<String> = match self {
+			RouteBy::Identifier => expression
+				.values_of("SYSLOG_IDENTIFIER")?
+				.into_iter()
+				.map(sanitize)
+				.collect(),
+			RouteBy::Facility => expression
+				.values_of("SYSLOG_FACILITY")?
+				.into_iter()
+				.map(|code| Facility::from_code(code).map(|facility| facility.name().to_string()))
+				.collect::<Option<_>>()?,
+		};
+		keys.sort();
+		keys.dedup();
+
+		Some(keys)
+	}
+
+	fn key(&self, entry: &JournalEntry, default_facility: Facility) -> String {
+		let value = match self {
+			RouteBy::Identifier => entry.get_field("SYSLOG_IDENTIFIER"),
+			RouteBy::Facility => Some(Facility::of(entry).unwrap_or(default_facility).name()),
+		};
+		sanitize(value.unwrap_or(""))
+	}
+}
+
+/// How entries are spread over the files of `fan_out`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FanOutMode {
+	/// One entry after the other, without any order between the files
+	RoundRobin,
+	/// By the hash of the unit, keeping the entries of a unit in order in one file
+	Hash,
+}
+
+impl Default for FanOutMode {
+	fn default() -> Self {
+		FanOutMode::RoundRobin
+	}
+}
+
+/// Spread the entries over this many sets of files, for readers consuming them in parallel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanOut {
+	pub files: usize,
+	#[serde(default)]
+	pub mode: FanOutMode,
+}
+
+/// Hash of the entry's unit, falling back to its identifier, as used by `fan_out: hash` and
+/// `shard_count`. xxh3 is stable across releases, so units keep their files and shards after
+/// an update.
+pub(crate) fn unit_hash(entry: &JournalEntry, unit_field: &str) -> u64 {
+	let unit = entry
+		.get_field(unit_field)
+		.or_else(|| entry.get_field("SYSLOG_IDENTIFIER"))
+		.unwrap_or("");
+	xxhash_rust::xxh3::xxh3_64(unit.as_bytes())
+}
+
+/// Which time selects the partition of an entry, see `bucket_by`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketBy {
+	/// The timestamp of the entry, so entries forwarded late still land in their partition
+	EventTime,
+	/// The time the entry is forwarded
+	WriteTime,
+}
+
+impl Default for BucketBy {
+	fn default() -> Self {
+		BucketBy::EventTime
+	}
+}
+
+/// Placeholders in `target_dir` and `prefix`, replaced with the UTC time of the entry to
+/// partition the output
+const PARTITION_PLACEHOLDERS: [(&str, &str); 4] = [
+	("{year}", "%Y"),
+	("{month}", "%m"),
+	("{day}", "%d"),
+	("{hour}", "%H"),
+];
+
+/// Where log lines are written to.
+pub(crate) enum Output {
+	Single {
+		writer: LogWriter,
+		config: LogWriterConfig,
+		header: Option<Vec<u8>>,
+		/// The writer was opened since the last `take_opened`
+		opened: bool,
+		/// Entries written to the current file, for `rotate_every_n_entries`
+		entries: u64,
+		/// The previous file was closed since the last `take_closed`
+		closed: bool,
+		/// The target directory was recreated since the last `take_recreated`
+		recreated: bool,
+	},
+	Routed(RoutedWriters),
+}
+
+impl Output {
+	pub(crate) fn new(
+		config: LogWriterConfig,
+		route: Route,
+		max_open_writers: usize,
+		header: Option<Vec<u8>>,
+	) -> Result<Self> {
+		let partitioned = is_partitioned(&config);
+		if route.route_by.is_none() && route.fan_out.is_none() && !route.per_boot && !partitioned {
+			let writer = open_single(&config, &header)?;
+			return Ok(Output::Single {
+				writer,
+				config,
+				header,
+				opened: true,
+				entries: 0,
+				closed: false,
+				recreated: false,
+			});
+		}
+
+		Ok(Output::Routed(RoutedWriters::new(
+			config,
+			route,
+			partitioned,
+			max_open_writers,
+			header,
+		)))
+	}
+
+	/// Select the writer the given entry has to be written to.
+	pub(crate) fn writer_for(&mut self, record: &Record<'_>) -> Result<&mut LogWriter> {
+		match self {
+			Output::Single { writer, .. } => Ok(writer),
+			Output::Routed(routed) => routed.writer_for(record),
+		}
+	}
+
+	/// Directory and file name prefix of the writer the next call of `writer_for` selects for
+	/// the given entry.
+	pub(crate) fn location_for(&self, record: &Record<'_>) -> (PathBuf, String) {
+		match self {
+			Output::Single { config, .. } => (config.target_dir.clone(), config.prefix.clone()),
+			Output::Routed(routed) => routed.key_for(record),
+		}
+	}
+
+	/// Select the writer for output not belonging to an entry, like lifecycle events.
+	pub(crate) fn writer_at(&mut self, time: &DateTime<Utc>) -> Result<&mut LogWriter> {
+		match self {
+			Output::Single { writer, .. } => Ok(writer),
+			Output::Routed(routed) => {
+				let dir = routed.dir_for(time);
+				let route = routed.with_part(routed.with_boot(String::new()), 0);
+				let prefix = routed.prefix_at(&route, time);
+				routed.writer_for_key((dir, prefix))
+			}
+		}
+	}
+
+	/// Flush all open writers.
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		match self {
+			Output::Single { writer, .. } => writer.flush().context("Flushing writer"),
+			Output::Routed(routed) => {
+				for ((dir, prefix), (_, writer)) in routed.writers.iter_mut() {
+					writer.flush().with_context(|| {
+						format!("Flushing writer for {} {}", dir.display(), prefix)
+					})?;
+				}
+				Ok(())
+			}
+		}
+	}
+
+	/// Directory and file name prefix of the writers opened since the last call.
+	pub(crate) fn take_opened(&mut self) -> Vec<(PathBuf, String)> {
+		match self {
+			Output::Single { config, opened, .. } => {
+				if std::mem::take(opened) {
+					vec![(config.target_dir.clone(), config.prefix.clone())]
+				} else {
+					Vec::new()
+				}
+			}
+			Output::Routed(routed) => std::mem::take(&mut routed.opened),
+		}
+	}
+
+	/// Directory and file name prefix of the writers whose file was flushed and closed since
+	/// the last call, by rotation, `reopen`, `start_boot`, eviction or a vanished directory.
+	pub(crate) fn take_closed(&mut self) -> Vec<(PathBuf, String)> {
+		match self {
+			Output::Single { config, closed, .. } => {
+				if std::mem::take(closed) {
+					vec![(config.target_dir.clone(), config.prefix.clone())]
+				} else {
+					Vec::new()
+				}
+			}
+			Output::Routed(routed) => std::mem::take(&mut routed.closed),
+		}
+	}
+
+	/// Whether a vanished target directory was recreated since the last call, losing the
+	/// entries written to its files.
+	pub(crate) fn take_recreated(&mut self) -> bool {
+		match self {
+			Output::Single { recreated, .. } => std::mem::take(recreated),
+			Output::Routed(routed) => std::mem::take(&mut routed.recreated),
+		}
+	}
+
+	/// Flush and close all writers, and open new files, after external rotation moved the
+	/// current ones away.
+	pub(crate) fn reopen(&mut self) -> Result<()> {
+		self.flush()?;
+		match self {
+			Output::Single {
+				writer,
+				config,
+				header,
+				opened,
+				entries,
+				closed,
+				..
+			} => {
+				*writer = open_single(config, header)?;
+				*opened = true;
+				*entries = 0;
+				*closed = true;
+			}
+			Output::Routed(routed) => {
+				routed.entries.clear();
+				// opened again with the next entry routed to them
+				for (key, _) in routed.writers.drain() {
+					metrics::OPEN_WRITERS.dec();
+					routed.closed.push(key);
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Count an entry written to the file at `location`, and roll over to a new file once it
+	/// holds `max` entries, see `rotate_every_n_entries`. If that fails, it is tried again
+	/// with the next entry.
+	pub(crate) fn count_entry(&mut self, location: &(PathBuf, String), max: u64) -> Result<()> {
+		match self {
+			Output::Single {
+				writer,
+				config,
+				header,
+				opened,
+				entries,
+				closed,
+				..
+			} => {
+				*entries += 1;
+				if *entries >= max {
+					writer.flush().context("Flushing writer")?;
+					*writer = open_single(config, header)?;
+					*opened = true;
+					*entries = 0;
+					*closed = true;
+					debug!("rolled over to a new file after {} entries", max);
+				}
+			}
+			Output::Routed(routed) => {
+				let entries = routed.entries.entry(location.clone()).or_insert(0);
+				*entries += 1;
+				if *entries < max {
+					return Ok(());
+				}
+				let (dir, prefix) = location;
+				if let Some((_, writer)) = routed.writers.get_mut(location) {
+					writer.flush().with_context(|| {
+						format!("Flushing writer for {} {}", dir.display(), prefix)
+					})?;
+				}
+				routed.entries.remove(location);
+				// opened with a new file by the next entry routed to it
+				if routed.writers.remove(location).is_some() {
+					metrics::OPEN_WRITERS.dec();
+					routed.closed.push(location.clone());
+					debug!(
+						"rolled over {} {} to a new file after {} entries",
+						dir.display(),
+						prefix,
+						max
+					);
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Flush and close the writers of the previous boot, and route all following entries into
+	/// the files named `boot`, see `file_per_boot`.
+	pub(crate) fn start_boot(&mut self, boot: String) -> Result<()> {
+		let routed = match self {
+			Output::Routed(routed) if routed.route.per_boot => routed,
+			_ => return Ok(()),
+		};
+		routed.entries.clear();
+		for ((dir, prefix), (_, mut writer)) in routed.writers.drain() {
+			metrics::OPEN_WRITERS.dec();
+			writer
+				.flush()
+				.with_context(|| format!("Flushing writer for {} {}", dir.display(), prefix))?;
+			routed.closed.push((dir, prefix));
+		}
+		debug!("routing entries into the files of boot {}", boot);
+		routed.boot = Some(boot);
+
+		Ok(())
+	}
+
+	/// Open the writers of all given route keys, so their files exist before the first entry
+	/// is routed to them, see `precreate_routes`. Files of time partitions and boots are
+	/// still created on demand.
+	pub(crate) fn precreate(&mut self, keys: &[String]) -> Result<()> {
+		let routed = match self {
+			Output::Routed(routed) if !routed.partitioned && !routed.route.per_boot => routed,
+			_ => return Ok(()),
+		};
+		let parts = routed
+			.route
+			.fan_out
+			.map_or(1, |fan_out| fan_out.files.max(1));
+		for key in keys {
+			for part in 0..parts {
+				let prefix = routed.prefix_for(&routed.with_part(key.clone(), part));
+				let dir = routed.config.target_dir.clone();
+				routed.writer_for_key((dir, prefix))?;
+			}
+		}
+		// there is no entry whose cursor file_events could record
+		routed.opened.clear();
+		debug!("precreated {} routed files", keys.len() * parts);
+
+		Ok(())
+	}
+
+	/// Create the directories of the output again if they vanished at runtime, e.g. with a
+	/// remounted tmpfs, and reopen the writers, which would otherwise keep writing to deleted
+	/// files. Returns whether any directory was recreated.
+	pub(crate) fn recreate_missing_dirs(&mut self) -> Result<bool> {
+		let dirs: Vec<PathBuf> = match self {
+			Output::Single { config, .. } => vec![config.target_dir.clone()],
+			Output::Routed(routed) => {
+				let mut dirs: Vec<PathBuf> =
+					routed.writers.keys().map(|(dir, _)| dir.clone()).collect();
+				if !routed.partitioned {
+					dirs.push(routed.config.target_dir.clone());
+				}
+				dirs.sort();
+				dirs.dedup();
+				dirs
+			}
+		};
+		let missing: Vec<PathBuf> = dirs.into_iter().filter(|dir| !dir.exists()).collect();
+		if missing.is_empty() {
+			return Ok(false);
+		}
+
+		for dir in &missing {
+			warn!("target directory {} vanished, recreating it", dir.display());
+			std::fs::create_dir_all(dir)
+				.with_context(|| format!("Recreating target directory {}", dir.display()))?;
+		}
+		// the old files are gone with their directory, so there is nothing left to flush
+		match self {
+			Output::Single {
+				writer,
+				config,
+				header,
+				opened,
+				entries,
+				closed,
+				recreated,
+			} => {
+				*writer = open_single(config, header)?;
+				*opened = true;
+				*entries = 0;
+				*closed = true;
+				*recreated = true;
+			}
+			Output::Routed(routed) => {
+				routed.entries.clear();
+				for (key, _) in routed.writers.drain() {
+					metrics::OPEN_WRITERS.dec();
+					routed.closed.push(key);
+				}
+				routed.recreated = true;
+			}
+		}
+
+		Ok(true)
+	}
+}
+
+/// Whether the error was caused by a missing file or directory.
+pub(crate) fn is_not_found(error: &anyhow::Error) -> bool {
+	error.chain().any(|e| {
+		e.downcast_ref::<std::io::Error>()
+			.map_or(false, |e| e.kind() == std::io::ErrorKind::NotFound)
+	})
+}
+
+fn open_single(config: &LogWriterConfig, header: &Option<Vec<u8>>) -> Result<LogWriter> {
+	let mut writer = LogWriter::new(config.clone()).with_context(|| {
+		format!(
+			"Creating log writer at path {}",
+			config.target_dir.display()
+		)
+	})?;
+	if let Some(header) = header {
+		writer.write_all(header).context("Writing header")?;
+	}
+
+	Ok(writer)
+}
+
+/// How entries are distributed over the files of an output
+#[derive(Debug, Clone)]
+pub(crate) struct Route {
+	pub(crate) route_by: Option<RouteBy>,
+	/// Route key of entries without facility with `route_by: facility`
+	pub(crate) default_facility: Facility,
+	pub(crate) fan_out: Option<FanOut>,
+	/// Hashed by `fan_out: hash`
+	pub(crate) unit_field: String,
+	pub(crate) bucket_by: BucketBy,
+	/// A separate set of files per boot, see `file_per_boot`
+	pub(crate) per_boot: bool,
+	/// Route each entry into the files of its own boot, instead of those of the boot set by
+	/// `start_boot`, as the boots of several machines interleave without `only_local`
+	pub(crate) boot_of_entry: bool,
+}
+
+/// A bounded set of open writers, one per target directory and file name prefix, which
+/// includes the route key and time partition.
+///
+/// If more than `max_open` writers would be open the least recently used one is closed.
+pub(crate) struct RoutedWriters {
+	config: LogWriterConfig,
+	route: Route,
+	partitioned: bool,
+	max_open: usize,
+	/// File of `fan_out: round_robin` the next entry is written to
+	next_part: usize,
+	/// Written to every newly opened writer, e.g. the column names of csv
+	header: Option<Vec<u8>>,
+	writers: HashMap<(PathBuf, String), (u64, LogWriter)>,
+	tick: u64,
+	/// Keys of the writers opened since the last `take_opened`
+	opened: Vec<(PathBuf, String)>,
+	/// Name of the files of the current boot with `file_per_boot`, set by `start_boot`
+	boot: Option<String>,
+	/// Entries written to the current file of each writer, for `rotate_every_n_entries`
+	entries: HashMap<(PathBuf, String), u64>,
+	/// Keys of the writers closed since the last `take_closed`
+	closed: Vec<(PathBuf, String)>,
+	/// A vanished target directory was recreated since the last `take_recreated`
+	recreated: bool,
+}
+
+impl RoutedWriters {
+	fn new(
+		config: LogWriterConfig,
+		route: Route,
+		partitioned: bool,
+		max_open: usize,
+		header: Option<Vec<u8>>,
+	) -> Self {
+		Self {
+			config,
+			route,
+			partitioned,
+			max_open: max_open.max(1),
+			next_part: 0,
+			header,
+			writers: HashMap::new(),
+			tick: 0,
+			opened: Vec::new(),
+			boot: None,
+			entries: HashMap::new(),
+			closed: Vec::new(),
+			recreated: false,
+		}
+	}
+
+	fn writer_for(&mut self, record: &Record<'_>) -> Result<&mut LogWriter> {
+		let key = self.key_for(record);
+		if let Some(fan_out) = self.route.fan_out {
+			self.next_part = (self.next_part + 1) % fan_out.files.max(1);
+		}
+		self.writer_for_key(key)
+	}
+
+	/// Directory and file name prefix of the writer for the entry.
+	fn key_for(&self, record: &Record<'_>) -> (PathBuf, String) {
+		let time = match self.route.bucket_by {
+			BucketBy::EventTime => record.time_utc(),
+			BucketBy::WriteTime => record.ingested_at,
+		};
+		let dir = self.dir_for(&time);
+		let route = match self.route.route_by {
+			Some(route_by) => route_by.key(record.entry, self.route.default_facility),
+			None => String::new(),
+		};
+		let route = match record.entry.get_field("_BOOT_ID") {
+			Some(_) if self.route.per_boot && self.route.boot_of_entry => {
+				with_boot(&boot_name(record.entry), route)
+			}
+			_ => self.with_boot(route),
+		};
+		let part = match self.route.fan_out {
+			Some(FanOut {
+				files,
+				mode: FanOutMode::Hash,
+			}) => (unit_hash(record.entry, &self.route.unit_field) % files.max(1) as u64) as usize,
+			_ => self.next_part,
+		};
+		(dir, self.prefix_at(&self.with_part(route, part), &time))
+	}
+
+	/// The route key prefixed with the name of the current boot, if any.
+	fn with_boot(&self, route: String) -> String {
+		match &self.boot {
+			Some(boot) => with_boot(boot, route),
+			None => route,
+		}
+	}
+
+	/// The route key of a file of `fan_out`, numbered from 0.
+	fn with_part(&self, route: String, part: usize) -> String {
+		match self.route.fan_out {
+			Some(_) if route.is_empty() => part.to_string(),
+			Some(_) => format!("{}{}{}", route, SEPARATOR, part),
+			None => route,
+		}
+	}
+
+	fn prefix_for(&self, route: &str) -> String {
+		if route.is_empty() {
+			self.config.prefix.clone()
+		} else {
+			format!("{}{}{}", self.config.prefix, route, SEPARATOR)
+		}
+	}
+
+	/// `prefix_for` with the partition placeholders replaced.
+	fn prefix_at(&self, route: &str, time: &DateTime<Utc>) -> String {
+		let prefix = self.prefix_for(route);
+		if self.partitioned {
+			replace_placeholders(&prefix, time)
+		} else {
+			prefix
+		}
+	}
+
+	fn dir_for(&self, time: &DateTime<Utc>) -> PathBuf {
+		if self.partitioned {
+			let dir = self.config.target_dir.to_string_lossy();
+			PathBuf::from(replace_placeholders(&dir, time))
+		} else {
+			self.config.target_dir.clone()
+		}
+	}
+
+	fn writer_for_key(&mut self, key: (PathBuf, String)) -> Result<&mut LogWriter> {
+		self.tick += 1;
+
+		if !self.writers.contains_key(&key) {
+			if self.writers.len() >= self.max_open {
+				self.evict()?;
+			}
+
+			let writer = self.open(&key)?;
+			self.writers.insert(key.clone(), (self.tick, writer));
+			self.opened.push(key.clone());
+		}
+
+		let (last_used, writer) = self.writers.get_mut(&key).unwrap();
+		*last_used = self.tick;
+		Ok(writer)
+	}
+
+	fn open(&self, (dir, prefix): &(PathBuf, String)) -> Result<LogWriter> {
+		let mut config = self.config.clone();
+		config.prefix = prefix.clone();
+		if self.partitioned {
+			std::fs::create_dir_all(dir)
+				.with_context(|| format!("Creating partition directory {}", dir.display()))?;
+		}
+		config.target_dir = dir.clone();
+
+		let mut writer = LogWriter::new(config)
+			.with_context(|| format!("Creating log writer for {} {}", dir.display(), prefix))?;
+		if let Some(header) = &self.header {
+			writer
+				.write_all(header)
+				.with_context(|| format!("Writing header for {} {}", dir.display(), prefix))?;
+		}
+		debug!("opened log writer for {} {}", dir.display(), prefix);
+		metrics::OPEN_WRITERS.inc();
+
+		Ok(writer)
+	}
+
+	/// Flush and close the least recently used writer.
+	fn evict(&mut self) -> Result<()> {
+		let key = match self
+			.writers
+			.iter()
+			.min_by_key(|(_, (last_used, _))| *last_used)
+			.map(|(key, _)| key.clone())
+		{
+			Some(key) => key,
+			None => return Ok(()),
+		};
+
+		// kept open with its buffered lines if flushing fails
+		if let Some((_, writer)) = self.writers.get_mut(&key) {
+			let (dir, prefix) = &key;
+			writer.flush().with_context(|| {
+				format!("Flushing evicted writer for {} {}", dir.display(), prefix)
+			})?;
+		}
+		self.writers.remove(&key);
+		self.entries.remove(&key);
+		metrics::OPEN_WRITERS.dec();
+		metrics::WRITER_EVICTIONS.inc();
+		let (dir, prefix) = key;
+		debug!("closed log writer for {} {}", dir.display(), prefix);
+		self.closed.push((dir, prefix));
+
+		Ok(())
+	}
+}
+
+fn is_partitioned(config: &LogWriterConfig) -> bool {
+	let target_dir = config.target_dir.to_string_lossy();
+	PARTITION_PLACEHOLDERS.iter().any(|(placeholder, _)| {
+		target_dir.contains(placeholder) || config.prefix.contains(placeholder)
+	})
+}
+
+fn replace_placeholders(template: &str, time: &DateTime<Utc>) -> String {
+	let mut replaced = template.to_string();
+	for (placeholder, format) in PARTITION_PLACEHOLDERS.iter() {
+		replaced = replaced.replace(placeholder, &time.format(format).to_string());
+	}
+
+	replaced
+}
+
+/// The route key prefixed with the name of a boot.
+fn with_boot(boot: &str, route: String) -> String {
+	if route.is_empty() {
+		boot.to_string()
+	} else {
+		format!("{}{}{}", boot, SEPARATOR, route)
+	}
+}
+
+/// Name of the files of the entry's boot with `file_per_boot`: its `_BOOT_ID` and the UTC
+/// time the boot started, derived from the entry's realtime and monotonic timestamps, e.g.
+/// `0123456789abcdef0123456789abcdef-20261014T081500Z`.
+pub(crate) fn boot_name(entry: &JournalEntry) -> String {
+	let boot_id = sanitize(entry.get_field("_BOOT_ID").unwrap_or(""));
+	let field = |name: &str| entry.get_field(name).and_then(|us| us.parse::<i64>().ok());
+	let booted_us = match (
+		field("__REALTIME_TIMESTAMP"),
+		field("__MONOTONIC_TIMESTAMP"),
+	) {
+		(Some(realtime_us), Some(monotonic_us)) => realtime_us - monotonic_us,
+		_ => return boot_id,
+	};
+	let booted = Utc.timestamp(booted_us.div_euclid(1_000_000), 0);
+
+	format!("{}-{}", boot_id, booted.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Separates the route key, boot, `fan_out` file and time in file names. `sanitize` never
+/// produces it, so the files of one route can't be taken for those of another.
+const SEPARATOR: char = '+';
+
+/// Make a journal field value safe to be used as part of a file name.
+///
+/// Other bytes than ASCII alphanumerics, `-`, `_` and `.` are percent-encoded, so distinct
+/// values never share a name. An empty or missing value is `%none`, which no value encodes to.
+fn sanitize(value: &str) -> String {
+	if value.is_empty() {
+		return "%none".to_string();
+	}
+
+	let mut name = String::with_capacity(value.len());
+	for (i, byte) in value.bytes().enumerate() {
+		match byte {
+			// no hidden files or path traversal
+			b'.' if i == 0 => name.push_str("%2E"),
+			b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(byte as char),
+			_ => name.push_str(&format!("%{:02X}", byte)),
+		}
+	}
+
+	name
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sanitized_values_stay_distinct() {
+		assert_eq!(sanitize("systemd-journald"), "systemd-journald");
+		assert_eq!(sanitize("a/b"), "a%2Fb");
+		assert_eq!(sanitize("a b"), "a%20b");
+		assert_eq!(sanitize("a_b"), "a_b");
+		assert_eq!(sanitize("a%2Fb"), "a%252Fb");
+		assert_eq!(sanitize(".."), "%2E.");
+		assert_eq!(sanitize(""), "%none");
+		assert_eq!(sanitize("none"), "none");
+		assert_eq!(sanitize("k\u{f6}lsch"), "k%C3%B6lsch");
+		assert!(!sanitize("a+b").contains(SEPARATOR));
+	}
+}
@@ -0,0 +1,990 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use journald::JournalEntry;
+use log::*;
+use log_writer::{LogWriter, LogWriterConfig};
+
+use crate::archive::{ArchiveConfig, DailyArchive};
+use crate::budget::Budget;
+use crate::config::{Config, ErrorPolicy};
+use crate::fifo::{Fifo, FifoPolicy};
+use crate::http::{HttpConfig, HttpSink};
+use crate::index::{self, CurrentFiles, Index};
+use crate::lifecycle::LifecycleEvent;
+use crate::loopback::{Loopback, LoopbackConfig};
+use crate::manifest::Manifests;
+use crate::metrics;
+use crate::otlp;
+#[cfg(feature = "otlp-grpc")]
+use crate::otlp_grpc;
+use crate::overflow::{self, Overflow};
+use crate::router::{self, BucketBy, FanOut, Output, Route, RouteBy};
+use crate::syslog::SyslogSocket;
+use crate::trailer::{TrailerFormat, Trailers};
+use crate::writer::{self, OutputFormat, Record};
+
+/// Where a sink delivers entries to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkTarget {
+	/// Files in `log_writer_config.target_dir`
+	Files,
+	/// The local syslog daemon, via the datagram socket at `syslog_socket`. Always uses the
+	/// `syslog` format.
+	SyslogLocal,
+	/// The named pipe at `path`
+	Fifo,
+	/// Batches POSTed to the endpoint configured in `http`
+	Http,
+	/// Back into the local journal, see `journal`
+	Journal,
+	/// OTLP/HTTP requests in the JSON encoding, POSTed to the collector configured in `http`,
+	/// e.g. `http://collector:4318/v1/logs`. Always uses the OTLP format.
+	Otlp,
+	/// OTLP/gRPC `Export` calls to the collector configured in `http`, e.g.
+	/// `http://collector:4317`, with its `headers` as metadata. Always uses the OTLP format.
+	/// Needs the `otlp-grpc` feature.
+	OtlpGrpc,
+	/// One gzip file per day in the directory configured in `archive`
+	Archive,
+}
+
+impl Default for SinkTarget {
+	fn default() -> Self {
+		SinkTarget::Files
+	}
+}
+
+/// An additional output, receiving every entry in its own format.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkConfig {
+	#[serde(default)]
+	pub target: SinkTarget,
+	/// Required for `target: files`
+	#[serde(default)]
+	pub log_writer_config: Option<LogWriterConfig>,
+	#[serde(default)]
+	pub format: OutputFormat,
+	#[serde(default)]
+	pub route_by: Option<RouteBy>,
+	#[serde(default)]
+	pub fan_out: Option<FanOut>,
+	#[serde(default = "default_syslog_socket")]
+	pub syslog_socket: PathBuf,
+	/// Required for `target: fifo`
+	#[serde(default)]
+	pub path: Option<PathBuf>,
+	/// See `overflow_dir` of the primary sink, for `target: files`
+	#[serde(default)]
+	pub overflow_dir: Option<PathBuf>,
+	#[serde(default)]
+	pub on_reader_absent: FifoPolicy,
+	/// Required for `target: http`, `otlp` and `otlp_grpc`
+	#[serde(default)]
+	pub http: Option<HttpConfig>,
+	/// Socket and fields of `target: journal`
+	#[serde(default)]
+	pub journal: Option<LoopbackConfig>,
+	/// Required for `target: archive`
+	#[serde(default)]
+	pub archive: Option<ArchiveConfig>,
+	/// With `skip` the sink is best effort: failures are logged and counted, but neither
+	/// stop forwarding nor hold back the cursor
+	#[serde(default)]
+	pub on_error: ErrorPolicy,
+}
+
+fn default_syslog_socket() -> PathBuf {
+	PathBuf::from("/dev/log")
+}
+
+enum Destination {
+	Files {
+		output: Output,
+		budget: Option<Budget>,
+		index: Option<Index>,
+		overflow: Option<Overflow>,
+		/// `recreate_target_dir`
+		recreate_dir: bool,
+		/// `rotate_every_n_entries`
+		rotate_every: Option<u64>,
+		/// `file_trailer`, of the primary output only
+		trailers: Option<Trailers>,
+		/// `file_manifest`, of the primary output only
+		manifests: Option<Manifests>,
+		/// Files of the primary output, for `file_events` about files rotated inside log_writer
+		file_events: Option<CurrentFiles>,
+	},
+	SyslogLocal(SyslogSocket),
+	Fifo(Fifo),
+	Http(HttpSink),
+	Journal(Loopback),
+	Otlp(HttpSink),
+	#[cfg(feature = "otlp-grpc")]
+	OtlpGrpc(otlp_grpc::GrpcSink),
+	Archive(DailyArchive),
+	Stdout(std::io::Stdout),
+}
+
+pub(crate) struct Sink {
+	/// Used in log messages
+	name: String,
+	destination: Destination,
+	format: OutputFormat,
+	buffer: Vec<u8>,
+	on_error: ErrorPolicy,
+	/// Number of failures of a best effort sink
+	failures: u64,
+}
+
+impl Sink {
+	fn new(
+		log_writer_config: LogWriterConfig,
+		format: OutputFormat,
+		route_by: Option<RouteBy>,
+		fan_out: Option<FanOut>,
+		overflow_dir: Option<&PathBuf>,
+		config: &Config,
+	) -> Result<Self> {
+		match (config.file_trailer, format) {
+			(Some(TrailerFormat::Inline), OutputFormat::Text)
+			| (Some(TrailerFormat::Inline), OutputFormat::ZonedText)
+			| (Some(TrailerFormat::Sidecar), _)
+			| (None, _) => {}
+			(Some(TrailerFormat::Inline), format) => bail!(
+				"file_trailer inline is only supported with the text formats, not {:?}",
+				format
+			),
+		}
+		if config.rotate_every_n_entries == Some(0) {
+			bail!("rotate_every_n_entries has to be at least 1");
+		}
+		if let Some(fan_out) = fan_out {
+			check_fan_out(fan_out, route_by, config)?;
+		}
+		// for noticing rotation inside log_writer
+		let max_file_size = log_writer_config.max_file_size as u64;
+		let budget = match config.max_total_bytes {
+			Some(max) => Some(Budget::new(
+				&log_writer_config.target_dir,
+				max,
+				config.on_budget_exceeded,
+			)?),
+			None => None,
+		};
+		let header = writer::csv_header(format, config)?;
+		let route = Route {
+			route_by,
+			default_facility: config.default_facility,
+			fan_out,
+			unit_field: config.unit_field.clone(),
+			bucket_by: config.bucket_by,
+			per_boot: config.file_per_boot,
+			boot_of_entry: !config.only_local,
+		};
+		let overflow = match overflow_dir {
+			Some(dir) => {
+				std::fs::create_dir_all(dir)
+					.with_context(|| format!("Creating overflow_dir {}", dir.display()))?;
+				let mut overflow_config = log_writer_config.clone();
+				overflow_config.target_dir = dir.clone();
+				let output = Output::new(
+					overflow_config,
+					route.clone(),
+					config.max_open_writers,
+					header.clone(),
+				)?;
+				let budget = match config.max_total_bytes {
+					Some(max) => Some(Budget::new(dir, max, config.on_budget_exceeded)?),
+					None => None,
+				};
+				Some(Overflow::new(
+					output,
+					dir.clone(),
+					budget,
+					config.index_interval.map(Index::new),
+					config.overflow_retry_interval,
+				))
+			}
+			None => None,
+		};
+		let mut output = Output::new(log_writer_config, route, config.max_open_writers, header)?;
+		if config.precreate_routes {
+			let keys = match route_by {
+				Some(route_by) => route_by.static_keys(config),
+				None => Some(vec![String::new()]),
+			};
+			match keys {
+				Some(keys) => output.precreate(&keys)?,
+				None => info!(
+					"not precreating routed files, as match_expression doesn't restrict the routed field"
+				),
+			}
+		}
+
+		let index = config.index_interval.map(Index::new);
+
+		Ok(Self::with_destination(
+			Destination::Files {
+				output,
+				budget,
+				index,
+				overflow,
+				recreate_dir: config.recreate_target_dir,
+				rotate_every: config.rotate_every_n_entries,
+				trailers: config
+					.file_trailer
+					.map(|format| Trailers::new(format, max_file_size)),
+				manifests: config
+					.file_manifest
+					.map(|algorithm| Manifests::new(algorithm, max_file_size)),
+				file_events: if config.file_events {
+					Some(CurrentFiles::new(max_file_size))
+				} else {
+					None
+				},
+			},
+			format,
+		))
+	}
+
+	fn with_destination(destination: Destination, format: OutputFormat) -> Self {
+		Self {
+			name: "primary sink".to_string(),
+			destination,
+			format,
+			buffer: Vec::new(),
+			on_error: ErrorPolicy::Abort,
+			failures: 0,
+		}
+	}
+
+	fn from_config(name: String, sink: &SinkConfig, config: &Config) -> Result<Self> {
+		let mut opened = match sink.target {
+			SinkTarget::Files => {
+				let log_writer_config = match &sink.log_writer_config {
+					Some(log_writer_config) => log_writer_config.clone(),
+					None => bail!("log_writer_config is required for target files"),
+				};
+				Self::new(
+					log_writer_config,
+					sink.format,
+					sink.route_by,
+					sink.fan_out,
+					sink.overflow_dir.as_ref(),
+					config,
+				)?
+			}
+			SinkTarget::SyslogLocal => Self::with_destination(
+				Destination::SyslogLocal(SyslogSocket::new(sink.syslog_socket.clone())),
+				OutputFormat::Syslog,
+			),
+			SinkTarget::Fifo => {
+				let path = match &sink.path {
+					Some(path) => path.clone(),
+					None => bail!("path is required for target fifo"),
+				};
+				Self::with_destination(
+					Destination::Fifo(Fifo::new(path, sink.on_reader_absent)),
+					sink.format,
+				)
+			}
+			SinkTarget::Http => {
+				let http = match &sink.http {
+					Some(http) => http.clone(),
+					None => bail!("http is required for target http"),
+				};
+				Self::with_destination(
+					Destination::Http(HttpSink::new(http).with_write_timeout(config.write_timeout)),
+					sink.format,
+				)
+			}
+			SinkTarget::Otlp => {
+				let http = match &sink.http {
+					Some(http) => http.clone(),
+					None => bail!("http is required for target otlp"),
+				};
+				Self::with_destination(
+					Destination::Otlp(otlp::sink(http).with_write_timeout(config.write_timeout)),
+					OutputFormat::Json,
+				)
+			}
+			#[cfg(feature = "otlp-grpc")]
+			SinkTarget::OtlpGrpc => {
+				let http = match &sink.http {
+					Some(http) => http.clone(),
+					None => bail!("http is required for target otlp_grpc"),
+				};
+				Self::with_destination(
+					Destination::OtlpGrpc(otlp_grpc::GrpcSink::new(http, config.write_timeout)?),
+					OutputFormat::Json,
+				)
+			}
+			#[cfg(not(feature = "otlp-grpc"))]
+			SinkTarget::OtlpGrpc => {
+				bail!("target otlp_grpc needs journald-writer built with the otlp-grpc feature")
+			}
+			SinkTarget::Journal => {
+				let journal = sink.journal.clone().unwrap_or_default();
+				Self::with_destination(Destination::Journal(Loopback::new(journal)), sink.format)
+			}
+			SinkTarget::Archive => {
+				let archive = match &sink.archive {
+					Some(archive) => archive.clone(),
+					None => bail!("archive is required for target archive"),
+				};
+				Self::with_destination(
+					Destination::Archive(DailyArchive::new(archive)?),
+					sink.format,
+				)
+			}
+		};
+		opened.name = name;
+		opened.on_error = sink.on_error;
+
+		Ok(opened)
+	}
+
+	/// Write the entry, and flush the writer if `flush` is set.
+	pub(crate) fn write(
+		&mut self,
+		record: &Record<'_>,
+		config: &Config,
+		flush: bool,
+	) -> Result<()> {
+		let result = self.write_record(record, config, flush);
+		self.check(result)
+	}
+
+	/// Whether the sink writes entries as they are, see `write_passthrough`: in the export
+	/// format to a single file, without anything looking at the entries on the way.
+	pub(crate) fn is_passthrough(&self) -> bool {
+		match &self.destination {
+			Destination::Files {
+				output: Output::Single { .. },
+				budget: None,
+				index: None,
+				overflow: None,
+				recreate_dir: false,
+				rotate_every: None,
+				trailers: None,
+				manifests: None,
+				file_events: None,
+			} => self.format == OutputFormat::Export,
+			_ => false,
+		}
+	}
+
+	/// Write the entry in the export format without a `Record`, and flush the writer if
+	/// `flush` is set. Only for sinks with `is_passthrough`.
+	pub(crate) fn write_passthrough(
+		&mut self,
+		entry: &JournalEntry,
+		config: &Config,
+		flush: bool,
+	) -> Result<()> {
+		self.buffer.clear();
+		let result = match &mut self.destination {
+			Destination::Files {
+				output: Output::Single { writer, .. },
+				..
+			} => write_passthrough(entry, writer, &mut self.buffer, config, flush),
+			_ => Err(anyhow::anyhow!("entries aren't written as they are")),
+		};
+		self.check(result)
+	}
+
+	fn write_record(&mut self, record: &Record<'_>, config: &Config, flush: bool) -> Result<()> {
+		self.buffer.clear();
+		match &mut self.destination {
+			Destination::Files {
+				output,
+				budget,
+				index,
+				overflow,
+				recreate_dir,
+				rotate_every,
+				trailers,
+				manifests,
+				file_events,
+			} => {
+				writer::write_log_line(record, &mut self.buffer, self.format, config)?;
+				let recreate_dir = *recreate_dir;
+				let rotate_every = *rotate_every;
+				let location = output.location_for(record);
+
+				let use_primary = overflow.as_mut().map_or(true, Overflow::use_primary);
+				let written_primary = match overflow {
+					Some(overflow) if !use_primary => {
+						overflow.write(record, &self.buffer, flush)?;
+						false
+					}
+					Some(overflow) => {
+						match write_primary(
+							output,
+							budget,
+							index,
+							record,
+							&self.buffer,
+							flush,
+							recreate_dir,
+						) {
+							Ok(()) => {
+								overflow.primary_recovered(&self.name);
+								true
+							}
+							Err(e) if overflow::is_disk_full(&e) => {
+								overflow.activate(&self.name, &e);
+								overflow.write(record, &self.buffer, flush)?;
+								false
+							}
+							Err(e) => return Err(e),
+						}
+					}
+					None => {
+						write_primary(
+							output,
+							budget,
+							index,
+							record,
+							&self.buffer,
+							flush,
+							recreate_dir,
+						)?;
+						true
+					}
+				};
+
+				let mut opened = output.take_opened();
+				if let Some(overflow) = overflow.as_mut() {
+					opened.extend(overflow.take_opened());
+				}
+				if config.file_events && !opened.is_empty() {
+					// so that the new files exist
+					match overflow.as_mut() {
+						Some(overflow) => {
+							flush_primary(output, overflow, &self.name)?;
+							overflow.flush()?;
+						}
+						None => output.flush()?,
+					}
+					for (dir, prefix) in &opened {
+						record_file_open(config, &self.name, dir, prefix, record)?;
+					}
+				}
+				if let (true, Some(files)) = (written_primary, file_events.as_mut()) {
+					// recorded above, the file is looked up anew
+					let new_writer = opened.contains(&location);
+					if new_writer {
+						files.closed(&location);
+					}
+					let rotated = files.written(&location, self.buffer.len(), || output.flush())?;
+					if rotated && !new_writer {
+						let (dir, prefix) = &location;
+						record_file_open(config, &self.name, dir, prefix, record)?;
+					}
+				}
+				if written_primary {
+					// a file replaced while writing, by recreate_target_dir, is summarized alone
+					close_files(output, trailers, manifests)?;
+					if let Some(trailers) = trailers.as_mut() {
+						trailers
+							.written(&location, record, self.buffer.len(), || output.flush())?;
+					}
+					// after the trailer, which may be the last line of a rotated file
+					if let Some(manifests) = manifests.as_mut() {
+						manifests.written(&location, self.buffer.len(), || output.flush())?;
+					}
+				}
+				// after file_events, so the next file is recorded with its own first entry
+				if let (true, Some(max)) = (written_primary, rotate_every) {
+					// the entry was written, so it doesn't fail with the roll
+					if let Err(e) = output.count_entry(&location, max) {
+						warn!(
+							"{}: rolling over to a new file failed, retrying with the next entry: {:#}",
+							self.name, e
+						);
+					}
+				}
+				close_files(output, trailers, manifests)?;
+			}
+			Destination::SyslogLocal(socket) => {
+				writer::write_syslog(record, &mut self.buffer, config)?;
+				socket.send(&self.buffer)?;
+			}
+			Destination::Fifo(fifo) => {
+				writer::write_log_line(record, &mut self.buffer, self.format, config)?;
+				fifo.write(&self.buffer)?;
+			}
+			// posted in batches, with the next flush of all sinks at the latest
+			Destination::Http(http) => {
+				writer::write_log_line(record, &mut self.buffer, self.format, config)?;
+				http.write(&self.buffer)?;
+			}
+			Destination::Otlp(http) => {
+				otlp::write_log_record(record, &mut self.buffer, config)?;
+				http.write(&self.buffer)?;
+			}
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(grpc) => grpc.write(otlp_grpc::log_record(record, config))?,
+			Destination::Journal(loopback) => loopback.send(record, &mut self.buffer, config)?,
+			Destination::Archive(archive) => {
+				writer::write_log_line(record, &mut self.buffer, self.format, config)?;
+				let time = match config.bucket_by {
+					BucketBy::EventTime => record.time_utc(),
+					BucketBy::WriteTime => record.ingested_at,
+				};
+				archive.write(&time, &self.buffer)?;
+				if flush {
+					archive.flush()?;
+				}
+			}
+			Destination::Stdout(stdout) => {
+				if config.json_pretty && self.format == OutputFormat::Json {
+					writer::write_json_pretty(record, &mut self.buffer, config)?;
+				} else {
+					writer::write_log_line(record, &mut self.buffer, self.format, config)?;
+				}
+				let mut stdout = stdout.lock();
+				stdout
+					.write_all(&self.buffer)
+					.context("Writing to stdout")?;
+				stdout.flush().context("Flushing stdout")?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Write and flush a lifecycle event of the daemon.
+	pub(crate) fn write_event(&mut self, event: &LifecycleEvent, config: &Config) -> Result<()> {
+		let result = self.write_lifecycle_event(event, config);
+		self.check(result)
+	}
+
+	fn write_lifecycle_event(&mut self, event: &LifecycleEvent, config: &Config) -> Result<()> {
+		self.buffer.clear();
+		event.write(&mut self.buffer, self.format, config)?;
+
+		match &mut self.destination {
+			Destination::Files {
+				output,
+				budget,
+				overflow,
+				..
+			} => {
+				let output = match overflow.as_mut().filter(|overflow| overflow.is_active()) {
+					Some(overflow) => {
+						overflow.reserve(self.buffer.len() as u64)?;
+						overflow.output()
+					}
+					None => {
+						if let Some(budget) = budget.as_mut() {
+							budget.reserve(self.buffer.len() as u64)?;
+						}
+						output
+					}
+				};
+
+				let log_writer = output.writer_at(&event.time)?;
+				log_writer
+					.write_all(&self.buffer)
+					.context("write to log_writer")?;
+				log_writer.flush().context("Flushing writer")?;
+			}
+			Destination::SyslogLocal(socket) => {
+				if self.buffer.last() == Some(&b'\n') {
+					self.buffer.pop();
+				}
+				socket.send(&self.buffer)?;
+			}
+			Destination::Fifo(fifo) => fifo.write(&self.buffer)?,
+			Destination::Http(http) => http.write(&self.buffer)?,
+			Destination::Archive(archive) => {
+				archive.write(&event.time, &self.buffer)?;
+				archive.flush()?;
+			}
+			// the daemon's own events are in its log already
+			Destination::Journal(_) | Destination::Otlp(_) => {}
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(_) => {}
+			Destination::Stdout(stdout) => {
+				let mut stdout = stdout.lock();
+				stdout
+					.write_all(&self.buffer)
+					.context("Writing to stdout")?;
+				stdout.flush().context("Flushing stdout")?;
+			}
+		}
+
+		Ok(())
+	}
+
+	pub(crate) fn flush(&mut self) -> Result<()> {
+		let result = match &mut self.destination {
+			Destination::Files {
+				output,
+				overflow,
+				recreate_dir,
+				trailers,
+				manifests,
+				..
+			} => {
+				// written data is lost with the directory, but the following isn't
+				let recreated = if *recreate_dir {
+					output
+						.recreate_missing_dirs()
+						.and_then(|_| close_files(output, trailers, manifests))
+				} else {
+					Ok(())
+				};
+				recreated.and_then(|_| match overflow {
+					Some(overflow) => {
+						flush_primary(output, overflow, &self.name).and_then(|_| overflow.flush())
+					}
+					None => output.flush(),
+				})
+			}
+			Destination::Http(http) | Destination::Otlp(http) => http.flush(),
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(grpc) => grpc.flush(),
+			Destination::Archive(archive) => archive.flush(),
+			// datagrams are sent right away, the others are flushed with every write
+			Destination::SyslogLocal(_)
+			| Destination::Fifo(_)
+			| Destination::Journal(_)
+			| Destination::Stdout(_) => Ok(()),
+		};
+		self.check(result)
+	}
+
+	/// `flush`, but giving up on the batch of a network sink once it isn't acknowledged by
+	/// `deadline`, see `shutdown_ack_timeout`.
+	pub(crate) fn flush_before(&mut self, deadline: Instant) -> Result<()> {
+		let result = match &mut self.destination {
+			Destination::Http(http) | Destination::Otlp(http) => http.flush_before(Some(deadline)),
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(grpc) => grpc.flush_before(Some(deadline)),
+			_ => return self.flush(),
+		};
+		self.check(result)
+	}
+
+	/// Whether written entries are held back until the next flush, so it has to precede
+	/// persisting the cursor.
+	pub(crate) fn has_pending(&self) -> bool {
+		match &self.destination {
+			Destination::Http(http) | Destination::Otlp(http) => http.has_pending(),
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(grpc) => grpc.has_pending(),
+			Destination::Archive(archive) => archive.has_pending(),
+			_ => false,
+		}
+	}
+
+	/// Whether the fields returned by `enrich_command` are written.
+	pub(crate) fn uses_enriched(&self) -> bool {
+		match &self.destination {
+			Destination::Otlp(_) | Destination::Journal(_) => true,
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(_) => true,
+			Destination::SyslogLocal(_) => false,
+			Destination::Files { .. }
+			| Destination::Fifo(_)
+			| Destination::Http(_)
+			| Destination::Archive(_)
+			| Destination::Stdout(_) => self.format.uses_enriched(),
+		}
+	}
+
+	/// Whether entries written since the last persisted cursor were lost with a vanished and
+	/// recreated target directory, see `recreate_target_dir`.
+	pub(crate) fn take_lost(&mut self) -> bool {
+		match &mut self.destination {
+			Destination::Files { output, .. } => output.take_recreated(),
+			_ => false,
+		}
+	}
+
+	/// Close and reopen the output files after external rotation, see `on_sighup`.
+	pub(crate) fn reopen(&mut self) -> Result<()> {
+		let result = match &mut self.destination {
+			Destination::Files {
+				output,
+				overflow,
+				trailers,
+				manifests,
+				..
+			} => match overflow {
+				Some(overflow) => output.reopen().and_then(|_| overflow.reopen()),
+				None => output.reopen(),
+			}
+			.and_then(|_| close_files(output, trailers, manifests)),
+			Destination::SyslogLocal(_)
+			| Destination::Fifo(_)
+			| Destination::Http(_)
+			| Destination::Journal(_)
+			| Destination::Otlp(_)
+			| Destination::Archive(_)
+			| Destination::Stdout(_) => Ok(()),
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(_) => Ok(()),
+		};
+		self.check(result)
+	}
+
+	/// Close the files of the previous boot and write into those of `boot` from now on, see
+	/// `file_per_boot`.
+	pub(crate) fn start_boot(&mut self, boot: &str) -> Result<()> {
+		let result = match &mut self.destination {
+			Destination::Files {
+				output,
+				overflow,
+				trailers,
+				manifests,
+				..
+			} => match overflow {
+				Some(overflow) => output
+					.start_boot(boot.to_string())
+					.and_then(|_| overflow.start_boot(boot.to_string())),
+				None => output.start_boot(boot.to_string()),
+			}
+			.and_then(|_| close_files(output, trailers, manifests)),
+			Destination::SyslogLocal(_)
+			| Destination::Fifo(_)
+			| Destination::Http(_)
+			| Destination::Journal(_)
+			| Destination::Otlp(_)
+			| Destination::Archive(_)
+			| Destination::Stdout(_) => Ok(()),
+			#[cfg(feature = "otlp-grpc")]
+			Destination::OtlpGrpc(_) => Ok(()),
+		};
+		self.check(result)
+	}
+
+	/// Flush the output files and write their trailers, at exit, see `file_trailer`.
+	pub(crate) fn finish(&mut self) -> Result<()> {
+		let result = match &mut self.destination {
+			Destination::Files {
+				output,
+				trailers: Some(trailers),
+				..
+			} => output.flush().and_then(|_| trailers.close_all()),
+			_ => Ok(()),
+		};
+		self.check(result)
+	}
+
+	/// Count and log failures of a best effort sink, instead of returning them.
+	fn check(&mut self, result: Result<()>) -> Result<()> {
+		match result {
+			Err(e) if self.on_error == ErrorPolicy::Skip => {
+				self.failures += 1;
+				metrics::SINK_FAILURES.inc();
+				warn!(
+					"{} failed ({} failures so far): {:#}",
+					self.name, self.failures, e
+				);
+				Ok(())
+			}
+			result => result.with_context(|| format!("Writing to {}", self.name)),
+		}
+	}
+}
+
+/// Refuse a `fan_out` without files or with more than `max_open_writers`, which would close
+/// and reopen a file for every entry, and warn if the files of all known routes don't fit.
+fn check_fan_out(fan_out: FanOut, route_by: Option<RouteBy>, config: &Config) -> Result<()> {
+	if fan_out.files == 0 {
+		bail!("fan_out needs at least 1 file");
+	}
+	if fan_out.files > config.max_open_writers {
+		bail!(
+			"fan_out files {} is more than max_open_writers {}",
+			fan_out.files,
+			config.max_open_writers
+		);
+	}
+	let routes = match route_by {
+		Some(route_by) => route_by.static_keys(config).map(|keys| keys.len()),
+		None => Some(1),
+	};
+	if let Some(routes) = routes.filter(|routes| fan_out.files * routes > config.max_open_writers) {
+		warn!(
+			"fan_out needs {} files for {} routes, more than max_open_writers {}, files will be closed and reopened",
+			fan_out.files * routes,
+			routes,
+			config.max_open_writers
+		);
+	}
+
+	Ok(())
+}
+
+/// Write the trailers and manifests of the files the output closed, see `file_trailer` and
+/// `file_manifest`.
+fn close_files(
+	output: &mut Output,
+	trailers: &mut Option<Trailers>,
+	manifests: &mut Option<Manifests>,
+) -> Result<()> {
+	for location in output.take_closed() {
+		if let Some(trailers) = trailers.as_mut() {
+			trailers.closed(&location)?;
+		}
+		if let Some(manifests) = manifests.as_mut() {
+			manifests.closed(&location)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Write the entry into `data` before passing it on, so that log_writer doesn't rotate in
+/// the middle of it.
+fn write_passthrough(
+	entry: &JournalEntry,
+	log_writer: &mut LogWriter,
+	data: &mut Vec<u8>,
+	config: &Config,
+	flush: bool,
+) -> Result<()> {
+	writer::write_passthrough(entry, data, config)?;
+	log_writer.write_all(data).context("write to log_writer")?;
+	if flush {
+		log_writer.flush().context("Flushing writer")?;
+	}
+
+	Ok(())
+}
+
+/// `write_files`, retried once after recreating the target directory with
+/// `recreate_target_dir` if it vanished.
+fn write_primary(
+	output: &mut Output,
+	budget: &mut Option<Budget>,
+	index: &mut Option<Index>,
+	record: &Record<'_>,
+	data: &[u8],
+	flush: bool,
+	recreate_dir: bool,
+) -> Result<()> {
+	match write_files(output, budget, index, record, data, flush) {
+		Err(e) if recreate_dir && router::is_not_found(&e) => {
+			if !output.recreate_missing_dirs()? {
+				return Err(e);
+			}
+			info!("recreated target directory, writing entry again");
+			write_files(output, budget, index, record, data, flush)
+		}
+		result => result,
+	}
+}
+
+/// Flush the primary output of a files sink with an overflow directory, switching to it if
+/// the target directory is full or read-only. The unwritten entries stay buffered for the
+/// primary until it is flushed successfully.
+fn flush_primary(output: &mut Output, overflow: &mut Overflow, name: &str) -> Result<()> {
+	match output.flush() {
+		Err(e) if overflow::is_disk_full(&e) => {
+			overflow.activate(name, &e);
+			Ok(())
+		}
+		result => result,
+	}
+}
+
+/// Write `data` of the entry into the directory of `output`, the primary or the overflow
+/// directory of a files sink.
+pub(crate) fn write_files(
+	output: &mut Output,
+	budget: &mut Option<Budget>,
+	index: &mut Option<Index>,
+	record: &Record<'_>,
+	data: &[u8],
+	flush: bool,
+) -> Result<()> {
+	if let Some(budget) = budget.as_mut() {
+		budget.reserve(data.len() as u64)?;
+	}
+
+	let location = output.location_for(record);
+	let indexed = index
+		.as_ref()
+		.map_or(false, |index| index.due(&location.0, &location.1));
+	let log_writer = output.writer_for(record)?;
+	log_writer.write_all(data).context("write to log_writer")?;
+	if flush || indexed {
+		log_writer.flush().context("Flushing writer")?;
+	}
+	if let Some(index) = index.as_mut().filter(|_| indexed) {
+		index.record(&location.0, &location.1, record, data.len())?;
+	}
+
+	Ok(())
+}
+
+/// Append a line about a newly opened output file to `file_events` next to the cursor file,
+/// with the cursor of the first entry written to it.
+fn record_file_open(
+	config: &Config,
+	sink: &str,
+	dir: &Path,
+	prefix: &str,
+	record: &Record<'_>,
+) -> Result<()> {
+	let file = match index::current_file(dir, prefix)? {
+		Some((file, _)) => file,
+		None => dir.join(prefix),
+	};
+	let line = serde_json::json!({
+		"timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+		"event": "file_open",
+		"sink": sink,
+		"file": file.display().to_string(),
+		"cursor": record.entry.get_field("__CURSOR"),
+	});
+	let path = config.cursor_file.with_file_name("file_events");
+	let mut events = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&path)
+		.with_context(|| format!("Opening {}", path.display()))?;
+	writeln!(events, "{}", line).with_context(|| format!("Writing {}", path.display()))?;
+	debug!("opened {} in {}", file.display(), sink);
+
+	Ok(())
+}
+
+/// A sink writing to stdout in the format of the primary sink, for `--dry-run`.
+pub(crate) fn open_stdout(config: &Config) -> Sink {
+	let mut sink = Sink::with_destination(Destination::Stdout(std::io::stdout()), config.format);
+	sink.name = "stdout".to_string();
+	sink
+}
+
+/// Open the primary sink configured at the top level, followed by all of `sinks`.
+pub(crate) fn open_all(config: &Config) -> Result<Vec<Sink>> {
+	let mut sinks = vec![Sink::new(
+		config.log_writer_config.clone(),
+		config.format,
+		config.route_by,
+		config.fan_out,
+		config.overflow_dir.as_ref(),
+		config,
+	)?];
+
+	for (i, sink) in config.sinks.iter().enumerate() {
+		let sink = Sink::from_config(format!("sink {}", i), sink, config)
+			.with_context(|| format!("Opening sink {}", i))?;
+		sinks.push(sink);
+	}
+
+	Ok(sinks)
+}
@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::metrics;
+
+/// Progress of the forwarder, as reported by the control socket.
+#[derive(Debug, Clone, serde::Serialize)]
+struct Status {
+	/// Last persisted cursor
+	cursor: Option<String>,
+	/// Timestamp of the last written entry, in microseconds since the epoch
+	last_timestamp_us: Option<i64>,
+	last_error: Option<String>,
+}
+
+static STATUS: Mutex<Status> = Mutex::new(Status {
+	cursor: None,
+	last_timestamp_us: None,
+	last_error: None,
+});
+
+fn with_status<F: FnOnce(&mut Status)>(f: F) {
+	let mut status = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+	f(&mut status)
+}
+
+pub(crate) fn set_cursor(cursor: &str) {
+	with_status(|status| status.cursor = Some(cursor.to_string()));
+}
+
+pub(crate) fn set_timestamp(timestamp_us: i64) {
+	with_status(|status| status.last_timestamp_us = Some(timestamp_us));
+}
+
+pub(crate) fn set_error(error: &anyhow::Error) {
+	with_status(|status| status.last_error = Some(format!("{:#}", error)));
+}
+
+/// Current status, including the lag behind the wall clock and all counters.
+pub(crate) fn snapshot() -> serde_json::Value {
+	let status = STATUS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+	let lag_seconds = status
+		.last_timestamp_us
+		.map(|us| (chrono::Utc::now().timestamp_micros() - us) as f64 / 1_000_000.0);
+	let counters: BTreeMap<&str, u64> = metrics::snapshot().into_iter().collect();
+
+	serde_json::json!({
+		"cursor": status.cursor,
+		"last_timestamp_us": status.last_timestamp_us,
+		"lag_seconds": lag_seconds,
+		"last_error": status.last_error,
+		"counters": counters,
+	})
+}
@@ -0,0 +1,140 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use log::*;
+
+/// Datagram connection to the socket of the local syslog daemon, usually `/dev/log`.
+///
+/// The socket is recreated when the syslog daemon restarts, so sending reconnects once if the
+/// connection was lost.
+pub(crate) struct SyslogSocket {
+	path: PathBuf,
+	socket: Option<UnixDatagram>,
+}
+
+impl SyslogSocket {
+	pub(crate) fn new(path: PathBuf) -> Self {
+		Self { path, socket: None }
+	}
+
+	pub(crate) fn send(&mut self, message: &[u8]) -> Result<()> {
+		let socket = self.connect()?;
+		if let Err(e) = socket.send(message) {
+			debug!(
+				"sending to {} failed, reconnecting: {}",
+				self.path.display(),
+				e
+			);
+			self.socket = None;
+			self.connect()?
+				.send(message)
+				.with_context(|| format!("Sending to {}", self.path.display()))?;
+		}
+
+		Ok(())
+	}
+
+	fn connect(&mut self) -> Result<&UnixDatagram> {
+		if self.socket.is_none() {
+			let socket = UnixDatagram::unbound().context("Creating datagram socket")?;
+			socket
+				.connect(&self.path)
+				.with_context(|| format!("Connecting to {}", self.path.display()))?;
+			debug!("connected to socket {}", self.path.display());
+			self.socket = Some(socket);
+		}
+
+		Ok(self.socket.as_ref().unwrap())
+	}
+}
+
+/// Names of the syslog facilities, indexed by their code
+const FACILITY_NAMES: [&str; 24] = [
+	"kern",
+	"user",
+	"mail",
+	"daemon",
+	"auth",
+	"syslog",
+	"lpr",
+	"news",
+	"uucp",
+	"cron",
+	"authpriv",
+	"ftp",
+	"ntp",
+	"security",
+	"console",
+	"solaris-cron",
+	"local0",
+	"local1",
+	"local2",
+	"local3",
+	"local4",
+	"local5",
+	"local6",
+	"local7",
+];
+
+/// A syslog facility, configured by its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Facility(u8);
+
+impl Facility {
+	pub(crate) const KERN: Facility = Facility(0);
+	pub(crate) const USER: Facility = Facility(1);
+
+	/// The facility of the entry's `SYSLOG_FACILITY` field, if valid
+	pub(crate) fn of(entry: &journald::JournalEntry) -> Option<Self> {
+		entry.get_field("SYSLOG_FACILITY").and_then(Self::from_code)
+	}
+
+	/// The facility of a `SYSLOG_FACILITY` value, if valid
+	pub(crate) fn from_code(code: &str) -> Option<Self> {
+		code.parse()
+			.ok()
+			.filter(|code| (*code as usize) < FACILITY_NAMES.len())
+			.map(Facility)
+	}
+
+	pub(crate) fn code(&self) -> u8 {
+		self.0
+	}
+
+	pub(crate) fn name(&self) -> &'static str {
+		FACILITY_NAMES[self.0 as usize]
+	}
+}
+
+impl Default for Facility {
+	fn default() -> Self {
+		Facility::USER
+	}
+}
+
+impl TryFrom<String> for Facility {
+	type Error = anyhow::Error;
+
+	fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+		match FACILITY_NAMES.iter().position(|name| *name == value) {
+			Some(code) => Ok(Facility(code as u8)),
+			None => bail!("Unknown syslog facility {}", value),
+		}
+	}
+}
+
+impl From<Facility> for String {
+	fn from(facility: Facility) -> Self {
+		facility.name().to_string()
+	}
+}
+
+impl fmt::Display for Facility {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
@@ -0,0 +1,300 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// The line layout of the text format, e.g. `{utc_time} [{severity}] {message}`.
+///
+/// `{{` and `}}` are literal braces. Unknown placeholders are rejected when parsing.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+	source: String,
+	segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+	Literal(String),
+	Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placeholder {
+	UtcTime,
+	LocalTime,
+	/// Verbose priority, e.g. `Informational`
+	Severity,
+	/// rsyslog priority keyword, e.g. `info`
+	SeverityKeyword,
+	/// OpenTelemetry severity number, e.g. `9` for info
+	OtelSeverity,
+	UnitName,
+	Identifier,
+	Message,
+	/// Reception time in microseconds since the epoch, unaffected by `timestamp_field` and
+	/// clamping
+	EpochUs,
+	/// Time the entry was processed by the forwarder, for measuring the forwarding delay
+	IngestedAt,
+	/// Filters the entry passed with `annotate_match`, empty otherwise
+	Match,
+	/// Number of the record with `forwarder_seq`, empty otherwise
+	Seq,
+	/// Seconds since boot, empty if unknown
+	Monotonic,
+	/// `instance_id` of the config
+	InstanceId,
+	/// `CONTAINER_NAME` set by Docker and Podman, empty for other entries
+	ContainerName,
+	ContainerId,
+	ImageName,
+	/// `_TRANSPORT`, e.g. `stdout` or `kernel`, empty if unknown
+	Transport,
+	/// `_CMDLINE` with its arguments separated by spaces, empty if unknown
+	Cmdline,
+	/// `_HOSTNAME` of entries received from another machine, see `only_local`
+	RemoteHost,
+	/// Hash of the message with `message_hash`, empty otherwise
+	MsgHash,
+}
+
+impl Placeholder {
+	fn from_name(name: &str) -> Option<Self> {
+		let placeholder = match name {
+			"utc_time" => Placeholder::UtcTime,
+			"local_time" => Placeholder::LocalTime,
+			"severity" => Placeholder::Severity,
+			"severity_keyword" => Placeholder::SeverityKeyword,
+			"otel_severity" => Placeholder::OtelSeverity,
+			"unit_name" => Placeholder::UnitName,
+			"identifier" => Placeholder::Identifier,
+			"message" => Placeholder::Message,
+			"epoch_us" => Placeholder::EpochUs,
+			"ingested_at" => Placeholder::IngestedAt,
+			"match" => Placeholder::Match,
+			"seq" => Placeholder::Seq,
+			"monotonic" => Placeholder::Monotonic,
+			"instance_id" => Placeholder::InstanceId,
+			"container_name" => Placeholder::ContainerName,
+			"container_id" => Placeholder::ContainerId,
+			"image_name" => Placeholder::ImageName,
+			"transport" => Placeholder::Transport,
+			"cmdline" => Placeholder::Cmdline,
+			"remote_host" => Placeholder::RemoteHost,
+			"msg_hash" => Placeholder::MsgHash,
+			_ => return None,
+		};
+		Some(placeholder)
+	}
+}
+
+impl Template {
+	pub(crate) fn segments(&self) -> &[Segment] {
+		&self.segments
+	}
+}
+
+impl Default for Template {
+	fn default() -> Self {
+		Template::try_from(
+			"{utc_time} {local_time} [{severity}] {unit_name}: {identifier}: {message}".to_string(),
+		)
+		.unwrap()
+	}
+}
+
+impl TryFrom<String> for Template {
+	type Error = anyhow::Error;
+
+	fn try_from(source: String) -> Result<Self> {
+		let mut segments = Vec::new();
+		let mut literal = String::new();
+		let mut chars = source.chars().peekable();
+
+		while let Some(c) = chars.next() {
+			match c {
+				'{' if chars.peek() == Some(&'{') => {
+					chars.next();
+					literal.push('{');
+				}
+				'}' if chars.peek() == Some(&'}') => {
+					chars.next();
+					literal.push('}');
+				}
+				'{' => {
+					let mut name = String::new();
+					loop {
+						match chars.next() {
+							Some('}') => break,
+							Some(c) => name.push(c),
+							None => bail!("Unterminated placeholder {{{} in template", name),
+						}
+					}
+					let placeholder = match Placeholder::from_name(&name) {
+						Some(placeholder) => placeholder,
+						None => bail!("Unknown placeholder {{{}}} in template", name),
+					};
+					if !literal.is_empty() {
+						segments.push(Segment::Literal(std::mem::take(&mut literal)));
+					}
+					segments.push(Segment::Placeholder(placeholder));
+				}
+				'}' => bail!("Unmatched }} in template, use }}}} for a literal brace"),
+				c => literal.push(c),
+			}
+		}
+		if !literal.is_empty() {
+			segments.push(Segment::Literal(literal));
+		}
+
+		Ok(Self { source, segments })
+	}
+}
+
+impl From<Template> for String {
+	fn from(template: Template) -> Self {
+		template.source
+	}
+}
+
+impl fmt::Display for Template {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.source)
+	}
+}
+
+/// A `strftime` format of the `{utc_time}` and `{local_time}` placeholders, e.g.
+/// `%Y-%m-%d %H:%M:%S%.3f`.
+///
+/// Checked when parsing by formatting a sample time, as chrono only fails while formatting.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimestampFormat(String);
+
+impl TimestampFormat {
+	pub(crate) fn as_str(&self) -> &str {
+		&self.0
+	}
+
+	/// Whether the format includes the offset or zone of the time, e.g. with `%z`, or is
+	/// unambiguous without, like `%s`.
+	pub(crate) fn has_offset(&self) -> bool {
+		use chrono::format::{Fixed, Item, Numeric, StrftimeItems};
+
+		StrftimeItems::new(&self.0).any(|item| {
+			matches!(
+				item,
+				Item::Fixed(Fixed::TimezoneName)
+					| Item::Fixed(Fixed::TimezoneOffset)
+					| Item::Fixed(Fixed::TimezoneOffsetColon)
+					| Item::Fixed(Fixed::TimezoneOffsetColonZ)
+					| Item::Fixed(Fixed::TimezoneOffsetZ)
+					| Item::Fixed(Fixed::RFC2822)
+					| Item::Fixed(Fixed::RFC3339)
+					| Item::Numeric(Numeric::Timestamp, _)
+			)
+		})
+	}
+}
+
+impl TryFrom<String> for TimestampFormat {
+	type Error = anyhow::Error;
+
+	fn try_from(format: String) -> Result<Self> {
+		use std::fmt::Write;
+
+		let sample = chrono::Local::now();
+		let mut formatted = String::new();
+		if write!(formatted, "{}", sample.format(&format)).is_err() {
+			bail!("Invalid timestamp_format {:?}", format);
+		}
+
+		Ok(Self(format))
+	}
+}
+
+impl From<TimestampFormat> for String {
+	fn from(format: TimestampFormat) -> Self {
+		format.0
+	}
+}
+
+/// Zone of the single timestamp of the `zoned_text` format: `local`, `UTC` or a fixed offset
+/// like `+02:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timezone {
+	/// The zone of the system, as for `{local_time}`
+	Local,
+	Fixed(chrono::FixedOffset),
+}
+
+impl Default for Timezone {
+	fn default() -> Self {
+		Timezone::Local
+	}
+}
+
+impl TryFrom<String> for Timezone {
+	type Error = anyhow::Error;
+
+	fn try_from(source: String) -> Result<Self> {
+		if source.eq_ignore_ascii_case("local") {
+			return Ok(Timezone::Local);
+		}
+		if source.eq_ignore_ascii_case("utc") || source == "Z" {
+			return Ok(Timezone::Fixed(chrono::FixedOffset::east(0)));
+		}
+
+		let (sign, offset) = match source.split_at(source.len().min(1)) {
+			("+", offset) => (1, offset),
+			("-", offset) => (-1, offset),
+			_ => bail!(
+				"Invalid timezone {:?}, expected local, UTC or an offset like +02:00",
+				source
+			),
+		};
+		let digits = offset.replacen(':', "", 1);
+		if !matches!(digits.len(), 2 | 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+			bail!("Invalid timezone offset {:?}, expected e.g. +02:00", source);
+		}
+		let hours: i32 = digits[..2].parse()?;
+		let minutes: i32 = if digits.len() == 4 {
+			digits[2..].parse()?
+		} else {
+			0
+		};
+		if hours > 23 || minutes > 59 {
+			bail!("Invalid timezone offset {:?}, out of range", source);
+		}
+
+		Ok(Timezone::Fixed(chrono::FixedOffset::east(
+			sign * (hours * 3600 + minutes * 60),
+		)))
+	}
+}
+
+impl From<Timezone> for String {
+	fn from(timezone: Timezone) -> Self {
+		match timezone {
+			Timezone::Local => "local".to_string(),
+			Timezone::Fixed(offset) => offset.to_string(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn timestamp_format_with_offset() {
+		let format = |format: &str| TimestampFormat::try_from(format.to_string()).unwrap();
+		assert!(!format("%Y-%m-%d %H:%M:%S%.3f").has_offset());
+		assert!(format("%Y-%m-%dT%H:%M:%S%:z").has_offset());
+		assert!(format("%F %T %z").has_offset());
+		assert!(format("%+").has_offset());
+		assert!(format("%s").has_offset());
+	}
+}
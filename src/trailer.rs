@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::index::CurrentFiles;
+use crate::writer::Record;
+
+/// Appended to the name of an output file for its `file_trailer: sidecar`
+pub(crate) const SIDECAR_SUFFIX: &str = ".trailer.json";
+
+/// Where the summary of a closed output file is written, see `file_trailer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailerFormat {
+	/// As last line of the file, `#journald-writer-trailer ` followed by the JSON summary.
+	/// Only for the text formats, as the others have no comment syntax.
+	Inline,
+	/// As JSON in a file next to it, named with `.trailer.json` appended
+	Sidecar,
+}
+
+/// What is known about the entries written to one output file
+struct FileSummary {
+	path: Option<PathBuf>,
+	entries: u64,
+	first_timestamp_us: i64,
+	last_timestamp_us: i64,
+	first_cursor: Option<String>,
+	last_cursor: Option<String>,
+}
+
+/// Summaries of the open output files of a files sink, written once a file is closed by
+/// rotation, also inside log_writer by `max_file_size`, by `file_per_boot` or at exit. Files
+/// moved away before `on_sighup` reopens them get none.
+pub(crate) struct Trailers {
+	format: TrailerFormat,
+	current: CurrentFiles,
+	files: HashMap<(PathBuf, String), FileSummary>,
+}
+
+impl Trailers {
+	pub(crate) fn new(format: TrailerFormat, max_file_size: u64) -> Self {
+		Self {
+			format,
+			current: CurrentFiles::new(max_file_size),
+			files: HashMap::new(),
+		}
+	}
+
+	/// Record an entry of `len` bytes written to the file at `location`. `flush` is called
+	/// before looking up the name of the file, see `CurrentFiles::written`.
+	pub(crate) fn written(
+		&mut self,
+		location: &(PathBuf, String),
+		record: &Record<'_>,
+		len: usize,
+		flush: impl FnOnce() -> Result<()>,
+	) -> Result<()> {
+		let cursor = record.entry.get_field("__CURSOR").map(str::to_string);
+		let changed = self.current.written(location, len, flush)?;
+		match self.files.get_mut(location) {
+			Some(summary) if !changed => {
+				summary.entries += 1;
+				summary.last_timestamp_us = record.timestamp_us;
+				summary.last_cursor = cursor;
+				return Ok(());
+			}
+			// rotated by log_writer, the entry is the first of the next file
+			Some(_) => {
+				if let Some(summary) = self.files.remove(location) {
+					self.write(summary)?;
+				}
+			}
+			None => {}
+		}
+
+		let path = self.current.path(location).map(Path::to_path_buf);
+		self.files.insert(
+			location.clone(),
+			FileSummary {
+				path,
+				entries: 1,
+				first_timestamp_us: record.timestamp_us,
+				last_timestamp_us: record.timestamp_us,
+				first_cursor: cursor.clone(),
+				last_cursor: cursor,
+			},
+		);
+
+		Ok(())
+	}
+
+	/// Write the trailer of the file at `location`, which was flushed and closed.
+	pub(crate) fn closed(&mut self, location: &(PathBuf, String)) -> Result<()> {
+		self.current.closed(location);
+		match self.files.remove(location) {
+			Some(summary) => self.write(summary),
+			None => Ok(()),
+		}
+	}
+
+	/// Write the trailers of all files, at exit once they are flushed.
+	pub(crate) fn close_all(&mut self) -> Result<()> {
+		for (location, summary) in std::mem::take(&mut self.files) {
+			self.current.closed(&location);
+			self.write(summary)?;
+		}
+
+		Ok(())
+	}
+
+	fn write(&self, summary: FileSummary) -> Result<()> {
+		let path = match &summary.path {
+			Some(path) => path,
+			None => return Ok(()),
+		};
+		// moved away by external rotation, or gone with its directory
+		if !path.exists() {
+			debug!("not writing trailer of vanished {}", path.display());
+			return Ok(());
+		}
+		let time = |us: i64| {
+			chrono::NaiveDateTime::from_timestamp(
+				us.div_euclid(1_000_000),
+				(us.rem_euclid(1_000_000) * 1_000) as u32,
+			)
+			.format("%Y-%m-%dT%H:%M:%S%.6fZ")
+			.to_string()
+		};
+		let trailer = serde_json::json!({
+			"file": path,
+			"entries": summary.entries,
+			"first_timestamp": time(summary.first_timestamp_us),
+			"last_timestamp": time(summary.last_timestamp_us),
+			"first_cursor": summary.first_cursor,
+			"last_cursor": summary.last_cursor,
+		});
+
+		let (target, line) = match self.format {
+			TrailerFormat::Inline => (
+				path.clone(),
+				format!("#journald-writer-trailer {}\n", trailer),
+			),
+			TrailerFormat::Sidecar => {
+				let mut sidecar = path.clone().into_os_string();
+				sidecar.push(SIDECAR_SUFFIX);
+				(PathBuf::from(sidecar), format!("{}\n", trailer))
+			}
+		};
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&target)
+			.with_context(|| format!("Opening {} for the trailer", target.display()))?;
+		file.write_all(line.as_bytes())
+			.with_context(|| format!("Writing trailer to {}", target.display()))?;
+		debug!(
+			"wrote trailer of {} with {} entries",
+			path.display(),
+			summary.entries
+		);
+
+		Ok(())
+	}
+}
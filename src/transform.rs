@@ -0,0 +1,31 @@
+use journald::JournalEntry;
+
+use crate::redact::Pattern;
+
+/// A regular expression replacement of `message_transforms`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageTransform {
+	pub pattern: Pattern,
+	/// Replaces every match, `$1` or `${name}` insert capture groups
+	#[serde(default)]
+	pub replacement: String,
+}
+
+/// A copy of the entry with all transforms applied to its message, in order.
+pub(crate) fn apply(transforms: &[MessageTransform], entry: &JournalEntry) -> JournalEntry {
+	let mut transformed = entry.clone();
+	if let Some(message) = entry.get_message() {
+		let mut message = message.to_string();
+		for transform in transforms {
+			message = transform
+				.pattern
+				.regex()
+				.replace_all(&message, transform.replacement.as_str())
+				.into_owned();
+		}
+		transformed.set_field("MESSAGE", &message);
+	}
+
+	transformed
+}
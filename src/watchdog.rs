@@ -0,0 +1,132 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::*;
+
+use crate::config::ErrorPolicy;
+
+thread_local! {
+	/// Base and start of the write of the watchdog of this thread's forwarder, see `disarmed`
+	static CURRENT: RefCell<Option<(Instant, Arc<AtomicU64>)>> = RefCell::new(None);
+}
+
+/// Run `f` without the watchdog of the current thread counting its time, e.g. while
+/// deliberately blocking until space is freed. The current write is watched again from the
+/// end of `f` on.
+pub(crate) fn disarmed<T>(f: impl FnOnce() -> T) -> T {
+	let current = CURRENT.with(|current| current.borrow().clone());
+	let (base, started) = match current {
+		Some(current) => current,
+		None => return f(),
+	};
+	let armed = started.swap(0, Ordering::Relaxed) != 0;
+	let result = f();
+	if armed {
+		started.store(base.elapsed().as_millis() as u64 + 1, Ordering::Relaxed);
+	}
+
+	result
+}
+
+/// Detects writes to the output which take longer than a deadline.
+///
+/// A blocked `write` can't be interrupted, so on timeout the watchdog logs an error and, if
+/// the error policy is to abort, terminates the process. The thread is stopped once the
+/// watchdog is dropped. It watches the writes of the thread spawning it, see `disarmed`.
+pub(crate) struct Watchdog {
+	base: Instant,
+	/// Milliseconds since `base` at which the current write started, plus one. Zero if idle.
+	started: Arc<AtomicU64>,
+	stop: Arc<AtomicBool>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+	pub(crate) fn spawn(timeout: Duration, policy: ErrorPolicy) -> Result<Self> {
+		let base = Instant::now();
+		let started = Arc::new(AtomicU64::new(0));
+
+		let stop = Arc::new(AtomicBool::new(false));
+
+		let thread_started = started.clone();
+		let thread_stop = stop.clone();
+		let interval = (timeout / 4).max(Duration::from_millis(100));
+		let thread = thread::Builder::new()
+			.name("write-watchdog".to_string())
+			.spawn(move || {
+				let mut reported = 0;
+				loop {
+					// unparked when dropped
+					thread::park_timeout(interval);
+					if thread_stop.load(Ordering::Relaxed) {
+						return;
+					}
+
+					let started = thread_started.load(Ordering::Relaxed);
+					if started == 0 || started == reported {
+						continue;
+					}
+					let elapsed = base.elapsed().as_millis() as u64 - (started - 1);
+					if elapsed < timeout.as_millis() as u64 {
+						continue;
+					}
+
+					error!("write to output blocked for more than {:?}", timeout);
+					if policy == ErrorPolicy::Abort {
+						std::process::exit(1);
+					}
+					reported = started;
+				}
+			})
+			.context("Spawning watchdog thread")?;
+
+		CURRENT.with(|current| *current.borrow_mut() = Some((base, started.clone())));
+		Ok(Self {
+			base,
+			started,
+			stop,
+			thread: Some(thread),
+		})
+	}
+
+	/// Start watching a write, which is done once the returned guard is dropped.
+	pub(crate) fn arm(&self) -> WatchdogGuard<'_> {
+		let now = self.base.elapsed().as_millis() as u64 + 1;
+		self.started.store(now, Ordering::Relaxed);
+		WatchdogGuard { watchdog: self }
+	}
+}
+
+impl Drop for Watchdog {
+	fn drop(&mut self) {
+		CURRENT.with(|current| {
+			let mut current = current.borrow_mut();
+			if let Some((_, started)) = current.as_ref() {
+				if Arc::ptr_eq(started, &self.started) {
+					*current = None;
+				}
+			}
+		});
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			thread.thread().unpark();
+			if thread.join().is_err() {
+				warn!("watchdog thread panicked");
+			}
+		}
+	}
+}
+
+pub(crate) struct WatchdogGuard<'a> {
+	watchdog: &'a Watchdog,
+}
+
+impl Drop for WatchdogGuard<'_> {
+	fn drop(&mut self) {
+		self.watchdog.started.store(0, Ordering::Relaxed);
+	}
+}
@@ -1,89 +1,1370 @@
+use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::fs::{rename, OpenOptions};
 use std::io::Write;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use anyhow::{bail, Context, Result};
 use chrono::TimeZone;
 use journald::JournalEntry;
+use log::*;
 
-pub(crate) fn write_log_line<W: Write, P: AsRef<Path>>(
-	log: JournalEntry,
+use crate::config::{Config, IdentifierColonPolicy, MissingTimestampPolicy};
+use crate::fingerprint;
+use crate::status;
+use crate::syslog::Facility;
+use crate::template::{Placeholder, Segment, Timezone};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+	/// One human readable line per entry
+	Text,
+	/// The journal export format, with all fields of the entry
+	Export,
+	/// RFC 3164 syslog messages, as understood by local syslog daemons
+	Syslog,
+	/// One JSON object per line, with the time as `timestamp` and all fields of the entry
+	Json,
+	/// The fields of `json` as MessagePack maps, each prefixed with its length as big endian
+	/// 32 bit integer
+	#[serde(rename = "messagepack")]
+	MessagePack,
+	/// Audit records in the layout of auditd's log, other entries as `text`
+	Audit,
+	/// One row of `csv_columns` per entry, quoted as in RFC 4180
+	Csv,
+	/// `csv` separated by tabs, with lines ending in `\n` instead of `\r\n`
+	Tsv,
+	/// One JSON object per line with the field names of the Elastic Common Schema
+	Ecs,
+	/// Kernel messages like `dmesg --decode`, other entries as `text`
+	Kernel,
+	/// `text` with `{utc_time}` and `{local_time}` collapsed into one timestamp in
+	/// `timezone`, including its offset
+	ZonedText,
+}
+
+impl OutputFormat {
+	/// Separator and line ending of the csv formats
+	fn delimiters(&self) -> Option<(char, &'static str)> {
+		match self {
+			OutputFormat::Csv => Some((',', "\r\n")),
+			OutputFormat::Tsv => Some(('\t', "\n")),
+			_ => None,
+		}
+	}
+
+	/// Whether the fields returned by `enrich_command` are written, instead of those of the
+	/// entry.
+	pub(crate) fn uses_enriched(&self) -> bool {
+		matches!(
+			self,
+			OutputFormat::Json | OutputFormat::MessagePack | OutputFormat::Csv | OutputFormat::Tsv
+		)
+	}
+}
+
+impl Default for OutputFormat {
+	fn default() -> Self {
+		OutputFormat::Text
+	}
+}
+
+/// A journal entry together with the values derived from it, computed once and shared by
+/// all sinks.
+pub(crate) struct Record<'a> {
+	pub(crate) entry: &'a JournalEntry,
+	/// Time in microseconds since the epoch, as rendered in the output
+	pub(crate) timestamp_us: i64,
+	/// Reception time in microseconds since the epoch
+	pub(crate) received_us: i64,
+	/// Time the entry was processed by the forwarder
+	pub(crate) ingested_at: chrono::DateTime<chrono::Utc>,
+	/// Filters the entry passed, see `annotate_match`
+	pub(crate) matched: Option<String>,
+	/// Number of the record in the forwarded stream, see `forwarder_seq`
+	pub(crate) seq: Option<u64>,
+	/// Order among records with the same timestamp, see `timestamp_tiebreaker`
+	pub(crate) tiebreaker: Option<u64>,
+	/// Entries filtered out since the previous record, with `skipped_since_last`
+	pub(crate) skipped_since_last: Option<u64>,
+	pub(crate) priority: Priority,
+	/// Truncate the message to this many bytes, see `max_entry_bytes`
+	pub(crate) max_message_bytes: Option<usize>,
+	/// Fields returned by `enrich_command`, written instead of those of the entry in the
+	/// structured formats
+	pub(crate) enriched: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl<'a> Record<'a> {
+	/// `None` if the entry has no usable time and `on_missing_timestamp` is `skip`.
+	pub(crate) fn new(entry: &'a JournalEntry, config: &Config) -> Result<Option<Self>> {
+		let received_us = match reception_time(entry) {
+			Some(received_us) => received_us,
+			None => match config.on_missing_timestamp {
+				MissingTimestampPolicy::UseNow => {
+					debug!("entry without timestamp, using the current time");
+					chrono::Utc::now().timestamp_micros()
+				}
+				MissingTimestampPolicy::Skip => return Ok(None),
+			},
+		};
+		let timestamp_us = match &config.timestamp_field {
+			Some(field) => field_timestamp(entry, field).unwrap_or(received_us),
+			None => received_us,
+		};
+
+		// default to emerge
+		let priority = entry
+			.get_field("PRIORITY")
+			.map(|v| v.try_into().ok())
+			.flatten()
+			.unwrap_or(Priority::Emerg);
+
+		Ok(Some(Self {
+			entry,
+			timestamp_us,
+			received_us,
+			ingested_at: chrono::Utc::now(),
+			matched: None,
+			seq: None,
+			tiebreaker: None,
+			skipped_since_last: None,
+			priority,
+			max_message_bytes: None,
+			enriched: None,
+		}))
+	}
+
+	/// Monotonic time since boot of `_BOOT_ID` in microseconds, if known
+	pub(crate) fn monotonic_us(&self) -> Option<u64> {
+		self.entry
+			.get_field("__MONOTONIC_TIMESTAMP")
+			.and_then(|us| us.parse().ok())
+	}
+
+	pub(crate) fn time_utc(&self) -> chrono::DateTime<chrono::Utc> {
+		let time = chrono::NaiveDateTime::from_timestamp(
+			self.timestamp_us.div_euclid(1_000_000),
+			(self.timestamp_us.rem_euclid(1_000_000) * 1_000) as u32,
+		);
+		chrono::DateTime::from_utc(time, chrono::Utc)
+	}
+
+	/// Clamp the timestamp to be at least `last`, and remember the result in `last`.
+	pub(crate) fn clamp_monotonic(&mut self, last: &mut Option<i64>) {
+		if let Some(last) = *last {
+			if self.timestamp_us < last {
+				debug!(
+					"clamping timestamp {} to previous timestamp {}",
+					self.timestamp_us, last
+				);
+				self.timestamp_us = last;
+			}
+		}
+		*last = Some(self.timestamp_us);
+	}
+}
+
+/// Reception time of the entry, falling back to the time given by the sender and the
+/// `__REALTIME_TIMESTAMP` field.
+fn reception_time(entry: &JournalEntry) -> Option<i64> {
+	match entry.get_reception_wallclock_time() {
+		Ok(time) => return Some(time.timestamp_us),
+		Err(e) => debug!("failed to get wallclock time from systemd: {}", e),
+	}
+
+	["_SOURCE_REALTIME_TIMESTAMP", "__REALTIME_TIMESTAMP"]
+		.iter()
+		.find_map(|field| entry.get_field(field).and_then(|us| us.parse().ok()))
+}
+
+/// Parse the field as microseconds since the epoch or RFC 3339 time, if present and valid.
+fn field_timestamp(entry: &JournalEntry, field: &str) -> Option<i64> {
+	let value = entry.get_field(field)?;
+	if let Ok(timestamp_us) = value.parse::<i64>() {
+		return Some(timestamp_us);
+	}
+	match chrono::DateTime::parse_from_rfc3339(value) {
+		Ok(time) => Some(time.timestamp_micros()),
+		Err(e) => {
+			debug!("ignoring malformed timestamp {}={:?}: {}", field, value, e);
+			None
+		}
+	}
+}
+
+/// Write the entry in the given format, and flush the writer.
+pub(crate) fn write_log_line<W: Write>(
+	record: &Record<'_>,
+	writer: &mut W,
+	format: OutputFormat,
+	config: &Config,
+) -> Result<()> {
+	match format {
+		OutputFormat::Text => write_text(record, writer, config, false)?,
+		OutputFormat::ZonedText => write_text(record, writer, config, true)?,
+		OutputFormat::Export => write_export(
+			record.entry,
+			writer,
+			record.max_message_bytes,
+			config.force_hostname.as_deref(),
+		)?,
+		OutputFormat::Json => write_json(record, writer, config)?,
+		OutputFormat::MessagePack => write_msgpack(record, writer, config)?,
+		OutputFormat::Audit if record.entry.get_field("_TRANSPORT") == Some("audit") => {
+			write_audit(record, writer)?
+		}
+		OutputFormat::Audit => write_text(record, writer, config, false)?,
+		OutputFormat::Kernel if record.entry.get_field("_TRANSPORT") == Some("kernel") => {
+			write_kernel(record, writer, config)?
+		}
+		OutputFormat::Kernel => write_text(record, writer, config, false)?,
+		OutputFormat::Syslog => {
+			write_syslog(record, writer, config)?;
+			writeln!(writer).context("write to log_writer")?;
+		}
+		OutputFormat::Csv | OutputFormat::Tsv => {
+			let fields = record_fields(record, config);
+			write_csv(writer, format, &config.csv_columns, &fields)?
+		}
+		OutputFormat::Ecs => write_ecs(record, writer, config)?,
+	}
+
+	writer.flush().context("Flushing writer")?;
+
+	Ok(())
+}
+
+/// Write the entry as it is in the export format, for sinks writing entries without a
+/// `Record`.
+pub(crate) fn write_passthrough<W: Write>(
+	entry: &JournalEntry,
+	writer: &mut W,
+	config: &Config,
+) -> Result<()> {
+	write_export(entry, writer, None, config.force_hostname.as_deref())
+}
+
+/// Persist the cursor of the entry, once it has been written to all sinks.
+pub(crate) fn update_cursor<P: AsRef<Path>>(log: &JournalEntry, cursor_path: P) -> Result<()> {
+	if let Some(cursor) = log.get_field("__CURSOR") {
+		write_cursor(cursor, cursor_path)?;
+		status::set_cursor(cursor);
+	}
+
+	Ok(())
+}
+
+/// Write the entry as text, with `zoned` only the first time placeholder as timestamp in
+/// `timezone`.
+fn write_text<W: Write>(
+	record: &Record<'_>,
+	writer: &mut W,
+	config: &Config,
+	zoned: bool,
+) -> Result<()> {
+	let log = record.entry;
+	let mut time_written = false;
+	let mut time_skipped = false;
+
+	for segment in config.text_template.segments() {
+		let placeholder = match segment {
+			Segment::Literal(literal) => {
+				// drop the separator of a skipped time placeholder, too
+				let literal = if time_skipped {
+					literal.strip_prefix(' ').unwrap_or(literal)
+				} else {
+					literal
+				};
+				time_skipped = false;
+				writer
+					.write_all(literal.as_bytes())
+					.context("write to log_writer")?;
+				continue;
+			}
+			Segment::Placeholder(placeholder) => placeholder,
+		};
+		time_skipped = false;
+
+		let written = match placeholder {
+			Placeholder::UtcTime | Placeholder::LocalTime if zoned && time_written => {
+				time_skipped = true;
+				continue;
+			}
+			Placeholder::UtcTime | Placeholder::LocalTime if zoned => {
+				time_written = true;
+				let time_utc = record.time_utc().naive_utc();
+				match config.timezone {
+					Timezone::Local => format_zoned_time(
+						writer,
+						chrono::Local.from_utc_datetime(&time_utc),
+						config,
+					),
+					Timezone::Fixed(offset) => {
+						format_zoned_time(writer, offset.from_utc_datetime(&time_utc), config)
+					}
+				}
+			}
+			Placeholder::UtcTime => format_time(writer, record.time_utc(), config),
+			Placeholder::LocalTime => {
+				let time_local = chrono::Local.from_utc_datetime(&record.time_utc().naive_utc());
+				format_time(writer, time_local, config)
+			}
+			Placeholder::Severity => write!(writer, "{}", record.priority),
+			Placeholder::SeverityKeyword => write!(writer, "{:#}", record.priority),
+			Placeholder::OtelSeverity => write!(writer, "{}", record.priority.otel_severity()),
+			Placeholder::UnitName => {
+				let unit_name = match config.unit_field.as_str() {
+					"_HOSTNAME" => None,
+					field => log.get_field(field),
+				}
+				.or_else(|| hostname(log, config))
+				.unwrap_or("");
+				write!(writer, "{}", pad(unit_name, config.pad_unit))
+			}
+			Placeholder::Identifier => {
+				let identifier = identifier(log, config)
+					.map(|identifier| escape_identifier_colons(identifier, config))
+					.unwrap_or_default();
+				write!(writer, "{}", pad(&identifier, config.pad_identifier))
+			}
+			Placeholder::Message if config.quote_message => {
+				write!(writer, "{}", quote(&text_message(record, config)?))
+			}
+			Placeholder::Message => write!(writer, "{}", text_message(record, config)?),
+			Placeholder::EpochUs => write!(writer, "{}", record.received_us),
+			Placeholder::IngestedAt => write!(
+				writer,
+				"{}",
+				record
+					.ingested_at
+					.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+			),
+			Placeholder::Seq => match record.seq {
+				Some(seq) => write!(writer, "{}", seq),
+				None => Ok(()),
+			},
+			Placeholder::Match => {
+				write!(writer, "{}", record.matched.as_deref().unwrap_or(""))
+			}
+			Placeholder::Monotonic => match record.monotonic_us() {
+				Some(us) => write!(writer, "{}.{:06}", us / 1_000_000, us % 1_000_000),
+				None => Ok(()),
+			},
+			Placeholder::InstanceId => {
+				write!(writer, "{}", config.instance_id.as_deref().unwrap_or(""))
+			}
+			Placeholder::ContainerName => {
+				write!(writer, "{}", log.get_field("CONTAINER_NAME").unwrap_or(""))
+			}
+			Placeholder::ContainerId => {
+				write!(writer, "{}", log.get_field("CONTAINER_ID").unwrap_or(""))
+			}
+			Placeholder::ImageName => {
+				write!(writer, "{}", log.get_field("IMAGE_NAME").unwrap_or(""))
+			}
+			Placeholder::Transport => {
+				write!(writer, "{}", log.get_field("_TRANSPORT").unwrap_or(""))
+			}
+			Placeholder::Cmdline => match log.get_field("_CMDLINE") {
+				Some(cmdline) => write!(writer, "{}", decode_cmdline(cmdline)),
+				None => Ok(()),
+			},
+			Placeholder::RemoteHost => write!(writer, "{}", remote_host(log, config).unwrap_or("")),
+			Placeholder::MsgHash => match &config.message_hash {
+				Some(hash) => write!(writer, "{}", fingerprint::message_hash(record, hash)),
+				None => Ok(()),
+			},
+		};
+		written.context("write to log_writer")?;
+	}
+	writeln!(writer).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// Write the time in `timestamp_format`, or RFC 3339 with seconds precision.
+fn format_time<W: Write, Tz: TimeZone>(
+	writer: &mut W,
+	time: chrono::DateTime<Tz>,
+	config: &Config,
+) -> std::io::Result<()>
+where
+	Tz::Offset: fmt::Display,
+{
+	match &config.timestamp_format {
+		Some(format) => write!(writer, "{}", time.format(format.as_str())),
+		None => write!(
+			writer,
+			"{}",
+			time.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+		),
+	}
+}
+
+/// `format_time`, followed by the offset if `timestamp_format` leaves it out, as the single
+/// timestamp of `zoned_text` is only unambiguous with it.
+fn format_zoned_time<W: Write, Tz: TimeZone>(
+	writer: &mut W,
+	time: chrono::DateTime<Tz>,
+	config: &Config,
+) -> std::io::Result<()>
+where
+	Tz::Offset: fmt::Display,
+{
+	match &config.timestamp_format {
+		Some(format) if !format.has_offset() => {
+			write!(writer, "{}", time.format(format.as_str()))?;
+			write!(writer, "{}", time.format("%:z"))
+		}
+		_ => format_time(writer, time, config),
+	}
+}
+
+fn write_json<W: Write>(record: &Record<'_>, writer: &mut W, config: &Config) -> Result<()> {
+	serde_json::to_writer(&mut *writer, &RecordFields { record, config })
+		.context("write to log_writer")?;
+	writeln!(writer).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// The fields of the json format as one line, as passed to `enrich_command`.
+pub(crate) fn json_line(record: &Record<'_>, config: &Config) -> Result<Vec<u8>> {
+	let mut line = Vec::new();
+	write_json(record, &mut line, config)?;
+	Ok(line)
+}
+
+/// The json format indented over multiple lines, followed by a blank line.
+pub(crate) fn write_json_pretty<W: Write>(
+	record: &Record<'_>,
+	writer: &mut W,
+	config: &Config,
+) -> Result<()> {
+	serde_json::to_writer_pretty(&mut *writer, &RecordFields { record, config })
+		.context("write to log_writer")?;
+	writeln!(writer, "\n").context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// Version of the Elastic Common Schema the `ecs` format follows
+pub(crate) const ECS_VERSION: &str = "8.11.0";
+
+/// The entry as JSON object named as in the Elastic Common Schema, so it can be indexed
+/// without an ingest pipeline. Fields without an ECS counterpart are left out.
+fn write_ecs<W: Write>(record: &Record<'_>, writer: &mut W, config: &Config) -> Result<()> {
+	let log = record.entry;
+	let mut syslog = serde_json::json!({
+		"severity": {
+			"code": record.priority as u8,
+			"name": record.priority.keyword(),
+		},
+	});
+	if let Some(facility) = Facility::of(log) {
+		syslog["facility"] = serde_json::json!({
+			"code": facility.code(),
+			"name": facility.name(),
+		});
+	}
+
+	let mut process = serde_json::Map::new();
+	if let Some(pid) = log
+		.get_field("_PID")
+		.or_else(|| log.get_field("SYSLOG_PID"))
+		.and_then(|pid| pid.parse::<u32>().ok())
+	{
+		process.insert("pid".to_string(), pid.into());
+	}
+	if let Some(name) =
+		identifier(log, config).or_else(|| log.get_field("_COMM").map(Cow::Borrowed))
+	{
+		process.insert("name".to_string(), name.into_owned().into());
+	}
+	for (field, key) in &[("_EXE", "executable"), ("_CMDLINE", "command_line")] {
+		if let Some(value) = log.get_field(field) {
+			let value = truncate(value, config.max_field_bytes);
+			process.insert(key.to_string(), value.into());
+		}
+	}
+
+	let mut ecs = serde_json::json!({
+		"@timestamp": record
+			.time_utc()
+			.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+		"ecs": { "version": ECS_VERSION },
+		"message": text_message(record, config)?,
+		"log": {
+			"level": record.priority.keyword(),
+			"syslog": syslog,
+		},
+	});
+	if !process.is_empty() {
+		ecs["process"] = process.into();
+	}
+	if let Some(hostname) = hostname(log, config) {
+		ecs["host"] = serde_json::json!({ "name": hostname });
+	}
+	if let Some(uid) = log.get_field("_UID") {
+		ecs["user"] = serde_json::json!({ "id": uid });
+	}
+	if let Some(instance_id) = &config.instance_id {
+		ecs["agent"] = serde_json::json!({ "id": instance_id });
+	}
+	if config.ingestion_timestamp {
+		ecs["event"] = serde_json::json!({
+			"ingested": record
+				.ingested_at
+				.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+		});
+	}
+
+	serde_json::to_writer(&mut *writer, &ecs).context("write to log_writer")?;
+	writeln!(writer).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// The fields of the json format as MessagePack map, prefixed with its length as big endian
+/// 32 bit integer.
+fn write_msgpack<W: Write>(record: &Record<'_>, writer: &mut W, config: &Config) -> Result<()> {
+	let data = rmp_serde::to_vec_named(&RecordFields { record, config })
+		.context("Serializing entry as MessagePack")?;
+	write_length_prefixed(writer, &data)
+}
+
+pub(crate) fn write_length_prefixed<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+	writer
+		.write_all(&(data.len() as u32).to_be_bytes())
+		.context("write to log_writer")?;
+	writer.write_all(data).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// Values of `columns` in `fields` as one row of the csv format, empty if missing.
+pub(crate) fn write_csv<W: Write>(
+	writer: &mut W,
+	format: OutputFormat,
+	columns: &[String],
+	fields: &serde_json::Map<String, serde_json::Value>,
+) -> Result<()> {
+	let values = columns.iter().map(|column| match fields.get(column) {
+		Some(serde_json::Value::String(value)) => Cow::Borrowed(value.as_str()),
+		Some(value) => Cow::Owned(value.to_string()),
+		None => Cow::Borrowed(""),
+	});
+	write_csv_row(writer, format, values)
+}
+
+/// The column names of the csv formats, if `csv_header` is set.
+pub(crate) fn csv_header(format: OutputFormat, config: &Config) -> Result<Option<Vec<u8>>> {
+	if !config.csv_header || format.delimiters().is_none() {
+		return Ok(None);
+	}
+
+	let mut header = Vec::new();
+	let columns = config
+		.csv_columns
+		.iter()
+		.map(|column| Cow::Borrowed(column.as_str()));
+	write_csv_row(&mut header, format, columns)?;
+	Ok(Some(header))
+}
+
+fn write_csv_row<'a, W: Write, I: Iterator<Item = Cow<'a, str>>>(
 	writer: &mut W,
-	cursor_path: P,
-	cursor_update: bool
+	format: OutputFormat,
+	values: I,
 ) -> Result<()> {
-	let time = log
-		.get_reception_wallclock_time()
-		.context("Failed to get wallcklock time from systemd")?
-		.timestamp_us;
-	let time =
-		chrono::NaiveDateTime::from_timestamp(time / 1_000 / 1_000, time as u32 % 1_000 % 1_000);
-	let time_utc: chrono::DateTime<chrono::Utc> = chrono::DateTime::from_utc(time, chrono::Utc);
-	let time_local = chrono::Local.from_utc_datetime(&time);
-
-	// default to emerge
-	let prio = log
-		.get_field("PRIORITY")
-		.map(|v| v.try_into().ok())
-		.flatten()
-		.unwrap_or(Priority::Emerg);
-
-	let hostname = log.get_field("_HOSTNAME").unwrap_or("airlink");
-
-	let identifier = log.get_field("SYSLOG_IDENTIFIER").unwrap_or("");
+	let (delimiter, line_end) = format.delimiters().unwrap_or((',', "\r\n"));
+	let mut row = String::new();
+	for (i, value) in values.enumerate() {
+		if i > 0 {
+			row.push(delimiter);
+		}
+		if value.contains(|c: char| c == delimiter || c == '"' || c == '\r' || c == '\n') {
+			row.push('"');
+			row.push_str(&value.replace('"', "\"\""));
+			row.push('"');
+		} else {
+			row.push_str(&value);
+		}
+	}
+	row.push_str(line_end);
+	writer
+		.write_all(row.as_bytes())
+		.context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// A value of the structured formats, borrowed from the entry or config where possible
+enum FieldValue<'a> {
+	Str(&'a str),
+	String(String),
+	Time(chrono::DateTime<chrono::Utc>),
+	Int(i64),
+	Float(f64),
+	/// The priority in all its representations, see `rich_severity`
+	Severity(Priority),
+}
+
+impl serde::Serialize for FieldValue<'_> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match self {
+			FieldValue::Str(value) => serializer.serialize_str(value),
+			FieldValue::String(value) => serializer.serialize_str(value),
+			FieldValue::Time(time) => {
+				serializer.collect_str(&time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+			}
+			FieldValue::Int(value) => serializer.serialize_i64(*value),
+			FieldValue::Float(value) => serializer.serialize_f64(*value),
+			FieldValue::Severity(priority) => {
+				use serde::ser::SerializeMap;
+
+				let mut map = serializer.serialize_map(Some(4))?;
+				map.serialize_entry("code", &(*priority as u8))?;
+				map.serialize_entry("keyword", priority.keyword())?;
+				map.serialize_entry("name", &priority.to_string())?;
+				map.serialize_entry("otel", &priority.otel_severity())?;
+				map.end()
+			}
+		}
+	}
+}
+
+impl From<FieldValue<'_>> for serde_json::Value {
+	fn from(value: FieldValue<'_>) -> Self {
+		match value {
+			FieldValue::Str(value) => value.into(),
+			FieldValue::String(value) => value.into(),
+			FieldValue::Time(time) => time
+				.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+				.into(),
+			FieldValue::Int(value) => value.into(),
+			FieldValue::Float(value) => value.into(),
+			FieldValue::Severity(priority) => serde_json::json!({
+				"code": priority as u8,
+				"keyword": priority.keyword(),
+				"name": priority.to_string(),
+				"otel": priority.otel_severity(),
+			}),
+		}
+	}
+}
+
+/// Pass each field of the structured formats to `visit`: the time as `timestamp`, the
+/// derived values, `MESSAGE` and the other fields of the entry, without collecting them
+/// first.
+fn visit_fields<'a, E>(
+	record: &Record<'a>,
+	config: &'a Config,
+	mut visit: impl FnMut(&'a str, FieldValue<'a>) -> std::result::Result<(), E>,
+) -> std::result::Result<(), E> {
+	let log = record.entry;
+	visit("timestamp", FieldValue::Time(record.time_utc()))?;
+	visit(
+		"otel_severity",
+		FieldValue::Int(record.priority.otel_severity().into()),
+	)?;
+	if config.rich_severity {
+		visit("severity", FieldValue::Severity(record.priority))?;
+	}
+	visit("epoch_us", FieldValue::Int(record.received_us))?;
+	if let Some(us) = record.monotonic_us() {
+		visit("monotonic", FieldValue::Float(us as f64 / 1_000_000.0))?;
+	}
+	if let Some(message) = log.get_message() {
+		let message = truncate(
+			truncate(message, record.max_message_bytes),
+			config.max_field_bytes,
+		);
+		visit("MESSAGE", FieldValue::Str(message))?;
+	}
+
+	let fields = log.get_fields().iter().filter(|(key, _)| *key != "MESSAGE");
+	let mut truncated_fields = 0;
+	for (i, (key, value)) in fields.enumerate() {
+		if config.max_fields.map_or(false, |max| i >= max) {
+			truncated_fields += 1;
+			continue;
+		}
+		let value = match &config.force_hostname {
+			Some(hostname) if key == "_HOSTNAME" => hostname.as_str(),
+			_ => truncate(value, config.max_field_bytes),
+		};
+		if key == "SYSLOG_IDENTIFIER" && normalizes_identifier(config) {
+			let value = match normalize_identifier(value, config) {
+				Cow::Borrowed(value) => FieldValue::Str(value),
+				Cow::Owned(value) => FieldValue::String(value),
+			};
+			visit(key, value)?;
+			continue;
+		}
+		visit(key, FieldValue::Str(value))?;
+	}
+	if normalizes_identifier(config) {
+		if let Some(identifier) = log.get_field("SYSLOG_IDENTIFIER") {
+			visit(
+				"raw_identifier",
+				FieldValue::Str(truncate(identifier, config.max_field_bytes)),
+			)?;
+		}
+	}
+	if truncated_fields > 0 {
+		visit("_truncated_fields", FieldValue::Int(truncated_fields))?;
+	}
+	if let Some(hostname) = &config.force_hostname {
+		if log.get_field("_HOSTNAME").is_none() {
+			visit("_HOSTNAME", FieldValue::Str(hostname))?;
+		}
+	}
+	if let Some(instance_id) = &config.instance_id {
+		visit("instance_id", FieldValue::Str(instance_id))?;
+	}
+	if config.ingestion_timestamp {
+		visit("ingested_at", FieldValue::Time(record.ingested_at))?;
+	}
+	if let Some(matched) = &record.matched {
+		visit("_match", FieldValue::String(matched.clone()))?;
+	}
+	if let Some(seq) = record.seq {
+		visit("seq", FieldValue::Int(seq as i64))?;
+	}
+	if let Some(tiebreaker) = record.tiebreaker {
+		visit("timestamp_tiebreaker", FieldValue::Int(tiebreaker as i64))?;
+	}
+	if let Some(skipped) = record.skipped_since_last {
+		visit("_skipped_since_last", FieldValue::Int(skipped as i64))?;
+	}
+	if config.include_transport {
+		let transport = log.get_field("_TRANSPORT").unwrap_or("");
+		visit("transport", FieldValue::Str(transport))?;
+	}
+	if let Some(hash) = &config.message_hash {
+		visit(
+			"msg_hash",
+			FieldValue::String(fingerprint::message_hash(record, hash)),
+		)?;
+	}
+	if let Some(remote_host) = remote_host(log, config) {
+		visit("remote_host", FieldValue::Str(remote_host))?;
+	}
+	if config.include_cmdline {
+		if let Some(cmdline) = log.get_field("_CMDLINE") {
+			let value = match decode_cmdline(cmdline) {
+				Cow::Borrowed(cmdline) => FieldValue::Str(cmdline),
+				Cow::Owned(cmdline) => FieldValue::String(cmdline),
+			};
+			visit("cmdline", value)?;
+		}
+	}
+	if config.container_enrichment {
+		for (field, key) in CONTAINER_FIELDS.iter() {
+			if let Some(value) = log.get_field(field) {
+				visit(
+					key,
+					FieldValue::Str(truncate(value, config.max_field_bytes)),
+				)?;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// The fields of the structured formats, serialized straight from the entry.
+struct RecordFields<'r, 'a> {
+	record: &'r Record<'a>,
+	config: &'a Config,
+}
+
+impl serde::Serialize for RecordFields<'_, '_> {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		use serde::ser::SerializeMap;
+
+		if let Some(enriched) = &self.record.enriched {
+			return enriched.serialize(serializer);
+		}
+
+		if self.config.sort_fields || !self.config.field_order.is_empty() {
+			let mut fields = Vec::new();
+			visit_fields(self.record, self.config, |key, value| {
+				fields.push((key, value));
+				Ok::<(), S::Error>(())
+			})?;
+			let order = &self.config.field_order;
+			fields.sort_by_key(|(key, _)| {
+				let position = order.iter().position(|field| field.as_str() == *key);
+				(
+					position.unwrap_or(order.len()),
+					self.config.sort_fields.then(|| *key),
+				)
+			});
+
+			let mut map = serializer.serialize_map(Some(fields.len()))?;
+			for (key, value) in &fields {
+				map.serialize_entry(key, value)?;
+			}
+			return map.end();
+		}
+
+		// MessagePack needs the length up front
+		let mut len = 0;
+		visit_fields(self.record, self.config, |_, _| {
+			len += 1;
+			Ok::<(), S::Error>(())
+		})?;
+
+		let mut map = serializer.serialize_map(Some(len))?;
+		visit_fields(self.record, self.config, |key, value| {
+			map.serialize_entry(key, &value)
+		})?;
+		map.end()
+	}
+}
+
+/// Fields of the structured formats collected into a map, for lookups by name.
+pub(crate) fn record_fields(
+	record: &Record<'_>,
+	config: &Config,
+) -> serde_json::Map<String, serde_json::Value> {
+	if let Some(enriched) = &record.enriched {
+		return enriched.clone();
+	}
+
+	let mut object = serde_json::Map::new();
+	visit_fields(record, config, |key, value| {
+		object.insert(key.to_string(), value.into());
+		Ok::<(), std::convert::Infallible>(())
+	})
+	.unwrap();
+
+	object
+}
+
+/// Fields set by Docker and Podman, with their key in the json formats
+const CONTAINER_FIELDS: [(&str, &str); 3] = [
+	("CONTAINER_NAME", "container_name"),
+	("CONTAINER_ID", "container_id"),
+	("IMAGE_NAME", "image_name"),
+];
+
+/// `force_hostname`, or the entry's `_HOSTNAME`
+fn hostname<'a>(log: &'a JournalEntry, config: &'a Config) -> Option<&'a str> {
+	config
+		.force_hostname
+		.as_deref()
+		.or_else(|| log.get_field("_HOSTNAME"))
+}
+
+/// `_HOSTNAME` of an entry logged on another machine, which is only forwarded without
+/// `only_local`.
+fn remote_host<'a>(log: &'a JournalEntry, config: &Config) -> Option<&'a str> {
+	let machine_id = config.machine_id.as_deref()?;
+	match log.get_field("_MACHINE_ID") {
+		Some(entry_machine_id) if entry_machine_id != machine_id => {
+			Some(log.get_field("_HOSTNAME").unwrap_or(entry_machine_id))
+		}
+		_ => None,
+	}
+}
+
+/// `SYSLOG_IDENTIFIER`, or `CONTAINER_NAME` with `container_enrichment`, normalized by
+/// `identifier_basename` and `lowercase_identifier`
+fn identifier<'a>(log: &'a JournalEntry, config: &Config) -> Option<Cow<'a, str>> {
+	log.get_field("SYSLOG_IDENTIFIER")
+		.or_else(|| {
+			if config.container_enrichment {
+				log.get_field("CONTAINER_NAME")
+			} else {
+				None
+			}
+		})
+		.map(|identifier| normalize_identifier(identifier, config))
+}
+
+fn normalizes_identifier(config: &Config) -> bool {
+	config.identifier_basename || config.lowercase_identifier
+}
+
+/// Strip the directories of e.g. `/usr/bin/foo` and lowercase the identifier, as configured.
+fn normalize_identifier<'a>(identifier: &'a str, config: &Config) -> Cow<'a, str> {
+	let identifier = if config.identifier_basename {
+		identifier.rsplit('/').next().unwrap_or(identifier)
+	} else {
+		identifier
+	};
+	if config.lowercase_identifier && identifier.chars().any(char::is_uppercase) {
+		Cow::Owned(identifier.to_lowercase())
+	} else {
+		Cow::Borrowed(identifier)
+	}
+}
+
+/// Escape colons in the identifier as configured by `identifier_colons`.
+fn escape_identifier_colons<'a>(identifier: Cow<'a, str>, config: &Config) -> Cow<'a, str> {
+	if !identifier.contains(':') {
+		return identifier;
+	}
+	match config.identifier_colons {
+		IdentifierColonPolicy::Keep => identifier,
+		IdentifierColonPolicy::PercentEncode => {
+			Cow::Owned(identifier.replace('%', "%25").replace(':', "%3A"))
+		}
+		IdentifierColonPolicy::Quote => Cow::Owned(quote(&identifier)),
+		IdentifierColonPolicy::Replace => Cow::Owned(identifier.replace(':', "_")),
+	}
+}
+
+/// Total size of the names and values of all fields
+pub(crate) fn entry_size(entry: &JournalEntry) -> usize {
+	entry
+		.get_fields()
+		.iter()
+		.map(|(key, value)| key.len() + value.len())
+		.sum()
+}
+
+/// Cut `value` to at most `max_bytes`, at a character boundary.
+fn truncate(value: &str, max_bytes: Option<usize>) -> &str {
+	match max_bytes {
+		Some(max_bytes) if value.len() > max_bytes => {
+			let mut end = max_bytes;
+			while !value.is_char_boundary(end) {
+				end -= 1;
+			}
+			&value[..end]
+		}
+		_ => value,
+	}
+}
+
+/// Write an audit record like auditd does, e.g.
+/// `type=SYSCALL msg=audit(1364481363.243:24287): arch=c000003e syscall=2 ...`
+fn write_audit<W: Write>(record: &Record<'_>, writer: &mut W) -> Result<()> {
+	let log = record.entry;
+	let timestamp_us = log
+		.get_field("_SOURCE_REALTIME_TIMESTAMP")
+		.and_then(|us| us.parse::<i64>().ok())
+		.unwrap_or(record.timestamp_us);
+	let audit_type = match log.get_field("_AUDIT_TYPE_NAME") {
+		Some(name) => Cow::Borrowed(name),
+		None => Cow::Owned(format!(
+			"UNKNOWN[{}]",
+			log.get_field("_AUDIT_TYPE").unwrap_or("")
+		)),
+	};
 
 	writeln!(
 		writer,
-		"{utc_time} {local_time} [{severity}] {unit_name}: {identifier}: {log_line}",
-		utc_time = time_utc.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
-		local_time = time_local.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
-		severity = prio,
-		unit_name = hostname,
-		identifier = identifier,
-		log_line = log
-			.get_message()
-			.context("No log line could be read from systemd")?,
+		"type={} msg=audit({}.{:03}:{}): {}",
+		audit_type,
+		timestamp_us.div_euclid(1_000_000),
+		timestamp_us.rem_euclid(1_000_000) / 1_000,
+		log.get_field("_AUDIT_ID").unwrap_or("0"),
+		log.get_message().unwrap_or("")
 	)
 	.context("write to log_writer")?;
 
-	writer.flush().context("Flushing writer")?;
+	Ok(())
+}
+
+/// Write a kernel message like `dmesg --decode`, with the time since boot and the subsystem
+/// of the device, e.g. `kern  :info  : [    5.123456] usb: usb 1-1: new high-speed USB device`
+fn write_kernel<W: Write>(record: &Record<'_>, writer: &mut W, config: &Config) -> Result<()> {
+	let log = record.entry;
+	let facility = Facility::of(log).unwrap_or(Facility::KERN);
+	write!(
+		writer,
+		"{:<6}:{:<7}: ",
+		facility.name(),
+		record.priority.keyword()
+	)
+	.context("write to log_writer")?;
+	match record.monotonic_us() {
+		Some(us) => write!(writer, "[{:>5}.{:06}] ", us / 1_000_000, us % 1_000_000),
+		None => write!(
+			writer,
+			"[{}] ",
+			record
+				.time_utc()
+				.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+		),
+	}
+	.context("write to log_writer")?;
+	if let Some(subsystem) = log.get_field("_KERNEL_SUBSYSTEM") {
+		write!(writer, "{}: ", subsystem).context("write to log_writer")?;
+	}
+	writeln!(writer, "{}", text_message(record, config)?).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// Serialize the entry as RFC 3164 syslog message, without trailing newline.
+///
+/// The hostname is left out, as it is added by the local syslog daemon.
+pub(crate) fn write_syslog<W: Write>(
+	record: &Record<'_>,
+	writer: &mut W,
+	config: &Config,
+) -> Result<()> {
+	let log = record.entry;
+	let facility = Facility::of(log).unwrap_or(config.default_facility);
+	let time_local = chrono::Local.from_utc_datetime(&record.time_utc().naive_utc());
+	let identifier = identifier(log, config)
+		.or_else(|| log.get_field("_COMM").map(Cow::Borrowed))
+		.map(|identifier| escape_identifier_colons(identifier, config))
+		.unwrap_or(Cow::Borrowed("journald"));
+
+	write!(
+		writer,
+		"<{}>{} {}",
+		facility.code() * 8 + record.priority as u8,
+		time_local.format("%b %e %H:%M:%S"),
+		identifier
+	)
+	.context("write to log_writer")?;
+	if let Some(pid) = log
+		.get_field("SYSLOG_PID")
+		.or_else(|| log.get_field("_PID"))
+	{
+		write!(writer, "[{}]", pid).context("write to log_writer")?;
+	}
+	write!(writer, ": {}", text_message(record, config)?).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// The message as written in the text format.
+fn text_message<'a>(record: &Record<'a>, config: &Config) -> Result<Cow<'a, str>> {
+	let log = record.entry;
+	let message = log
+		.get_message()
+		.filter(|message| !message.is_empty())
+		.or_else(|| {
+			config
+				.message_fallback_fields
+				.iter()
+				.find_map(|field| log.get_field(field).filter(|value| !value.is_empty()))
+		})
+		// an empty message is still written as such
+		.or_else(|| log.get_message())
+		.context("No log line could be read from systemd")?;
+	let message = truncate(message, record.max_message_bytes);
+	let mut message = if config.sanitize_control_chars {
+		escape_control_chars(message)
+	} else {
+		Cow::Borrowed(message)
+	};
+
+	if config.include_code_location {
+		if let Some(file) = log.get_field("CODE_FILE") {
+			let message = message.to_mut();
+			match log.get_field("CODE_LINE") {
+				Some(line) => message.push_str(&format!(" ({}:{})", file, line)),
+				None => message.push_str(&format!(" ({})", file)),
+			}
+		}
+	}
+
+	Ok(message)
+}
+
+/// Serialize all fields of the entry in the journal export format, which can be imported
+/// again with `systemd-journal-remote`.
+///
+/// See <https://systemd.io/JOURNAL_EXPORT_FORMATS/>
+///
+/// `MESSAGE` is truncated to `max_message_bytes` and `_HOSTNAME` replaced with
+/// `force_hostname`, if given.
+pub(crate) fn write_export<W: Write>(
+	log: &JournalEntry,
+	writer: &mut W,
+	max_message_bytes: Option<usize>,
+	force_hostname: Option<&str>,
+) -> Result<()> {
+	let mut hostname_written = false;
+	for (key, value) in log.get_fields() {
+		let value = match key.as_str() {
+			"MESSAGE" => truncate(value, max_message_bytes),
+			"_HOSTNAME" => {
+				hostname_written = true;
+				force_hostname.unwrap_or(value.as_str())
+			}
+			_ => value.as_str(),
+		};
+		// most values have no control characters, which is cheaper to rule out bytewise
+		let written = if may_have_control_chars(value) && has_control_chars(value) {
+			write_export_binary(writer, key, value)
+		} else {
+			writer
+				.write_all(key.as_bytes())
+				.and_then(|_| writer.write_all(b"="))
+				.and_then(|_| writer.write_all(value.as_bytes()))
+				.and_then(|_| writer.write_all(b"\n"))
+		};
+		written.with_context(|| format!("write field {} to log_writer", key))?;
+	}
+	if let Some(hostname) = force_hostname.filter(|_| !hostname_written) {
+		writeln!(writer, "_HOSTNAME={}", hostname)
+			.context("write field _HOSTNAME to log_writer")?;
+	}
+	writeln!(writer).context("write to log_writer")?;
+
+	Ok(())
+}
+
+/// Whether the value contains an ASCII control character other than tab, or the lead byte
+/// of a C1 control character, which is cheaper to check than decoding it.
+fn may_have_control_chars(value: &str) -> bool {
+	value
+		.bytes()
+		.any(|b| (b < 0x20 && b != b'\t') || b == 0x7f || b == 0xc2)
+}
+
+/// Binary safe serialization: name, little endian length and the raw data
+fn write_export_binary<W: Write>(writer: &mut W, key: &str, value: &str) -> std::io::Result<()> {
+	writer.write_all(key.as_bytes())?;
+	writer.write_all(b"\n")?;
+	writer.write_all(&(value.len() as u64).to_le_bytes())?;
+	writer.write_all(value.as_bytes())?;
+	writer.write_all(b"\n")
+}
+
+fn has_control_chars(value: &str) -> bool {
+	value.chars().any(|c| c.is_control() && c != '\t')
+}
+
+/// Left justify `value` to exactly `width` characters, truncating it if it is longer.
+fn pad(value: &str, width: Option<usize>) -> Cow<'_, str> {
+	match width {
+		None => Cow::Borrowed(value),
+		Some(width) => {
+			let truncated: String = value.chars().take(width).collect();
+			Cow::Owned(format!("{:<width$}", truncated, width = width))
+		}
+	}
+}
 
-	if (cursor_update) {
-		if let Some(cursor) = log.get_field("__CURSOR") {
-			write_cursor(cursor, cursor_path)?;
+/// Replace all control characters except tab with their `\xNN` escape.
+fn escape_control_chars(value: &str) -> Cow<'_, str> {
+	if !has_control_chars(value) {
+		return Cow::Borrowed(value);
+	}
+
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		if c.is_control() && c != '\t' {
+			escaped.push_str(&format!("\\x{:02x}", c as u32));
+		} else {
+			escaped.push(c);
+		}
+	}
+
+	Cow::Owned(escaped)
+}
+
+/// `_CMDLINE` as readable command line: arguments separated by NUL are joined with spaces,
+/// each quoted by `quote_arg` if needed, so that they can be split again. A value without
+/// NUL, with the arguments already joined by spaces as journald stores them, only has its
+/// control characters escaped.
+fn decode_cmdline(cmdline: &str) -> Cow<'_, str> {
+	let cmdline = cmdline.trim_end_matches('\0');
+	if !cmdline.contains('\0') {
+		return escape_control_chars(cmdline);
+	}
+
+	Cow::Owned(
+		cmdline
+			.split('\0')
+			.map(quote_arg)
+			.collect::<Vec<_>>()
+			.join(" "),
+	)
+}
+
+/// An argument of `decode_cmdline`, wrapped in double quotes if it is empty or contains
+/// whitespace, quotes, backslashes or control characters. Within the quotes, `"` and `\`
+/// are escaped with a backslash and control characters as `\n`, `\r`, `\t` or `\xNN`.
+fn quote_arg(arg: &str) -> Cow<'_, str> {
+	let needs_quotes = |c: char| c.is_whitespace() || c.is_control() || "\"'\\".contains(c);
+	if !arg.is_empty() && !arg.contains(needs_quotes) {
+		return Cow::Borrowed(arg);
+	}
+
+	let mut quoted = String::with_capacity(arg.len() + 2);
+	quoted.push('"');
+	for c in arg.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			'\t' => quoted.push_str("\\t"),
+			c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+			c => quoted.push(c),
 		}
 	}
+	quoted.push('"');
+
+	Cow::Owned(quoted)
+}
+
+/// Wrap `value` in double quotes, escaping quotes, backslashes and line breaks with a
+/// backslash.
+fn quote(value: &str) -> String {
+	let mut quoted = String::with_capacity(value.len() + 2);
+	quoted.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+
+	quoted
+}
+
+/// Device and inode of each cursor file as last written or read by the forwarder, to notice
+/// it being replaced by another process
+static CURSOR_IDENTITIES: Mutex<Vec<(PathBuf, (u64, u64))>> = Mutex::new(Vec::new());
+
+fn file_identity(path: &Path) -> Option<(u64, u64)> {
+	std::fs::metadata(path)
+		.ok()
+		.map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+fn remember_identity(identities: &mut Vec<(PathBuf, (u64, u64))>, cursor_path: &Path) {
+	identities.retain(|(path, _)| path != cursor_path);
+	if let Some(identity) = file_identity(cursor_path) {
+		identities.push((cursor_path.to_path_buf(), identity));
+	}
+}
 
-	//write_cursor()
+/// Remember the cursor file as read at startup, see `cursor_file_replaced`.
+pub(crate) fn remember_cursor_file(cursor_path: &Path) {
+	let mut identities = CURSOR_IDENTITIES.lock().unwrap_or_else(|e| e.into_inner());
+	remember_identity(&mut identities, cursor_path);
+}
+
+/// Whether the cursor file was replaced, or deleted, by another process since the forwarder
+/// last read or wrote it. Every write of the forwarder replaces the file as well, so the
+/// identity it wrote is tracked.
+pub(crate) fn cursor_file_replaced(cursor_path: &Path) -> bool {
+	let identities = CURSOR_IDENTITIES.lock().unwrap_or_else(|e| e.into_inner());
+	identities
+		.iter()
+		.find(|(path, _)| path == cursor_path)
+		.map_or(false, |(_, known)| {
+			file_identity(cursor_path) != Some(*known)
+		})
+}
+
+pub(crate) fn write_cursor<P: AsRef<Path>>(cursor: &str, cursor_path: P) -> Result<()> {
+	// held while replacing the file, so a concurrent check doesn't see it half done
+	let mut identities = CURSOR_IDENTITIES.lock().unwrap_or_else(|e| e.into_inner());
+	write_atomically(cursor, cursor_path.as_ref())?;
+	remember_identity(&mut identities, cursor_path.as_ref());
 
 	Ok(())
 }
 
-fn write_cursor<P: AsRef<Path>>(cursor: &str, cursor_path: P) -> Result<()> {
-	let mut tmp_file = cursor_path.as_ref().to_path_buf();
-	tmp_file.set_extension("~");
+/// Replace the file by a temporary one with the content.
+fn write_atomically(content: &str, cursor_path: &Path) -> Result<()> {
+	let tmp_file = cursor_tmp_path(cursor_path)?;
 	let path = tmp_file.display().to_string();
 	let mut file = OpenOptions::new()
 		.create(true)
 		.write(true)
-		.append(false)
+		.truncate(true)
 		.open(&tmp_file)
 		.with_context(|| format!("Open cursor file: {}", path))?;
 	drop(path);
 
-	file.write_all(cursor.as_bytes())
+	file.write_all(content.as_bytes())
 		.context("Writing cursor")?;
 
-	rename(&tmp_file, cursor_path.as_ref()).context("Moving cursor file")?;
+	rename(&tmp_file, cursor_path).context("Moving cursor file")?;
 
 	Ok(())
 }
 
+/// File next to the cursor file keeping the `forwarder_seq` of the entry of the cursor
+fn seq_path(cursor_path: &Path) -> PathBuf {
+	let mut path = cursor_path.as_os_str().to_owned();
+	path.push(".seq");
+	PathBuf::from(path)
+}
+
+/// Persist the `forwarder_seq` of the entry with `cursor`, after its cursor.
+pub(crate) fn write_seq<P: AsRef<Path>>(seq: u64, cursor: &str, cursor_path: P) -> Result<()> {
+	write_atomically(
+		&format!("{} {}", seq, cursor),
+		&seq_path(cursor_path.as_ref()),
+	)
+	.context("Writing forwarder_seq")
+}
+
+/// The `forwarder_seq` persisted with `cursor`, 0 if there is none. A seq persisted with
+/// another cursor, e.g. after a crash between writing both, is continued with a warning.
+pub(crate) fn read_seq<P: AsRef<Path>>(cursor: Option<&str>, cursor_path: P) -> Result<u64> {
+	let path = seq_path(cursor_path.as_ref());
+	let content = match std::fs::read_to_string(&path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+		Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+	};
+	let (seq, seq_cursor) = content
+		.trim()
+		.split_once(' ')
+		.unwrap_or((content.trim(), ""));
+	let seq = seq
+		.parse()
+		.with_context(|| format!("Parsing forwarder_seq in {}", path.display()))?;
+	if Some(seq_cursor) != cursor.map(str::trim) {
+		warn!(
+			"{} was persisted with another cursor, forwarder_seq may repeat or skip numbers",
+			path.display()
+		);
+	}
+
+	Ok(seq)
+}
+
+/// The temporary file the cursor is written to before renaming it, in the directory of the
+/// cursor file so that the rename never crosses file systems.
+fn cursor_tmp_path(cursor_path: &Path) -> Result<PathBuf> {
+	let name = match cursor_path.file_name() {
+		Some(name) => name.to_string_lossy(),
+		None => bail!("Cursor file {} has no file name", cursor_path.display()),
+	};
+	let dir = match cursor_path.parent() {
+		Some(dir) => dir,
+		None => Path::new(""),
+	};
+
+	Ok(dir.join(format!("{}.~", name)))
+}
+
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum Priority {
+#[derive(
+	Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Priority {
 	Emerg = 0,
 	Alert,
 	Crit,
@@ -112,6 +1393,12 @@ impl TryFrom<u8> for Priority {
 	}
 }
 
+impl From<Priority> for u8 {
+	fn from(priority: Priority) -> Self {
+		priority as u8
+	}
+}
+
 impl TryFrom<&str> for Priority {
 	type Error = anyhow::Error;
 
@@ -121,8 +1408,43 @@ impl TryFrom<&str> for Priority {
 	}
 }
 
+impl Priority {
+	/// OpenTelemetry severity number, on the scale from 1 (trace) to 24 (fatal)
+	pub(crate) fn otel_severity(&self) -> u8 {
+		match self {
+			Priority::Emerg => 21,
+			Priority::Alert => 19,
+			Priority::Crit => 18,
+			Priority::Err => 17,
+			Priority::Warning => 13,
+			Priority::Notice => 10,
+			Priority::Info => 9,
+			Priority::Debug => 5,
+		}
+	}
+
+	/// The lowercase rsyslog severity keyword
+	pub(crate) fn keyword(&self) -> &'static str {
+		match self {
+			Priority::Emerg => "emerg",
+			Priority::Alert => "alert",
+			Priority::Crit => "crit",
+			Priority::Err => "err",
+			Priority::Warning => "warning",
+			Priority::Notice => "notice",
+			Priority::Info => "info",
+			Priority::Debug => "debug",
+		}
+	}
+}
+
+/// Formats the verbose name, or the rsyslog keyword with the alternate flag (`{:#}`).
 impl fmt::Display for Priority {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if f.alternate() {
+			return write!(f, "{}", self.keyword());
+		}
+
 		let msg = match self {
 			&Priority::Emerg => "Emergency",
 			&Priority::Alert => "Alert",
@@ -137,3 +1459,209 @@ impl fmt::Display for Priority {
 		write!(f, "{}", msg)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::template::Template;
+
+	fn entry(fields: &[(&str, &str)]) -> JournalEntry {
+		let mut entry = JournalEntry::new();
+		for (key, value) in fields {
+			entry.set_field(*key, *value);
+		}
+		entry
+	}
+
+	#[test]
+	fn priority_keywords() {
+		let keywords: Vec<_> = (0..8)
+			.map(|priority| Priority::try_from(priority).unwrap().keyword())
+			.collect();
+		assert_eq!(
+			keywords,
+			["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"]
+		);
+		assert_eq!(format!("{:#}", Priority::Warning), "warning");
+		assert_eq!(format!("{}", Priority::Warning), "Warning");
+	}
+
+	#[test]
+	fn severity_keyword_placeholder() {
+		let mut config = crate::bench::config();
+		config.text_template =
+			Template::try_from("{severity_keyword}: {message}".to_string()).unwrap();
+		let entry = entry(&[
+			("__REALTIME_TIMESTAMP", "1760429700000000"),
+			("PRIORITY", "3"),
+			("MESSAGE", "disk failed"),
+		]);
+		let record = Record::new(&entry, &config).unwrap().unwrap();
+		let mut out = Vec::new();
+		write_log_line(&record, &mut out, OutputFormat::Text, &config).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "err: disk failed\n");
+	}
+
+	#[test]
+	fn passthrough_writes_the_export_of_the_record() {
+		let config = crate::bench::config();
+		let entry = entry(&[
+			("__REALTIME_TIMESTAMP", "1760429700000000"),
+			("MESSAGE", "first line\nsecond line"),
+			("SYSLOG_IDENTIFIER", "app"),
+		]);
+		let record = Record::new(&entry, &config).unwrap().unwrap();
+		let mut exported = Vec::new();
+		write_log_line(&record, &mut exported, OutputFormat::Export, &config).unwrap();
+		let mut out = Vec::new();
+		write_passthrough(&entry, &mut out, &config).unwrap();
+		assert_eq!(out, exported);
+	}
+
+	#[test]
+	fn cmdline_placeholder_keeps_the_arguments_as_stored() {
+		let mut config = crate::bench::config();
+		config.text_template = Template::try_from("{cmdline}".to_string()).unwrap();
+		let entry = entry(&[
+			("__REALTIME_TIMESTAMP", "1760429700000000"),
+			("_CMDLINE", "sh -c \"echo hi\"\x1b"),
+		]);
+		let record = Record::new(&entry, &config).unwrap().unwrap();
+		let mut out = Vec::new();
+		write_log_line(&record, &mut out, OutputFormat::Text, &config).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "sh -c \"echo hi\"\\x1b\n");
+	}
+
+	/// Split a command line of `decode_cmdline` into its arguments again.
+	fn split_cmdline(cmdline: &str) -> Vec<String> {
+		let mut args = Vec::new();
+		let mut chars = cmdline.chars().peekable();
+		while let Some(c) = chars.next() {
+			if c != '"' {
+				let mut arg = c.to_string();
+				while let Some(c) = chars.next_if(|c| *c != ' ') {
+					arg.push(c);
+				}
+				args.push(arg);
+			} else {
+				let mut arg = String::new();
+				while let Some(c) = chars.next() {
+					match c {
+						'"' => break,
+						'\\' => match chars.next().unwrap() {
+							'n' => arg.push('\n'),
+							'r' => arg.push('\r'),
+							't' => arg.push('\t'),
+							'x' => {
+								let code: String = chars.by_ref().take(2).collect();
+								arg.push(u8::from_str_radix(&code, 16).unwrap() as char);
+							}
+							c => arg.push(c),
+						},
+						c => arg.push(c),
+					}
+				}
+				args.push(arg);
+			}
+			chars.next_if_eq(&' ');
+		}
+		args
+	}
+
+	#[test]
+	fn cmdline_arguments_are_quoted_to_split_again() {
+		let args = [
+			"sh",
+			"-c",
+			"echo \"hi\" 'there'",
+			"",
+			"a\\b\tc\x1b",
+			"--flag=1",
+		];
+		let decoded = decode_cmdline(&format!("{}\0", args.join("\0"))).into_owned();
+		assert_eq!(
+			decoded,
+			r#"sh -c "echo \"hi\" 'there'" "" "a\\b\tc\x1b" --flag=1"#
+		);
+		assert_eq!(split_cmdline(&decoded), args);
+
+		// as stored by journald, with the arguments already joined
+		assert_eq!(decode_cmdline("sleep 10"), "sleep 10");
+	}
+
+	#[test]
+	fn reception_time_falls_back_to_the_source_time() {
+		let mut config = crate::bench::config();
+		let source = entry(&[("_SOURCE_REALTIME_TIMESTAMP", "1760429700000000")]);
+		let record = Record::new(&source, &config).unwrap().unwrap();
+		assert_eq!(record.received_us, 1760429700000000);
+
+		let without_time = entry(&[("MESSAGE", "imported")]);
+		let before_us = chrono::Utc::now().timestamp_micros();
+		let record = Record::new(&without_time, &config).unwrap().unwrap();
+		assert!(record.received_us >= before_us);
+
+		config.on_missing_timestamp = MissingTimestampPolicy::Skip;
+		assert!(Record::new(&without_time, &config).unwrap().is_none());
+	}
+
+	#[test]
+	fn cursor_tmp_path_is_next_to_the_cursor() {
+		assert_eq!(
+			cursor_tmp_path(Path::new("/run/journald-writer/cursor")).unwrap(),
+			Path::new("/run/journald-writer/cursor.~")
+		);
+		assert_eq!(
+			cursor_tmp_path(Path::new("cursor")).unwrap(),
+			Path::new("cursor.~")
+		);
+		assert!(cursor_tmp_path(Path::new("/")).is_err());
+	}
+
+	#[test]
+	fn write_cursor_replaces_the_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let cursor_file = dir.path().join("state").join("cursor");
+		std::fs::create_dir(cursor_file.parent().unwrap()).unwrap();
+
+		write_cursor("s=ab;i=1", &cursor_file).unwrap();
+		write_cursor("s=ab;i=2", &cursor_file).unwrap();
+		assert_eq!(std::fs::read_to_string(&cursor_file).unwrap(), "s=ab;i=2");
+		// the temporary file was renamed
+		let files: Vec<_> = std::fs::read_dir(cursor_file.parent().unwrap())
+			.unwrap()
+			.map(|file| file.unwrap().file_name())
+			.collect();
+		assert_eq!(files, ["cursor"]);
+	}
+
+	#[test]
+	fn write_cursor_truncates_a_stale_temporary_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let cursor_file = dir.path().join("cursor");
+		// left over from a crash while writing a longer cursor
+		std::fs::write(
+			cursor_tmp_path(&cursor_file).unwrap(),
+			"s=abcdef;i=123456;b=0123",
+		)
+		.unwrap();
+
+		write_cursor("s=ab;i=1", &cursor_file).unwrap();
+		assert_eq!(std::fs::read_to_string(&cursor_file).unwrap(), "s=ab;i=1");
+	}
+
+	/// Values of a repeated field overwrite each other in `JournalEntry`, so only the last one
+	/// reaches the formats. Written as an array once the journald crate keeps all of them.
+	#[test]
+	fn repeated_field_keeps_the_last_value() {
+		let config = crate::bench::config();
+		let entry = entry(&[
+			("__REALTIME_TIMESTAMP", "1760429700000000"),
+			("TAG", "first"),
+			("TAG", "second"),
+		]);
+		let record = Record::new(&entry, &config).unwrap().unwrap();
+		let fields = record_fields(&record, &config);
+		assert_eq!(fields.get("TAG"), Some(&serde_json::json!("second")));
+	}
+}